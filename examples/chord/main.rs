@@ -70,7 +70,7 @@ fn main() {
             Tone::B.oct(3)
         ].velocity(50)
     ]
-        .split_notes(&vec![true, false, false, true, false, false, true, true, false, false])
+        .split_notes(&[true, false, false, true, false, false, true, true, false, false])
         .midibox();
 
     let roots =
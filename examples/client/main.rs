@@ -1,7 +1,7 @@
 use std::thread::sleep;
 use std::time::Duration;
 use client_example::midibox_player_client::MidiboxPlayerClient;
-use client_example::{PlayRequest, PlayResponse, StopRequest, StopResponse};
+use client_example::{PlayRequest, StopRequest};
 
 pub mod client_example {
     include!("../../src/generated/midibox.rs");
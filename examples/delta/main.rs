@@ -33,30 +33,30 @@ fn main() {
             s1.clone()
                 .velocity(70)
                 .transpose_down(Oct)
-                .split_notes(&vec![true, false, true, true, false, true, false, true]),
+                .split_notes(&[true, false, true, true, false, true, false, true]),
             s1.clone()
                 .velocity(60)
                 .transpose_down(Oct)
                 .harmonize_down(&c_maj, Degree::Fourth)
-                .split_notes(&vec![false, true, false, false, true, false, true, false]),
+                .split_notes(&[false, true, false, false, true, false, true, false]),
             s1.clone()
-                .split_notes(&vec![true, false, false, true]),
+                .split_notes(&[true, false, false, true]),
             s1.clone()
                 .velocity(110)
                 .harmonize_up(&c_maj, Degree::Third)
-                .split_notes(&vec![false, true, false, false, true]),
+                .split_notes(&[false, true, false, false, true]),
             s1.clone()
                 .velocity(60)
                 .harmonize_down(&c_maj, Degree::Second)
-                .split_notes(&vec![false, false, true, false, false, true]),
+                .split_notes(&[false, false, true, false, false, true]),
             s1.clone()
                 .velocity(90)
                 .harmonize_up(&c_maj, Degree::Fifth)
-                .split_notes(&vec![true, true, false, false, true]),
+                .split_notes(&[true, true, false, false, true]),
             s1.clone()
                 .velocity(80)
                 .harmonize_down(&c_maj, Degree::Fourth)
-                .split_notes(&vec![false, false, true, true, false, false, true]),
+                .split_notes(&[false, false, true, true, false, false, true]),
         ].into_iter().map(|seq| seq.midibox()).collect()
     ).unwrap()
 }
@@ -0,0 +1,173 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// The order an `Arpeggiator` plays the notes of a chord in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpMode {
+    /// Lowest to highest, then starting again from the lowest.
+    Up,
+    /// Highest to lowest, then starting again from the highest.
+    Down,
+    /// Lowest to highest and back down, without repeating the top and bottom notes.
+    UpDown,
+    /// A note of the chord picked at random each time.
+    Random,
+}
+
+/// Spreads each chord the wrapped midibox emits out into single notes, one every `note_ticks`
+/// ticks, for as long as the chord would have lasted.
+///
+/// The arpeggio re-latches onto every new chord, starting its pattern over. The last note of a
+/// chord is cut short if the chord's duration isn't a multiple of `note_ticks`. Rests and
+/// emissions of a single note are passed through unchanged, as are control events, which are
+/// sent with the first note of their chord.
+pub struct Arpeggiator {
+    mode: ArpMode,
+    note_ticks: u32,
+    /// The latched chord, lowest pitch first.
+    notes: Vec<Midi>,
+    /// Ticks of the latched chord still to be filled.
+    remaining: u32,
+    /// Arpeggio notes played since the chord was latched.
+    step: usize,
+    /// Picks the notes in `ArpMode::Random`.
+    rng: StdRng,
+    midibox: Box<dyn Midibox>,
+}
+
+impl Arpeggiator {
+    pub fn wrap(midibox: Box<dyn Midibox>, mode: ArpMode, note_ticks: u32) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, mode, note_ticks, StdRng::from_entropy())
+    }
+
+    /// Like `wrap`, with the same random picks every time for the same seed.
+    pub fn wrap_seeded(
+        midibox: Box<dyn Midibox>,
+        mode: ArpMode,
+        note_ticks: u32,
+        seed: u64
+    ) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, mode, note_ticks, StdRng::seed_from_u64(seed))
+    }
+
+    fn wrap_with_rng(
+        midibox: Box<dyn Midibox>,
+        mode: ArpMode,
+        note_ticks: u32,
+        rng: StdRng
+    ) -> Box<dyn Midibox> {
+        Box::new(Arpeggiator {
+            mode,
+            note_ticks: note_ticks.max(1),
+            notes: Vec::new(),
+            remaining: 0,
+            step: 0,
+            rng,
+            midibox,
+        })
+    }
+
+    fn pick(&mut self) -> Midi {
+        let len = self.notes.len();
+        let index = match self.mode {
+            ArpMode::Up => self.step % len,
+            ArpMode::Down => len - 1 - self.step % len,
+            ArpMode::UpDown if len == 1 => 0,
+            ArpMode::UpDown => {
+                let period = 2 * len - 2;
+                let at = self.step % period;
+                if at < len { at } else { period - at }
+            }
+            ArpMode::Random => self.rng.gen_range(0..len),
+        };
+        self.notes[index]
+    }
+}
+
+impl Midibox for Arpeggiator {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let mut emission = Vec::new();
+        if self.remaining == 0 {
+            let chord = self.midibox.next()?;
+            let duration = chord.iter().map(|n| n.duration).max().unwrap_or(0);
+            let (mut notes, others): (Vec<Midi>, Vec<Midi>) = chord.iter()
+                .partition(|note| !note.is_rest());
+            if notes.len() < 2 || duration == 0 {
+                return Some(chord);
+            }
+            notes.sort_by_key(|note| note.u8_maybe());
+            self.notes = notes;
+            self.remaining = duration;
+            self.step = 0;
+            emission.extend(others.into_iter().filter(|note| note.control.is_some()));
+        }
+        let ticks = self.note_ticks.min(self.remaining);
+        emission.push(self.pick().set_duration(ticks));
+        self.step += 1;
+        self.remaining -= ticks;
+        Some(emission)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.remaining == 0 && self.midibox.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arpeggiator::{ArpMode, Arpeggiator};
+    use crate::chord::Chord;
+    use crate::collect_emissions;
+    use crate::midi::Midi;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    fn arpeggiate(mode: ArpMode, note_ticks: u32, count: usize) -> Vec<Vec<Midi>> {
+        let seq = Seq::chords(vec![
+            Chord::new(vec![Tone::G.oct(4) * 6, Tone::C.oct(4) * 6, Tone::E.oct(4) * 6]),
+            Chord::note(Tone::Rest * 2),
+            Chord::new(vec![Tone::D.oct(4) * 3, Tone::F.oct(4) * 3]),
+        ]);
+        collect_emissions(Arpeggiator::wrap(seq.midibox(), mode, note_ticks).as_mut(), count)
+    }
+
+    #[test]
+    fn arpeggiates_each_chord_in_order() {
+        let c = Tone::C.oct(4);
+        let e = Tone::E.oct(4);
+        let g = Tone::G.oct(4);
+        assert_eq!(arpeggiate(ArpMode::Up, 1, 8), vec![
+            vec![c * 1], vec![e * 1], vec![g * 1], vec![c * 1], vec![e * 1], vec![g * 1],
+            vec![Tone::Rest * 2],
+            vec![Tone::D.oct(4) * 1],
+        ]);
+        assert_eq!(arpeggiate(ArpMode::Down, 2, 4), vec![
+            vec![g * 2], vec![e * 2], vec![c * 2], vec![Tone::Rest * 2],
+        ]);
+        assert_eq!(arpeggiate(ArpMode::UpDown, 1, 6), vec![
+            vec![c * 1], vec![e * 1], vec![g * 1], vec![e * 1], vec![c * 1], vec![e * 1],
+        ]);
+    }
+
+    #[test]
+    fn relatches_on_each_new_chord() {
+        let emissions = arpeggiate(ArpMode::Up, 2, 7);
+        // the second chord starts its pattern over and its last note is cut short
+        assert_eq!(emissions[4..], [
+            vec![Tone::D.oct(4) * 2],
+            vec![Tone::F.oct(4) * 1],
+            vec![Tone::C.oct(4) * 2],
+        ]);
+        let random = arpeggiate(ArpMode::Random, 1, 6);
+        assert!(random.iter().all(|emission| emission.len() == 1
+            && [Tone::C, Tone::E, Tone::G].contains(&emission[0].tone)));
+        let chord = Seq::chords(vec![Chord::new(vec![Tone::C.oct(4) * 8, Tone::E.oct(4) * 8])]);
+        let seeded = |seed| collect_emissions(
+            Arpeggiator::wrap_seeded(chord.midibox(), ArpMode::Random, 1, seed).as_mut(),
+            16
+        );
+        assert_eq!(seeded(3), seeded(3));
+    }
+}
@@ -0,0 +1,27 @@
+use crate::Midibox;
+use crate::midi::{Control, Midi};
+use crate::sequences::Seq;
+
+/// Builders for pitch bend automation.
+pub struct PitchBend;
+
+impl PitchBend {
+    /// Glides the pitch bend on MIDI channel 0 from `from` to `to` (offsets from centre, see
+    /// `Control::PitchBend`), moving once a tick over `ticks` ticks, then starts the glide again.
+    pub fn ramp(from: i16, to: i16, ticks: u32) -> Box<dyn Midibox> {
+        PitchBend::ramp_on_channel(0, from, to, ticks)
+    }
+
+    /// Like `ramp`, bending MIDI channel `channel` (0-15).
+    pub fn ramp_on_channel(channel: u8, from: i16, to: i16, ticks: u32) -> Box<dyn Midibox> {
+        let ticks = ticks.max(1);
+        let steps = (0..ticks).map(|i| {
+            let progress = if ticks == 1 { 1.0 } else { i as f64 / (ticks - 1) as f64 };
+            let offset = from as f64 + (to as f64 - from as f64) * progress;
+            Midi::control(Control::PitchBend(offset.round() as i16))
+                .set_channel(channel)
+                .set_duration(1)
+        });
+        Seq::new(steps.collect()).midibox()
+    }
+}
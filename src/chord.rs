@@ -47,7 +47,7 @@ impl ToChord for Chord {
 
 impl MutMidi for Chord {
     fn total_duration(&self) -> u32 {
-        self.notes.iter().map(|n| n.duration).max().unwrap_or_else(|| 0)
+        self.notes.iter().map(|n| n.duration).max().unwrap_or(0)
     }
 
     fn duration(mut self, duration: u32) -> Self {
@@ -105,4 +105,77 @@ impl MutMidi for Chord {
             .collect();
         self
     }
-}
\ No newline at end of file
+}
+
+/// Inverts a chord `inversion` times, each time raising its lowest note an octave, so the first
+/// inversion of C4-E4-G4 is E4-G4-C5.
+///
+/// The notes come back ordered from lowest to highest, followed by any rests and control
+/// events. They keep their velocities and everything else but their pitch, and a note that
+/// can't be raised an octave without leaving the MIDI range stays where it is.
+pub fn invert_chord(notes: Vec<Midi>, inversion: usize) -> Vec<Midi> {
+    let (mut pitched, unpitched) = split_pitched(notes);
+    for _ in 0..inversion {
+        if let Some(lowest) = pitched.first_mut() {
+            *lowest = lowest.octave_up(1);
+        }
+        pitched.sort_by_key(|note| note.u8_maybe());
+    }
+    pitched.extend(unpitched);
+    pitched
+}
+
+/// Lowers the `drop`th-highest note of a chord an octave, for the drop-2 and drop-3 voicings of
+/// jazz comping: drop 2 spreads C4-E4-G4-B4 into G3-C4-E4-B4.
+///
+/// The notes come back ordered as for `invert_chord`. A `drop` of 0, or greater than the number
+/// of notes, leaves the pitches alone, and a note that can't be lowered an octave without
+/// leaving the MIDI range stays where it is.
+pub fn drop_voicing(notes: Vec<Midi>, drop: usize) -> Vec<Midi> {
+    let (mut pitched, unpitched) = split_pitched(notes);
+    if (1..=pitched.len()).contains(&drop) {
+        let dropped = pitched.len() - drop;
+        pitched[dropped] = pitched[dropped].octave_down(1);
+        pitched.sort_by_key(|note| note.u8_maybe());
+    }
+    pitched.extend(unpitched);
+    pitched
+}
+
+/// Separates the notes with a pitch, ordered from lowest to highest, from the rests and control
+/// events.
+fn split_pitched(notes: Vec<Midi>) -> (Vec<Midi>, Vec<Midi>) {
+    let (mut pitched, unpitched): (Vec<Midi>, Vec<Midi>) = notes.into_iter()
+        .partition(|note| note.u8_maybe().is_some());
+    pitched.sort_by_key(|note| note.u8_maybe());
+    (pitched, unpitched)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::{drop_voicing, invert_chord};
+    use crate::midi::Midi;
+    use crate::tone::Tone;
+
+    #[test]
+    fn inversions_and_drop_voicings() {
+        let seventh = vec![Tone::B.oct(4).set_velocity(70), Tone::C.oct(4), Tone::E.oct(4), Tone::G.oct(4)];
+        assert_eq!(invert_chord(seventh.clone(), 1), vec![
+            Tone::E.oct(4), Tone::G.oct(4), Tone::B.oct(4).set_velocity(70), Tone::C.oct(5),
+        ]);
+        assert_eq!(invert_chord(seventh.clone(), 5), vec![
+            Tone::E.oct(5), Tone::G.oct(5), Tone::B.oct(5).set_velocity(70), Tone::C.oct(6),
+        ]);
+        assert_eq!(drop_voicing(seventh.clone(), 2), vec![
+            Tone::G.oct(3), Tone::C.oct(4), Tone::E.oct(4), Tone::B.oct(4).set_velocity(70),
+        ]);
+        assert_eq!(drop_voicing(seventh.clone(), 3), vec![
+            Tone::E.oct(3), Tone::C.oct(4), Tone::G.oct(4), Tone::B.oct(4).set_velocity(70),
+        ]);
+        assert_eq!(drop_voicing(seventh, 5)[0], Tone::C.oct(4));
+
+        // G9 is the highest MIDI note, so can't go up another octave
+        let high = vec![Midi::rest(), Tone::G.oct(9), Tone::E.oct(9)];
+        assert_eq!(invert_chord(high, 1), vec![Tone::E.oct(9), Tone::G.oct(9), Midi::rest()]);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// Echoes the notes the wrapped midibox emits: each one is repeated every `delay_ticks` ticks,
+/// up to `repeats` times, with its velocity multiplied by `feedback` at each repeat. The tail
+/// stops early once an echo would be quieter than `min_velocity`.
+///
+/// Echoes keep the pitch and duration of their note, and overlap whatever is playing when they
+/// come in. To fit them into the player's steps, the wrapped midibox's steps are split wherever
+/// an echo starts, with the notes sounding across the split tied over it (see `Midi::tie`), as
+/// for a `Strum`. An echo landing on a note of the same pitch that's still sounding carries it
+/// on instead of restarting it. Rests and control events aren't echoed, and zero-duration steps
+/// are passed through.
+pub struct Delay {
+    delay_ticks: u32,
+    feedback: f64,
+    repeats: usize,
+    min_velocity: u8,
+    /// Ticks emitted so far.
+    time: u64,
+    /// When the wrapped midibox's next step starts.
+    source_time: u64,
+    /// The notes, with the tick they start on, that haven't started yet or are still sounding.
+    scheduled: Vec<(u64, Midi)>,
+    midibox: Box<dyn Midibox>,
+}
+
+impl Delay {
+    pub fn wrap(
+        midibox: Box<dyn Midibox>,
+        delay_ticks: u32,
+        feedback: f64,
+        repeats: usize,
+        min_velocity: u8
+    ) -> Box<dyn Midibox> {
+        Box::new(Delay {
+            delay_ticks: delay_ticks.max(1),
+            feedback: feedback.clamp(0.0, 1.0),
+            repeats,
+            min_velocity: min_velocity.max(1),
+            time: 0,
+            source_time: 0,
+            scheduled: Vec::new(),
+            midibox,
+        })
+    }
+
+    /// Schedules the notes of the wrapped midibox's step starting now, and their echoes.
+    fn schedule(&mut self, notes: &[Midi]) {
+        for note in notes.iter().filter(|note| !note.is_rest() || note.control.is_some()) {
+            self.scheduled.push((self.time, *note));
+            if note.control.is_some() {
+                continue;
+            }
+            for repeat in 1..=self.repeats {
+                let velocity = (note.velocity as f64 * self.feedback.powi(repeat as i32)).round();
+                if velocity < self.min_velocity as f64 {
+                    break;
+                }
+                let start = self.time + repeat as u64 * self.delay_ticks as u64;
+                self.scheduled.push((start, note.set_velocity(velocity as u8).set_tie(false)));
+            }
+        }
+    }
+}
+
+impl Midibox for Delay {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        if self.time == self.source_time {
+            let notes = self.midibox.next()?;
+            let step_ticks = notes.iter().map(|n| n.duration).max().unwrap_or(0);
+            if step_ticks == 0 {
+                return Some(notes);
+            }
+            self.schedule(&notes);
+            self.source_time += step_ticks as u64;
+        }
+
+        let time = self.time;
+        let end = self.scheduled.iter()
+            .map(|(start, _)| *start)
+            .filter(|start| *start > time)
+            .fold(self.source_time, u64::min);
+        let length = (end - time) as u32;
+        let onsets: Vec<Option<u8>> = self.scheduled.iter()
+            .filter(|(start, note)| *start == time && note.control.is_none())
+            .map(|(_, note)| note.u8_maybe())
+            .collect();
+        // a note restarted by an echo of the same pitch gives way to it
+        self.scheduled.retain(|(start, note)| *start >= time || !onsets.contains(&note.u8_maybe()));
+
+        let mut step: Vec<Midi> = self.scheduled.iter()
+            .filter(|(start, note)| *start == time || (*start < time && note.control.is_none()))
+            .map(|(start, note)| {
+                let remaining = (start + note.duration as u64 - time) as u32;
+                let tie = if remaining > length { note.control.is_none() } else { note.tie };
+                note.set_duration(remaining.min(length)).set_tie(tie)
+            })
+            .collect();
+        if step.iter().all(|note| note.duration < length) {
+            step.push(Midi::rest().set_duration(length));
+        }
+        self.scheduled.retain(|(start, note)| {
+            *start > time || (note.control.is_none() && start + note.duration as u64 > end)
+        });
+        self.time = end;
+        Some(step)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.scheduled.is_empty() && self.time == self.source_time && self.midibox.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collect_emissions;
+    use crate::delay::Delay;
+    use crate::midi::Midi;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn echoes_fade_out_between_the_steps() {
+        let c = Tone::C.oct(4);
+        let seq = Seq::new(vec![c * 2, Midi::rest() * 6]);
+        let emissions = collect_emissions(Delay::wrap(seq.midibox(), 3, 0.5, 3, 20).as_mut(), 8);
+        assert_eq!(emissions, vec![
+            vec![c * 2],
+            vec![Midi::rest() * 1],
+            vec![c.set_velocity(50) * 2, Midi::rest() * 3],
+            // the third echo would be quieter than the minimum
+            vec![c.set_velocity(25) * 2],
+            vec![c * 2],
+            vec![Midi::rest() * 1],
+            vec![c.set_velocity(50) * 2, Midi::rest() * 3],
+            vec![c.set_velocity(25) * 2],
+        ]);
+
+        let long = Seq::new(vec![Tone::E.oct(4) * 3]);
+        let emissions = collect_emissions(Delay::wrap(long.midibox(), 2, 0.5, 1, 1).as_mut(), 3);
+        assert_eq!(emissions[0], vec![Tone::E.oct(4).set_tie(true) * 2]);
+    }
+}
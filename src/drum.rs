@@ -0,0 +1,97 @@
+use crate::midi::Midi;
+
+/// Channel 10 in the usual 1-16 numbering, which General MIDI reserves for percussion.
+pub const DRUM_CHANNEL: u8 = 9;
+
+// General MIDI percussion key map
+pub const ACOUSTIC_BASS_DRUM: u8 = 35;
+pub const KICK: u8 = 36;
+pub const SIDE_STICK: u8 = 37;
+pub const SNARE: u8 = 38;
+pub const CLAP: u8 = 39;
+pub const ELECTRIC_SNARE: u8 = 40;
+pub const LOW_FLOOR_TOM: u8 = 41;
+pub const CLOSED_HAT: u8 = 42;
+pub const HIGH_FLOOR_TOM: u8 = 43;
+pub const PEDAL_HAT: u8 = 44;
+pub const LOW_TOM: u8 = 45;
+pub const OPEN_HAT: u8 = 46;
+pub const LOW_MID_TOM: u8 = 47;
+pub const HIGH_MID_TOM: u8 = 48;
+pub const CRASH: u8 = 49;
+pub const HIGH_TOM: u8 = 50;
+pub const RIDE: u8 = 51;
+pub const CHINA: u8 = 52;
+pub const RIDE_BELL: u8 = 53;
+pub const TAMBOURINE: u8 = 54;
+pub const SPLASH: u8 = 55;
+pub const COWBELL: u8 = 56;
+pub const CRASH_2: u8 = 57;
+pub const VIBRASLAP: u8 = 58;
+pub const RIDE_2: u8 = 59;
+pub const HIGH_BONGO: u8 = 60;
+pub const LOW_BONGO: u8 = 61;
+pub const MUTE_HIGH_CONGA: u8 = 62;
+pub const OPEN_HIGH_CONGA: u8 = 63;
+pub const LOW_CONGA: u8 = 64;
+pub const HIGH_TIMBALE: u8 = 65;
+pub const LOW_TIMBALE: u8 = 66;
+pub const HIGH_AGOGO: u8 = 67;
+pub const LOW_AGOGO: u8 = 68;
+pub const CABASA: u8 = 69;
+pub const MARACAS: u8 = 70;
+pub const SHORT_WHISTLE: u8 = 71;
+pub const LONG_WHISTLE: u8 = 72;
+pub const SHORT_GUIRO: u8 = 73;
+pub const LONG_GUIRO: u8 = 74;
+pub const CLAVES: u8 = 75;
+pub const HIGH_WOOD_BLOCK: u8 = 76;
+pub const LOW_WOOD_BLOCK: u8 = 77;
+pub const MUTE_CUICA: u8 = 78;
+pub const OPEN_CUICA: u8 = 79;
+pub const MUTE_TRIANGLE: u8 = 80;
+pub const OPEN_TRIANGLE: u8 = 81;
+
+/// A hit of the given General MIDI percussion key, sent on the drum channel.
+pub fn hit(key: u8) -> Midi {
+    Midi::from(key).set_channel(DRUM_CHANNEL)
+}
+
+/// A hit of the given General MIDI percussion key with the given velocity and duration.
+pub fn hit_with(key: u8, velocity: u8, duration: u32) -> Midi {
+    hit(key).set_velocity(velocity).set_duration(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drum::{CLAP, CLOSED_HAT, CRASH, DRUM_CHANNEL, hit, hit_with, KICK, OPEN_HAT, OPEN_TRIANGLE, PEDAL_HAT, RIDE, SIDE_STICK, SNARE};
+    use crate::tone::Tone;
+
+    #[test]
+    fn general_midi_key_map() {
+        assert_eq!(KICK, 36);
+        assert_eq!(SIDE_STICK, 37);
+        assert_eq!(SNARE, 38);
+        assert_eq!(CLAP, 39);
+        assert_eq!(CLOSED_HAT, 42);
+        assert_eq!(PEDAL_HAT, 44);
+        assert_eq!(OPEN_HAT, 46);
+        assert_eq!(CRASH, 49);
+        assert_eq!(RIDE, 51);
+        assert_eq!(OPEN_TRIANGLE, 81);
+    }
+
+    #[test]
+    fn hits_default_to_channel_10() {
+        let kick = hit(KICK);
+        assert_eq!(kick.u8_maybe(), Some(36));
+        assert_eq!(kick.tone, Tone::C);
+        assert_eq!(kick.channel, Some(DRUM_CHANNEL));
+
+        let snare = hit_with(SNARE, 80, 2);
+        assert_eq!(snare.u8_maybe(), Some(38));
+        assert_eq!(snare.velocity, 80);
+        assert_eq!(snare.duration, 2);
+        assert_eq!(snare.channel, Some(9));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::Midibox;
+use crate::cycle_ticks;
+use crate::midi::Midi;
+use crate::router::VelocityCurve;
+
+/// Remaps the velocity of every note the wrapped midibox emits, e.g. to compress or expand the
+/// dynamics of an imported performance.
+///
+/// Results are kept within 1 to 127 so that no note-on turns into a note-off. Rests and control
+/// events are passed through unchanged.
+pub struct ShapeVelocity {
+    shape: Box<dyn Fn(u8) -> u8>,
+    midibox: Box<dyn Midibox>,
+}
+
+impl ShapeVelocity {
+    /// Shapes velocities with one of the curves also used per port by the player. A
+    /// `VelocityCurve::Gamma` gives an exponential response.
+    pub fn wrap(midibox: Box<dyn Midibox>, curve: VelocityCurve) -> Box<dyn Midibox> {
+        Self::wrap_with(midibox, move |velocity| curve.apply(velocity))
+    }
+
+    /// Shapes velocities with an arbitrary function.
+    pub fn wrap_with(midibox: Box<dyn Midibox>, shape: impl Fn(u8) -> u8 + 'static) -> Box<dyn Midibox> {
+        Box::new(ShapeVelocity {
+            shape: Box::new(shape),
+            midibox
+        })
+    }
+}
+
+impl Midibox for ShapeVelocity {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        self.midibox.next()
+            .map(|it|
+                it.into_iter()
+                    .map(|note| {
+                        if note.is_rest() {
+                            return note;
+                        }
+                        note.set_velocity((self.shape)(note.velocity).clamp(1, 127))
+                    }).collect::<Vec<Midi>>()
+            )
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// Morphs from one pattern into another by fading their velocities, playing both together.
+///
+/// Over `cycles` cycles of `from` (see `Midibox::cycle`), `from` fades out while `to` fades in,
+/// so that halfway through both play at half velocity. Notes faded all the way out become rests,
+/// so before the fade only `from` sounds and after it only `to`. The two are polled in lockstep,
+/// so they should share a rhythm. A `from` that doesn't repeat fades over `cycles` ticks.
+pub struct Crossfade {
+    from: Box<dyn Midibox>,
+    to: Box<dyn Midibox>,
+    fade_ticks: u64,
+    /// Ticks of output produced so far, counting steps as `cycle_ticks` does.
+    position: u64,
+}
+
+impl Crossfade {
+    pub fn wrap(from: Box<dyn Midibox>, to: Box<dyn Midibox>, cycles: u32) -> Box<dyn Midibox> {
+        let cycle_ticks = cycle_ticks(from.as_ref()).unwrap_or(1);
+        Box::new(Crossfade {
+            from,
+            to,
+            fade_ticks: (cycles as u64 * cycle_ticks).max(1),
+            position: 0,
+        })
+    }
+}
+
+/// Scales the velocity of each note by `gain`, turning notes that would be silent into rests.
+fn fade(notes: Vec<Midi>, gain: f64) -> impl Iterator<Item = Midi> {
+    notes.into_iter().map(move |note| {
+        if note.is_rest() {
+            return note;
+        }
+        let velocity = (note.velocity as f64 * gain).round() as u8;
+        if velocity == 0 {
+            Midi::rest().set_duration(note.duration)
+        } else {
+            note.set_velocity(velocity.min(127))
+        }
+    })
+}
+
+impl Midibox for Crossfade {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let from = self.from.next()?;
+        let to = self.to.next()?;
+        let progress = (self.position as f64 / self.fade_ticks as f64).min(1.0);
+        let step_ticks = from.iter().chain(&to).map(|n| n.duration).max().unwrap_or(0);
+        self.position += step_ticks as u64;
+        Some(fade(from, 1.0 - progress).chain(fade(to, progress)).collect())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.from.is_finished() && self.to.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collect_emissions;
+    use crate::dynamics::{Crossfade, ShapeVelocity};
+    use crate::router::VelocityCurve;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    fn shaped_velocities(curve: VelocityCurve) -> Vec<u8> {
+        let seq = Seq::new(vec![
+            Tone::C.oct(4).set_velocity(16),
+            Tone::Rest.oct(4).set_velocity(16),
+            Tone::C.oct(4).set_velocity(64),
+            Tone::C.oct(4).set_velocity(112),
+        ]);
+        collect_emissions(ShapeVelocity::wrap(seq.midibox(), curve).as_mut(), 4)
+            .into_iter().map(|emission| emission[0].velocity).collect()
+    }
+
+    #[test]
+    fn velocities_follow_the_curve() {
+        assert_eq!(shaped_velocities(VelocityCurve::Linear), vec![16, 16, 64, 112]);
+        assert_eq!(shaped_velocities(VelocityCurve::Gamma(2.0)), vec![2, 16, 32, 99]);
+        // the middle is left in place and the ends are pushed outward
+        assert_eq!(shaped_velocities(VelocityCurve::SCurve(1.0)), vec![6, 16, 64, 122]);
+
+        let mut inverted = ShapeVelocity::wrap_with(
+            Seq::new(vec![Tone::C.oct(4).set_velocity(127)]).midibox(),
+            |velocity| 127 - velocity
+        );
+        assert_eq!(inverted.next().unwrap()[0].velocity, 1);
+    }
+
+    #[test]
+    fn crossfade_blends_velocities() {
+        let from = Seq::new(vec![Tone::C.oct(4).set_velocity(100) * 2, Tone::D.oct(4).set_velocity(100) * 2]);
+        let to = Seq::new(vec![Tone::E.oct(4).set_velocity(80) * 2, Tone::F.oct(4).set_velocity(80) * 2]);
+        let emissions = collect_emissions(Crossfade::wrap(from.midibox(), to.midibox(), 2).as_mut(), 6);
+        let velocities: Vec<Vec<u8>> = emissions.iter()
+            .map(|notes| notes.iter().map(|n| if n.is_rest() { 0 } else { n.velocity }).collect())
+            .collect();
+        // faded over two cycles of four ticks, one step of two ticks at a time
+        assert_eq!(velocities, vec![
+            vec![100, 0], vec![75, 20], vec![50, 40], vec![25, 60], vec![0, 80], vec![0, 80],
+        ]);
+        assert_eq!(emissions[4], vec![Tone::Rest * 2, Tone::E.oct(4).set_velocity(80) * 2]);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::Midibox;
+use crate::cycle_ticks;
+use crate::midi::Midi;
+
+/// Plays `primary` but swaps in `alternate` on every `n`th cycle of it (see `Midibox::cycle`),
+/// e.g. a drum fill every fourth bar.
+///
+/// Cycles are counted from zero, and the alternate plays on those whose number is `offset` more
+/// than a multiple of `n`, so an offset of 2 with `n` of 4 lands the fill on the third loop of
+/// every four. Each step comes from whichever midibox the cycle it starts in belongs to, so the
+/// alternate should fill a cycle exactly to keep the groove in time. The primary isn't polled
+/// during the alternate's cycles and picks up where it left off, while the alternate carries on
+/// from its last step each time. A primary that doesn't repeat has cycles of one tick.
+pub struct EveryN {
+    primary: Box<dyn Midibox>,
+    alternate: Box<dyn Midibox>,
+    n: u64,
+    offset: u64,
+    cycle_ticks: u64,
+    /// Ticks of output produced so far, counting steps as `cycle_ticks` does.
+    position: u64,
+}
+
+impl EveryN {
+    pub fn wrap(
+        primary: Box<dyn Midibox>,
+        alternate: Box<dyn Midibox>,
+        n: u32,
+        offset: u32
+    ) -> Box<dyn Midibox> {
+        let cycle_ticks = cycle_ticks(primary.as_ref()).unwrap_or(1);
+        let n = n.max(1) as u64;
+        Box::new(EveryN {
+            primary,
+            alternate,
+            n,
+            offset: offset as u64 % n,
+            cycle_ticks: cycle_ticks.max(1),
+            position: 0,
+        })
+    }
+
+    fn in_alternate_cycle(&self) -> bool {
+        (self.position / self.cycle_ticks) % self.n == self.offset
+    }
+}
+
+impl Midibox for EveryN {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let notes = if self.in_alternate_cycle() {
+            self.alternate.next()?
+        } else {
+            self.primary.next()?
+        };
+        self.position += notes.iter().map(|n| n.duration).max().unwrap_or(0) as u64;
+        Some(notes)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.primary.is_finished() && self.alternate.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collect_emissions;
+    use crate::fill::EveryN;
+    use crate::midi::Midi;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn alternate_replaces_every_nth_cycle() {
+        let groove = Seq::new(vec![Tone::C.oct(2) * 2, Tone::D.oct(2) * 2]);
+        let fill = Seq::new(vec![Tone::A.oct(2) * 1, Tone::B.oct(2) * 1, Tone::A.oct(2) * 2]);
+        let mut midibox = EveryN::wrap(groove.midibox(), fill.midibox(), 4, 2);
+        let pitches: Vec<Vec<u8>> = collect_emissions(midibox.as_mut(), 11).iter()
+            .map(|notes| notes.iter().filter_map(Midi::u8_maybe).collect())
+            .collect();
+        let groove_loop = [vec![36], vec![38]];
+        let fill_loop = [vec![45], vec![47], vec![45]];
+        // the fill lands on the third loop of four, then the groove carries on
+        let expected = [&groove_loop[..], &groove_loop, &fill_loop, &groove_loop, &groove_loop];
+        assert_eq!(pitches, expected.concat());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::Midibox;
+use crate::midi::Midi;
+use crate::scale::{Degree, Scale};
+
+/// Harmonizes each note with a degree chosen by how strong the beat it lands on is, so that
+/// strong beats get fuller harmony than the beats between them.
+///
+/// A step is on a strong beat when it starts on the downbeat or halfway through the bar, as with
+/// beats one and three in 4/4. Harmonizing by the unison adds nothing, leaving a single note.
+/// Notes outside the scale and rests are passed through unharmonized.
+pub struct BeatHarmonizer {
+    scale: Scale,
+    strong_degree: Degree,
+    weak_degree: Degree,
+    bar_ticks: u32,
+    /// Ticks of output produced so far, summed from the duration of each emission.
+    position: u64,
+    midibox: Box<dyn Midibox>,
+}
+
+impl BeatHarmonizer {
+    pub fn wrap(
+        midibox: Box<dyn Midibox>,
+        scale: Scale,
+        strong_degree: Degree,
+        weak_degree: Degree,
+        bar_ticks: u32,
+    ) -> Box<dyn Midibox> {
+        Box::new(BeatHarmonizer {
+            scale,
+            strong_degree,
+            weak_degree,
+            bar_ticks: bar_ticks.max(1),
+            position: 0,
+            midibox,
+        })
+    }
+
+    fn is_strong_beat(&self) -> bool {
+        let bar_ticks = self.bar_ticks as u64;
+        let offset = self.position % bar_ticks;
+        offset == 0 || (bar_ticks.is_multiple_of(2) && offset == bar_ticks / 2)
+    }
+}
+
+impl Midibox for BeatHarmonizer {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let notes = self.midibox.next()?;
+        let degree = if self.is_strong_beat() { self.strong_degree } else { self.weak_degree };
+        self.position += notes.iter().map(|n| n.duration).max().unwrap_or(0) as u64;
+        if degree == Degree::Unison {
+            return Some(notes);
+        }
+        let harmony: Vec<Midi> = notes.iter()
+            .filter(|note| !note.is_rest())
+            .filter_map(|note| self.scale.harmonize_up(*note, degree))
+            .collect();
+        Some(notes.into_iter().chain(harmony).collect())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// Moves every note the wrapped midibox emits onto the nearest tone of a scale, forcing any
+/// source, such as a random one or an off-key recording, into the key.
+///
+/// Notes already in the scale are left alone. A note halfway between two scale tones goes to
+/// the lower one, unless that would fall below octave 0. Rests pass through unchanged.
+pub struct SnapToScale {
+    scale: Scale,
+    midibox: Box<dyn Midibox>,
+}
+
+impl SnapToScale {
+    pub fn wrap(midibox: Box<dyn Midibox>, scale: Scale) -> Box<dyn Midibox> {
+        Box::new(SnapToScale {
+            scale,
+            midibox,
+        })
+    }
+}
+
+impl Midibox for SnapToScale {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        self.midibox.next()
+            .map(|notes| notes.into_iter().map(|note| self.scale.snap(note)).collect())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harmony::{BeatHarmonizer, SnapToScale};
+    use crate::scale::{Degree, Scale};
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn strong_beats_get_fuller_harmony() {
+        let mut midibox = BeatHarmonizer::wrap(
+            Seq::new(vec![Tone::C.oct(4) * 4, Tone::D.oct(4) * 4, Tone::E.oct(4) * 4, Tone::Rest * 4])
+                .midibox(),
+            Scale::major(Tone::C),
+            Degree::Fifth,
+            Degree::Unison,
+            16
+        );
+        assert_eq!(midibox.next(), Some(vec![Tone::C.oct(4) * 4, Tone::G.oct(4) * 4]));
+        assert_eq!(midibox.next(), Some(vec![Tone::D.oct(4) * 4]));
+        assert_eq!(midibox.next(), Some(vec![Tone::E.oct(4) * 4, Tone::B.oct(4) * 4]));
+        assert_eq!(midibox.next(), Some(vec![Tone::Rest * 4]));
+
+        let mut midibox = BeatHarmonizer::wrap(
+            Seq::new(vec![Tone::C.oct(4) * 2]).midibox(),
+            Scale::major(Tone::C),
+            Degree::Sixth,
+            Degree::Third,
+            8
+        );
+        let harmonies: Vec<_> = (0..4).map(|_| midibox.next().unwrap()[1].tone).collect();
+        assert_eq!(harmonies, vec![Tone::A, Tone::E, Tone::A, Tone::E]);
+    }
+
+    #[test]
+    fn snaps_to_the_nearest_scale_tone() {
+        let pitches: Vec<u8> = (12..=127).collect();
+        let mut midibox = SnapToScale::wrap(
+            Seq::new(pitches.iter().map(|p| Tone::C.oct(4).set_pitch_u8(Some(*p))).collect()).midibox(),
+            Scale::major(Tone::D)
+        );
+        let in_key = Scale::major(Tone::D).tones();
+        for pitch in &pitches {
+            let snapped = midibox.next().unwrap()[0].u8_maybe().unwrap();
+            assert!(in_key.contains(&Tone::C.oct(4).set_pitch_u8(Some(snapped)).tone));
+            assert!(snapped.abs_diff(*pitch) <= 1, "{} snapped to {}", pitch, snapped);
+        }
+
+        let mut midibox = SnapToScale::wrap(
+            Seq::new(vec![Tone::C.oct(4), Tone::Eb.oct(4), Tone::Rest * 1, Tone::D.oct(4)]).midibox(),
+            Scale::major(Tone::D)
+        );
+        // C is between B and Db, and Eb between D and E, so both go down
+        assert_eq!(midibox.next(), Some(vec![Tone::B.oct(3)]));
+        assert_eq!(midibox.next(), Some(vec![Tone::D.oct(4)]));
+        assert_eq!(midibox.next(), Some(vec![Tone::Rest * 1]));
+        assert_eq!(midibox.next(), Some(vec![Tone::D.oct(4)]));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::info;
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
+
+use crate::Midibox;
+use crate::meter::Meter;
+use crate::midi::{CLOCK_MSG, CLOCK_PULSES_PER_TICK, CONTINUE_MSG, Midi, NOTE_OFF_MSG, NOTE_ON_MSG, START_MSG, STOP_MSG};
+use crate::player::Transport;
+
+/// How many of the latest pulse intervals are averaged to measure the tempo, smoothing out
+/// jitter in when they arrive.
+const SMOOTHING_PULSES: usize = 24;
+
+fn find_port(midi_in: &MidiInput, port_id: usize) -> Result<MidiInputPort, String> {
+    let ports = midi_in.ports();
+    for (i, p) in ports.iter().enumerate() {
+        info!("Input {}: {}", i, midi_in.port_name(p).unwrap());
+    }
+    ports.get(port_id).cloned().ok_or_else(|| format!("Could not find input port {}", port_id))
+}
+
+/// Plays the notes held down on an external keyboard or controller.
+///
+/// Each poll emits every key that is down as a one-tick tied note, so the player keeps it
+/// sounding for as long as the key stays down and releases it on the first poll after the key
+/// comes up. A key pressed and released between polls is still played, for one tick. When
+/// nothing is held the emission is empty.
+pub struct MidiInputBox {
+    keys: Arc<Mutex<Keys>>,
+    /// Kept so the port stays open for as long as the midibox exists.
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiInputBox {
+    /// Opens MIDI input port `port_id` and starts listening for notes.
+    pub fn for_port(port_id: usize) -> Result<Self, Box<dyn Error>> {
+        let midi_in = MidiInput::new("midibox input")?;
+        let port = find_port(&midi_in, port_id)?;
+
+        let keys = Arc::new(Mutex::new(Keys::default()));
+        let callback_keys = Arc::clone(&keys);
+        let connection = midi_in
+            .connect(&port, "midibox input", move |_, message, _| {
+                callback_keys.lock().unwrap().handle(message);
+            }, ())
+            .map_err(|err| format!("Could not connect to input port {}: {}", port_id, err))?;
+
+        Ok(MidiInputBox { keys, _connection: connection })
+    }
+}
+
+impl Midibox for MidiInputBox {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        Some(self.keys.lock().unwrap().drain())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Key {
+    note: u8,
+    velocity: u8,
+    channel: u8,
+}
+
+/// The keys received from the controller, shared between the input thread and the midibox.
+#[derive(Debug, Default)]
+struct Keys {
+    held: Vec<Key>,
+    /// Keys that were pressed and released again before they were ever polled.
+    tapped: Vec<Key>,
+    /// Keys that have been pressed since the last poll.
+    unpolled: Vec<Key>,
+}
+
+impl Keys {
+    fn handle(&mut self, message: &[u8]) {
+        let [status, note, velocity] = match message {
+            [status, note, velocity, ..] => [*status, *note, *velocity],
+            _ => return,
+        };
+        let key = Key { note, velocity, channel: status & 0x0F };
+        let same_key = |other: &Key| other.note == key.note && other.channel == key.channel;
+        match status & 0xF0 {
+            NOTE_ON_MSG if velocity > 0 => {
+                self.held.retain(|held| !same_key(held));
+                self.held.push(key);
+                self.unpolled.push(key);
+            }
+            NOTE_ON_MSG | NOTE_OFF_MSG => {
+                self.held.retain(|held| !same_key(held));
+                if let Some(i) = self.unpolled.iter().position(same_key) {
+                    self.tapped.push(self.unpolled.remove(i));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Midi> {
+        self.unpolled.clear();
+        let held = self.held.iter().map(|key| (key, true));
+        let tapped = self.tapped.iter().map(|key| (key, false));
+        let notes = held.chain(tapped)
+            .map(|(key, tie)| {
+                Midi::from(key.note)
+                    .set_velocity(key.velocity)
+                    .set_channel(key.channel)
+                    .set_tie(tie)
+            })
+            .collect();
+        self.tapped.clear();
+        notes
+    }
+}
+
+/// Follows the MIDI clock sent by an external master, such as a hardware sequencer, instead of
+/// keeping its own tempo.
+///
+/// Each tick lasts as long as six clock pulses (a sixteenth note at 24 pulses per quarter note),
+/// measured from the average gap between the latest pulses. Until the clock has been running
+/// for two pulses, ticks take their length from `fallback`. Start and continue messages resume
+/// the transport (see `transport`) and stop pauses it, so sharing it with the player through
+/// `PlayerConfig::with_transport` lets the master start and stop playback. The player can't
+/// rewind, so start carries on from where playback stopped.
+pub struct MidiClockMeter {
+    clock: Arc<Mutex<Clock>>,
+    fallback: Box<dyn Meter>,
+    transport: Transport,
+    /// Kept so the port stays open for as long as the meter exists.
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiClockMeter {
+    /// Opens MIDI input port `port_id` and starts listening for clock and transport messages.
+    pub fn for_port(port_id: usize, fallback: Box<dyn Meter>) -> Result<Self, Box<dyn Error>> {
+        let mut midi_in = MidiInput::new("midibox clock")?;
+        midi_in.ignore(Ignore::SysexAndActiveSense);
+        let port = find_port(&midi_in, port_id)?;
+
+        let clock = Arc::new(Mutex::new(Clock::default()));
+        let transport = Transport::new();
+        let callback_clock = Arc::clone(&clock);
+        let callback_transport = transport.clone();
+        let connection = midi_in
+            .connect(&port, "midibox clock", move |stamp, message, _| {
+                callback_clock.lock().unwrap().handle(stamp, message, &callback_transport);
+            }, ())
+            .map_err(|err| format!("Could not connect to input port {}: {}", port_id, err))?;
+
+        Ok(MidiClockMeter { clock, fallback, transport, _connection: connection })
+    }
+
+    /// The transport driven by the master's start, stop and continue messages.
+    pub fn transport(&self) -> Transport {
+        self.transport.clone()
+    }
+}
+
+impl Meter for MidiClockMeter {
+    fn tick_duration(&self, tick_id: u64) -> Duration {
+        self.clock.lock().unwrap().tick_duration()
+            .unwrap_or_else(|| self.fallback.tick_duration(tick_id))
+    }
+}
+
+/// The timing of the clock pulses received, shared between the input thread and the meter.
+#[derive(Debug, Default)]
+struct Clock {
+    /// When the last pulse arrived, in microseconds, unless the clock has stopped since.
+    last_pulse: Option<u64>,
+    /// The latest gaps between pulses, in microseconds.
+    intervals: VecDeque<u64>,
+}
+
+impl Clock {
+    fn handle(&mut self, stamp: u64, message: &[u8], transport: &Transport) {
+        match message.first() {
+            Some(&CLOCK_MSG) => {
+                if let Some(last) = self.last_pulse {
+                    self.intervals.push_back(stamp.saturating_sub(last));
+                    if self.intervals.len() > SMOOTHING_PULSES {
+                        self.intervals.pop_front();
+                    }
+                }
+                self.last_pulse = Some(stamp);
+            }
+            Some(&START_MSG) | Some(&CONTINUE_MSG) => transport.resume(),
+            Some(&STOP_MSG) => {
+                transport.pause();
+                // the pause mustn't count as a gap between pulses
+                self.last_pulse = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick_duration(&self) -> Option<Duration> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let average = self.intervals.iter().sum::<u64>() / self.intervals.len() as u64;
+        Some(Duration::from_micros(average * CLOCK_PULSES_PER_TICK as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::input::{Clock, Keys};
+    use crate::player::{Transport, TransportState};
+    use crate::midi::Midi;
+    use crate::tone::Tone;
+
+    #[test]
+    fn held_keys_are_tied_until_released() {
+        let mut keys = Keys::default();
+        assert_eq!(keys.drain(), Vec::<Midi>::new());
+
+        keys.handle(&[0x91, 60, 90]);
+        let held = vec![Tone::C.oct(4).set_velocity(90).set_channel(1).set_tie(true)];
+        assert_eq!(keys.drain(), held);
+        assert_eq!(keys.drain(), held);
+
+        // a quick tap is played once even though it was released before being polled
+        keys.handle(&[0x90, 64, 70]);
+        keys.handle(&[0x90, 64, 0]);
+        keys.handle(&[0x81, 60, 0]);
+        assert_eq!(keys.drain(), vec![Tone::E.oct(4).set_velocity(70).set_channel(0)]);
+        assert!(keys.drain().is_empty());
+    }
+
+    #[test]
+    fn clock_pulses_set_the_tick_length() {
+        let mut clock = Clock::default();
+        let transport = Transport::new();
+        assert_eq!(clock.tick_duration(), None);
+
+        clock.handle(0, &[0xF8], &transport);
+        assert_eq!(clock.tick_duration(), None);
+        for pulse in 1..=4 {
+            // 20ms per pulse is 125 quarter notes a minute
+            clock.handle(pulse * 20_000, &[0xF8], &transport);
+        }
+        assert_eq!(clock.tick_duration(), Some(Duration::from_millis(120)));
+
+        clock.handle(90_000, &[0xFC], &transport);
+        assert_eq!(transport.state(), TransportState::Paused);
+        clock.handle(5_000_000, &[0xFB], &transport);
+        clock.handle(5_000_100, &[0xF8], &transport);
+        clock.handle(5_020_100, &[0xF8], &transport);
+        assert_eq!(transport.state(), TransportState::Playing);
+        // the time spent stopped isn't measured as a pulse
+        assert_eq!(clock.tick_duration(), Some(Duration::from_millis(120)));
+    }
+}
@@ -0,0 +1,145 @@
+use std::f64::consts::TAU;
+use crate::Midibox;
+use crate::midi::{Control, Midi};
+
+/// The shape an `Lfo` sweeps its controller through over each period. Every shape starts its
+/// cycle at the LFO's `min` value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    /// Eases up to `max` halfway through the period and back down.
+    Sine,
+    /// Climbs steadily to `max` halfway through the period and back down.
+    Triangle,
+    /// Holds `min` for the first half of the period and `max` for the second.
+    Square,
+    /// Climbs steadily towards `max` over the whole period, then drops back to `min`.
+    Saw,
+}
+
+impl Waveform {
+    /// How far between `min` (0) and `max` (1) the wave is, `phase` of the way through a period.
+    fn level(&self, phase: f64) -> f64 {
+        match self {
+            Waveform::Sine => 0.5 - 0.5 * (TAU * phase).cos(),
+            Waveform::Triangle => 1.0 - (2.0 * phase - 1.0).abs(),
+            Waveform::Square => if phase < 0.5 { 0.0 } else { 1.0 },
+            Waveform::Saw => phase,
+        }
+    }
+}
+
+/// A low-frequency oscillator that automates a controller, e.g. 74 for a filter sweep, by
+/// emitting a Control Change every `step_ticks` ticks that follows a waveform.
+///
+/// The wave repeats every `period_ticks` ticks, moving between `min` and `max` (0-127, clamped;
+/// `min` may be the larger, to turn the wave upside down). Being a midibox of its own, it plays
+/// on a channel alongside the notes it shapes and stays in time with the transport.
+pub struct Lfo {
+    controller: u8,
+    waveform: Waveform,
+    period_ticks: u32,
+    step_ticks: u32,
+    min: u8,
+    max: u8,
+    /// Ticks into the current period.
+    position: u32,
+}
+
+impl Lfo {
+    /// An LFO sweeping `controller` over its whole range, updating it every tick.
+    pub fn new(controller: u8, waveform: Waveform, period_ticks: u32) -> Self {
+        Lfo {
+            controller,
+            waveform,
+            period_ticks: period_ticks.max(1),
+            step_ticks: 1,
+            min: 0,
+            max: 127,
+            position: 0,
+        }
+    }
+
+    pub fn with_range(mut self, min: u8, max: u8) -> Self {
+        self.min = min.min(127);
+        self.max = max.min(127);
+        self
+    }
+
+    /// Starts the wave `ticks` ticks into its period.
+    pub fn with_phase(mut self, ticks: u32) -> Self {
+        self.position = ticks % self.period_ticks;
+        self
+    }
+
+    /// Updates the controller every `ticks` ticks rather than every tick.
+    pub fn with_step_ticks(mut self, ticks: u32) -> Self {
+        self.step_ticks = ticks.max(1);
+        self
+    }
+
+    fn emission(&self, position: u32) -> Vec<Midi> {
+        let level = self.waveform.level(position as f64 / self.period_ticks as f64);
+        let value = self.min as f64 + (self.max as f64 - self.min as f64) * level;
+        let control = Control::ControlChange { controller: self.controller, value: value.round() as u8 };
+        vec![Midi::control(control).set_duration(self.step_ticks)]
+    }
+
+    fn advanced(&self, position: u32, steps: u32) -> u32 {
+        ((position as u64 + self.step_ticks as u64 * steps as u64) % self.period_ticks as u64) as u32
+    }
+}
+
+impl Midibox for Lfo {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let emission = self.emission(self.position);
+        self.position = self.advanced(self.position, 1);
+        Some(emission)
+    }
+
+    fn cycle(&self) -> Option<Vec<Vec<Midi>>> {
+        let (mut a, mut b) = (self.period_ticks, self.step_ticks);
+        while b > 0 {
+            (a, b) = (b, a % b);
+        }
+        self.peek((self.period_ticks / a) as usize)
+    }
+
+    fn peek(&self, count: usize) -> Option<Vec<Vec<Midi>>> {
+        Some((0..count as u32).map(|i| self.emission(self.advanced(self.position, i))).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Midibox;
+    use crate::collect_emissions;
+    use crate::lfo::{Lfo, Waveform};
+    use crate::midi::Control;
+
+    fn values(mut lfo: Lfo, count: usize) -> Vec<u8> {
+        collect_emissions(&mut lfo, count).iter()
+            .map(|emission| match emission[0].control {
+                Some(Control::ControlChange { controller: 74, value }) => value,
+                other => panic!("not a filter cutoff change: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn waveforms_sweep_between_min_and_max() {
+        assert_eq!(values(Lfo::new(74, Waveform::Sine, 4).with_range(20, 100), 5), vec![20, 60, 100, 60, 20]);
+        assert_eq!(values(Lfo::new(74, Waveform::Triangle, 4), 5), vec![0, 64, 127, 64, 0]);
+        assert_eq!(values(Lfo::new(74, Waveform::Square, 4).with_phase(1), 4), vec![0, 127, 127, 0]);
+        assert_eq!(values(Lfo::new(74, Waveform::Saw, 4).with_range(100, 0), 5), vec![100, 75, 50, 25, 100]);
+    }
+
+    #[test]
+    fn steps_last_step_ticks_and_cycle_through_the_period() {
+        let lfo = Lfo::new(74, Waveform::Saw, 6).with_step_ticks(4);
+        // steps fall at ticks 0, 4, 2 (of the next period), then back at 0
+        let cycle = lfo.cycle().unwrap();
+        assert_eq!(cycle.len(), 3);
+        assert!(cycle.iter().all(|emission| emission[0].duration == 4));
+        assert_eq!(values(lfo, 4), vec![0, 85, 42, 0]);
+    }
+}
@@ -2,15 +2,86 @@ use midi::{Midi};
 
 pub mod sequences;
 pub mod router;
+pub mod delay;
+pub mod drum;
 pub mod drumlogue;
+pub mod dynamics;
+pub mod fill;
+pub mod harmony;
+pub mod input;
 pub mod rand;
 pub mod midi;
 pub mod player;
+pub mod arpeggiator;
+pub mod bend;
+pub mod lfo;
 pub mod chord;
 pub mod meter;
+pub mod ratchet;
 pub mod scale;
+pub mod sink;
+pub mod smf;
+pub mod spec;
+pub mod step_sequencer;
+pub mod strum;
 pub mod tone;
+pub mod validate;
 
 pub trait Midibox {
+    /// The notes of the next step. `None` means there was no input this time: the player logs
+    /// it and polls again on the next tick. A midibox that has nothing left to play says so
+    /// through `is_finished` instead.
     fn next(&mut self) -> Option<Vec<Midi>>;
-}
\ No newline at end of file
+
+    /// Whether the midibox has played everything it's going to, e.g. a one-shot sequence (see
+    /// `Seq::once`) that has played through. The player stops polling a finished channel, and a
+    /// bounded run ends early once every channel has finished. Once finished, a midibox stays
+    /// finished. Wrappers are finished when the midibox they wrap is and they have nothing of
+    /// their own left to emit.
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /// One full cycle of the emissions this midibox repeats, starting from its current position,
+    /// without advancing it. Midiboxes that don't repeat (e.g. random ones) return `None`.
+    fn cycle(&self) -> Option<Vec<Vec<Midi>>> {
+        None
+    }
+
+    /// The next `count` emissions, without advancing. Midiboxes that can't look ahead return
+    /// `None`.
+    fn peek(&self, _count: usize) -> Option<Vec<Vec<Midi>>> {
+        None
+    }
+}
+
+/// Calls `next` on `midibox` up to `n` times and returns what it emitted, stopping early if it
+/// runs out.
+pub fn collect_emissions(midibox: &mut dyn Midibox, n: usize) -> Vec<Vec<Midi>> {
+    (0..n).map_while(|_| midibox.next()).collect()
+}
+
+/// The length in ticks of one cycle of `midibox` (see `Midibox::cycle`), taking each step to be
+/// as long as its longest note, as wrappers that track their position in ticks do. `None` for a
+/// midibox that doesn't repeat.
+pub(crate) fn cycle_ticks(midibox: &dyn Midibox) -> Option<u64> {
+    midibox.cycle().map(|steps| steps.iter()
+        .map(|notes| notes.iter().map(|n| n.duration).max().unwrap_or(0) as u64)
+        .sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collect_emissions;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn collect_emissions_cycles() {
+        let mut midibox = Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4), Tone::E.oct(4)]).midibox();
+        let tones: Vec<Tone> = collect_emissions(midibox.as_mut(), 7).iter().map(|e| e[0].tone).collect();
+        assert_eq!(tones, vec![Tone::C, Tone::D, Tone::E, Tone::C, Tone::D, Tone::E, Tone::C]);
+
+        assert!(collect_emissions(Seq::empty().midibox().as_mut(), 3).is_empty());
+    }
+}
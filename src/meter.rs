@@ -1,7 +1,25 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crossbeam::atomic::AtomicCell;
+
+use crate::smf;
+
 pub trait Meter {
-    fn tick_duration(&self) -> Duration;
+    /// The real-time length of the tick `tick_id`, counting from the start of playback.
+    fn tick_duration(&self, tick_id: u64) -> Duration;
+}
+
+/// The slowest tempo, in ticks per minute, that a `RampMeter` or `SharedMeter` runs at. Slower
+/// tempos, including zero, negative and NaN ones, are raised to it so that a tick always has a
+/// finite length.
+pub const MIN_BPM: f64 = 1.0;
+
+/// The real-time length of the first beat (see `smf::TICKS_PER_BEAT`), which sets the tempo
+/// written to exported files. Measuring a whole beat evens out swing.
+pub(crate) fn beat_duration(meter: &dyn Meter) -> Duration {
+    (0..smf::TICKS_PER_BEAT as u64).map(|tick_id| meter.tick_duration(tick_id)).sum()
 }
 
 #[derive(Debug, Clone)]
@@ -10,7 +28,7 @@ pub struct Bpm {
 }
 
 impl Meter for Bpm {
-    fn tick_duration(&self) -> Duration {
+    fn tick_duration(&self, _tick_id: u64) -> Duration {
         Duration::from_secs(60) / self.bpm
     }
 }
@@ -20,3 +38,259 @@ impl Bpm {
         Bpm { bpm }
     }
 }
+
+/// A tempo that can be changed while the player is running, e.g. from a tap tempo control on
+/// another thread. Clones share the tempo, so keep one to change it with after handing the meter
+/// to the player: each tick is as long as the tempo when it starts. Like `Bpm`, tempos count
+/// ticks per minute, and tempos below `MIN_BPM` are raised to it.
+#[derive(Debug, Clone)]
+pub struct SharedMeter {
+    bpm: Arc<AtomicCell<f64>>,
+}
+
+impl SharedMeter {
+    pub fn new(bpm: f64) -> Self {
+        SharedMeter { bpm: Arc::new(AtomicCell::new(bpm.max(MIN_BPM))) }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm.load()
+    }
+
+    /// Changes the tempo from the next tick on.
+    pub fn set_bpm(&self, bpm: f64) {
+        self.bpm.store(bpm.max(MIN_BPM));
+    }
+}
+
+impl Meter for SharedMeter {
+    fn tick_duration(&self, _tick_id: u64) -> Duration {
+        Duration::from_secs_f64(60.0 / self.bpm.load())
+    }
+}
+
+/// Changes tempo smoothly (accelerando or ritardando) from `start_bpm` to `end_bpm` over the first
+/// `ticks` ticks, then holds the final tempo. Like `Bpm`, tempos count ticks per minute, and
+/// tempos below `MIN_BPM` are raised to it.
+#[derive(Debug, Clone)]
+pub struct RampMeter {
+    start_bpm: f64,
+    end_bpm: f64,
+    ticks: u64,
+}
+
+impl RampMeter {
+    pub fn new(start_bpm: f64, end_bpm: f64, ticks: u64) -> Self {
+        RampMeter {
+            start_bpm: start_bpm.max(MIN_BPM),
+            end_bpm: end_bpm.max(MIN_BPM),
+            ticks,
+        }
+    }
+
+    /// The tempo halfway through the tick `tick_id`.
+    fn bpm(&self, tick_id: u64) -> f64 {
+        if tick_id >= self.ticks {
+            return self.end_bpm;
+        }
+        let progress = (tick_id as f64 + 0.5) / self.ticks as f64;
+        self.start_bpm + (self.end_bpm - self.start_bpm) * progress
+    }
+}
+
+impl Meter for RampMeter {
+    fn tick_duration(&self, tick_id: u64) -> Duration {
+        Duration::from_secs_f64(60.0 / self.bpm(tick_id))
+    }
+}
+
+/// Applies swing at playback time: steps alternate between being stretched and squeezed in real
+/// time, so every off-beat step starts late while the musical tick counts stay the same. This
+/// works with any midibox, including ones that aren't known ahead of time.
+pub struct SwingMeter {
+    meter: Box<dyn Meter>,
+    /// The length in ticks of the swung subdivision, e.g. an eighth note.
+    step_ticks: u64,
+    /// How much on-beat steps are lengthened, and off-beat steps shortened, as a fraction of a
+    /// step: from 0 (straight) to 0.75.
+    swing: f64,
+}
+
+impl SwingMeter {
+    /// Swings every other step of `step_ticks` ticks by `swing`, which is clamped to 0.0-0.75.
+    /// A swing of 1/3 gives a triplet feel.
+    pub fn new(meter: Box<dyn Meter>, step_ticks: u64, swing: f64) -> Self {
+        SwingMeter {
+            meter,
+            step_ticks: step_ticks.max(1),
+            swing: swing.clamp(0.0, 0.75),
+        }
+    }
+}
+
+impl Meter for SwingMeter {
+    fn tick_duration(&self, tick_id: u64) -> Duration {
+        let base = self.meter.tick_duration(tick_id);
+        if (tick_id / self.step_ticks).is_multiple_of(2) {
+            base.mul_f64(1.0 + self.swing)
+        } else {
+            base.mul_f64(1.0 - self.swing)
+        }
+    }
+}
+
+/// A time signature such as 4/4 or 7/8: `beats_per_bar` beats of `beat_unit` notes each.
+///
+/// A tick is a sixteenth note (see `smf::TICKS_PER_BEAT`), so the beat unit is one of 1, 2, 4, 8
+/// or 16, and a beat lasts a whole number of ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    beats_per_bar: u32,
+    beat_unit: u32,
+}
+
+impl TimeSignature {
+    pub fn new(beats_per_bar: u32, beat_unit: u32) -> Result<Self, String> {
+        let whole_note_ticks = smf::TICKS_PER_BEAT * 4;
+        if beat_unit == 0 || beat_unit > whole_note_ticks || !whole_note_ticks.is_multiple_of(beat_unit) {
+            return Err(format!("Unsupported beat unit: {}", beat_unit));
+        }
+        if beats_per_bar == 0 {
+            return Err("A bar must have at least one beat".to_string());
+        }
+        Ok(TimeSignature { beats_per_bar, beat_unit })
+    }
+
+    pub fn beat_ticks(&self) -> u64 {
+        (smf::TICKS_PER_BEAT * 4 / self.beat_unit) as u64
+    }
+
+    pub fn bar_ticks(&self) -> u64 {
+        self.beats_per_bar as u64 * self.beat_ticks()
+    }
+
+    /// Where `ticks` ticks from the start of the first bar fall.
+    pub fn position(&self, ticks: u64) -> Position {
+        Position {
+            bar: ticks / self.bar_ticks(),
+            beat: (ticks % self.bar_ticks() / self.beat_ticks()) as u32,
+            tick: (ticks % self.beat_ticks()) as u32,
+        }
+    }
+}
+
+/// The length of a note as a fraction of a whole note, e.g. `NoteValue::QUARTER` or
+/// `NoteValue::EIGHTH.dotted()`, for writing rhythms musically rather than in ticks.
+///
+/// The player only counts ticks, and a tick is a sixteenth note: `smf::TICKS_PER_BEAT` ticks
+/// make up a quarter note. Values that don't come to a whole number of ticks, such as triplets
+/// or a dotted sixteenth, are rounded (see `NoteValue::ticks`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteValue {
+    numerator: u32,
+    denominator: u32,
+}
+
+impl NoteValue {
+    pub const WHOLE: NoteValue = NoteValue { numerator: 1, denominator: 1 };
+    pub const HALF: NoteValue = NoteValue { numerator: 1, denominator: 2 };
+    pub const QUARTER: NoteValue = NoteValue { numerator: 1, denominator: 4 };
+    pub const EIGHTH: NoteValue = NoteValue { numerator: 1, denominator: 8 };
+    pub const SIXTEENTH: NoteValue = NoteValue { numerator: 1, denominator: 16 };
+
+    /// Half as long again.
+    pub fn dotted(self) -> Self {
+        NoteValue { numerator: self.numerator * 3, denominator: self.denominator * 2 }
+    }
+
+    /// Two thirds as long, so that three take the time of two.
+    pub fn triplet(self) -> Self {
+        NoteValue { numerator: self.numerator * 2, denominator: self.denominator * 3 }
+    }
+
+    /// The length in ticks, which may fall between ticks.
+    pub fn exact_ticks(&self) -> f64 {
+        (self.numerator * smf::TICKS_PER_BEAT * 4) as f64 / self.denominator as f64
+    }
+
+    /// The length rounded to the nearest tick, and at least one tick so that the note sounds.
+    pub fn ticks(&self) -> u32 {
+        (self.exact_ticks().round() as u32).max(1)
+    }
+}
+
+/// A musical position: the bar, the beat within it, and the tick within that beat, all counted
+/// from zero. Displayed counting from one, as in `3.2.1` for the second beat of the third bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub bar: u64,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.bar + 1, self.beat + 1, self.tick + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::meter::{Bpm, MIN_BPM, Meter, NoteValue, Position, RampMeter, SwingMeter, TimeSignature};
+
+    #[test]
+    fn note_values_in_ticks() {
+        assert_eq!(NoteValue::WHOLE.ticks(), 16);
+        assert_eq!(NoteValue::QUARTER.ticks(), 4);
+        assert_eq!(NoteValue::HALF.dotted().ticks(), 12);
+        assert_eq!(NoteValue::EIGHTH.dotted().ticks(), 3);
+        assert_eq!(NoteValue::HALF.triplet().exact_ticks(), 16.0 / 3.0);
+        assert_eq!(NoteValue::HALF.triplet().ticks(), 5);
+        assert_eq!(NoteValue::SIXTEENTH.triplet().ticks(), 1);
+    }
+
+    #[test]
+    fn positions_follow_the_time_signature() {
+        let seven_eight = TimeSignature::new(7, 8).unwrap();
+        assert_eq!((seven_eight.beat_ticks(), seven_eight.bar_ticks()), (2, 14));
+        assert_eq!(seven_eight.position(0), Position { bar: 0, beat: 0, tick: 0 });
+        assert_eq!(seven_eight.position(13), Position { bar: 0, beat: 6, tick: 1 });
+        assert_eq!(seven_eight.position(31), Position { bar: 2, beat: 1, tick: 1 });
+        assert_eq!(seven_eight.position(31).to_string(), "3.2.2");
+
+        let four_four = TimeSignature::new(4, 4).unwrap();
+        assert_eq!(four_four.position(21), Position { bar: 1, beat: 1, tick: 1 });
+        assert!(TimeSignature::new(3, 3).is_err());
+        assert!(TimeSignature::new(3, 32).is_err());
+        assert!(TimeSignature::new(0, 4).is_err());
+    }
+
+    #[test]
+    fn swing_lengthens_on_beats_and_shortens_off_beats() {
+        let meter = SwingMeter::new(Box::new(Bpm::new(600)), 2, 0.5);
+        let ticks: Vec<Duration> = (0..6).map(|tick_id| meter.tick_duration(tick_id)).collect();
+        let on = Duration::from_millis(150);
+        let off = Duration::from_millis(50);
+        assert_eq!(ticks, vec![on, on, off, off, on, on]);
+
+        let clamped = SwingMeter::new(Box::new(Bpm::new(600)), 1, 0.9);
+        assert_eq!(clamped.tick_duration(1), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn ramp_elapsed_time_matches_the_integral() {
+        let meter = RampMeter::new(60.0, 120.0, 1000);
+        let elapsed: f64 = (0..1000).map(|tick_id| meter.tick_duration(tick_id).as_secs_f64()).sum();
+        // the integral of 60 / bpm(t) over the ramp
+        let expected = 60.0 * 1000.0 / (120.0 - 60.0) * (120.0_f64 / 60.0).ln();
+        assert!((elapsed - expected).abs() / expected < 0.001, "{} vs {}", elapsed, expected);
+
+        assert_eq!(meter.tick_duration(1000), Duration::from_millis(500));
+        assert_eq!(meter.tick_duration(5000), Duration::from_millis(500));
+
+        let stopped = RampMeter::new(60.0, 0.0, 10);
+        assert_eq!(stopped.tick_duration(10), Duration::from_secs_f64(60.0 / MIN_BPM));
+        assert_eq!(RampMeter::new(f64::NAN, -5.0, 10).tick_duration(3), Duration::from_secs(60));
+    }
+}
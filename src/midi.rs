@@ -1,5 +1,8 @@
+use std::fmt::{Display, Formatter};
 use std::ops::{Add, Mul, Sub};
+use std::str::FromStr;
 use crate::chord::{Chord, ToChord};
+use crate::meter::NoteValue;
 use crate::scale::{Degree, Interval, Scale};
 use crate::tone::Tone;
 
@@ -9,13 +12,61 @@ const DEFAULT_DURATION: u32 = 1;
 
 pub const NOTE_ON_MSG: u8 = 0x90;
 pub const NOTE_OFF_MSG: u8 = 0x80;
+pub const CONTROL_CHANGE_MSG: u8 = 0xB0;
+pub const PROGRAM_CHANGE_MSG: u8 = 0xC0;
+pub const PITCH_BEND_MSG: u8 = 0xE0;
+pub const CLOCK_MSG: u8 = 0xF8;
+pub const START_MSG: u8 = 0xFA;
+pub const CONTINUE_MSG: u8 = 0xFB;
+pub const STOP_MSG: u8 = 0xFC;
+/// The controller number of the All Notes Off channel mode message.
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+
+/// The MIDI pitches a `Midi` can hold: up to the highest MIDI note, and down to C0, the lowest
+/// note that `oct` can describe.
+pub const PITCH_RANGE: std::ops::RangeInclusive<u8> = 12..=127;
+
+/// MIDI clock runs at 24 pulses per quarter note, so this many pulses make up a tick (see
+/// `smf::TICKS_PER_BEAT`).
+pub const CLOCK_PULSES_PER_TICK: u32 = 24 / crate::smf::TICKS_PER_BEAT;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Midi {
     pub tone: Tone,
     pub oct: u8,
     pub velocity: u8,
+    /// The velocity (0-127) of the note's NOTE_OFF message, which some synths use to shape the
+    /// release. `None` sends the note-on velocity.
+    pub release_velocity: Option<u8>,
     pub duration: u32,
+    /// The MIDI channel (0-15) to send this note on. `None` leaves the choice to the player,
+    /// which uses the channel's configured MIDI channel (see `PlayerConfig::with_midi_channel`)
+    /// or channel 0.
+    pub channel: Option<u8>,
+    /// When set, the note is held into the next step on its channel instead of being released,
+    /// provided that step sounds the same pitch. The next step's note then continues it rather
+    /// than retriggering.
+    pub tie: bool,
+    /// A control event sent instead of a note. Control events don't sound, are applied as soon
+    /// as they are polled, and may have a duration of zero.
+    pub control: Option<Control>,
+}
+
+/// Instructions to the player that travel alongside notes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    /// Changes the tempo to this many ticks per minute from the tick it is polled on, replacing
+    /// the player's meter for the rest of playback.
+    TempoChange(f64),
+    /// Sets a controller (e.g. 1 for the mod wheel, 74 for filter cutoff) to a value, sent as a
+    /// MIDI Control Change message when the step starts.
+    ControlChange { controller: u8, value: u8 },
+    /// Bends the channel's pitch by this offset from centre, from -8192 (fully down) to 8191
+    /// (fully up), sent as a MIDI Pitch Bend message when the step starts.
+    PitchBend(i16),
+    /// Switches the channel to another program (patch), sent as a MIDI Program Change message
+    /// when the step starts.
+    ProgramChange(u8),
 }
 
 impl Midi {
@@ -24,10 +75,20 @@ impl Midi {
             tone: Tone::Rest,
             oct: DEFAULT_OCT,
             velocity: DEFAULT_VELOCITY,
+            release_velocity: None,
             duration: DEFAULT_DURATION,
+            channel: None,
+            tie: false,
+            control: None,
         }
     }
 
+    /// A silent, zero-duration step that carries `control` to the player. Give it a duration to
+    /// have it take up time like a rest.
+    pub fn control(control: Control) -> Self {
+        Midi { control: Some(control), ..Midi::rest().set_duration(0) }
+    }
+
     pub fn oct(val: u8) -> u8 {
         (val / 12) - 1
     }
@@ -40,11 +101,21 @@ impl Midi {
     }
 
     pub fn from_tone(tone: Tone, oct: u8) -> Midi {
-        Midi { tone, oct, velocity: DEFAULT_VELOCITY, duration: DEFAULT_DURATION }
+        Midi {
+            tone,
+            oct,
+            velocity: DEFAULT_VELOCITY,
+            release_velocity: None,
+            duration: DEFAULT_DURATION,
+            channel: None,
+            tie: false,
+            control: None,
+        }
     }
 
+    /// The note with MIDI pitch `val`, or a rest for pitches outside `PITCH_RANGE`.
     pub fn from(val: u8) -> Midi {
-        Midi::from_tone(Tone::from(val), Midi::oct(val))
+        Midi::rest().set_pitch_u8(Some(val))
     }
 
     pub fn is_rest(&self) -> bool {
@@ -55,31 +126,154 @@ impl Midi {
         self.tone.u8(self.oct)
     }
 
+    /// Sets the velocity (0-127) for this note. Values above 127 are clamped.
     pub fn set_velocity(&self, velocity: u8) -> Self {
-        Midi { tone: self.tone, oct: self.oct, velocity, duration: self.duration }
+        Midi { velocity: velocity.min(127), ..*self }
+    }
+
+    /// Sets the velocity (0-127) sent when this note is released. Values above 127 are clamped.
+    pub fn set_release_velocity(&self, velocity: u8) -> Self {
+        Midi { release_velocity: Some(velocity.min(127)), ..*self }
     }
 
     pub fn set_duration(&self, duration: u32) -> Self {
-        Midi { tone: self.tone, oct: self.oct, velocity: self.velocity, duration }
+        Midi { duration, ..*self }
+    }
+
+    /// Sets the duration to a whole note, in ticks. See `NoteValue`.
+    pub fn whole(&self) -> Self {
+        self.set_note_value(NoteValue::WHOLE)
+    }
+
+    /// Sets the duration to a half note.
+    pub fn half(&self) -> Self {
+        self.set_note_value(NoteValue::HALF)
+    }
+
+    /// Sets the duration to a quarter note.
+    pub fn quarter(&self) -> Self {
+        self.set_note_value(NoteValue::QUARTER)
+    }
+
+    /// Sets the duration to an eighth note.
+    pub fn eighth(&self) -> Self {
+        self.set_note_value(NoteValue::EIGHTH)
     }
 
+    /// Sets the duration to a sixteenth note, a single tick.
+    pub fn sixteenth(&self) -> Self {
+        self.set_note_value(NoteValue::SIXTEENTH)
+    }
+
+    /// Sets the duration to `value` rounded to the nearest tick, e.g.
+    /// `NoteValue::QUARTER.dotted()` or `NoteValue::EIGHTH.triplet()`.
+    pub fn set_note_value(&self, value: NoteValue) -> Self {
+        self.set_duration(value.ticks())
+    }
+
+    /// Sets the MIDI channel (0-15) for this note. Values above 15 are clamped.
+    pub fn set_channel(&self, channel: u8) -> Self {
+        Midi { channel: Some(channel.min(15)), ..*self }
+    }
+
+    pub fn set_tie(&self, tie: bool) -> Self {
+        Midi { tie, ..*self }
+    }
+
+    /// Sets the MIDI pitch of this note, making it a rest for `None` or a pitch outside
+    /// `PITCH_RANGE`.
     pub fn set_pitch_u8(&self, val: Option<u8>) -> Self {
-        match val {
+        match val.filter(|v| PITCH_RANGE.contains(v)) {
             None => self.set_pitch(Tone::Rest, 0),
             Some(v) => self.set_pitch(Tone::from(v), Midi::oct(v))
         }
     }
 
     pub fn set_pitch(&self, tone: Tone, oct: u8) -> Self {
-        Midi { tone, oct, velocity: self.velocity, duration: self.duration }
+        Midi { tone, oct, ..*self }
+    }
+
+    /// The MIDI message that starts (`NOTE_ON_MSG`) or ends (`NOTE_OFF_MSG`) this note on its
+    /// channel. Rests have no message, and control events only have one when they start, if they
+    /// are sent as MIDI at all. Notes pitched above 127 have no message either, and velocities
+    /// are clamped to 127, so only valid data bytes are ever sent.
+    pub fn message(&self, status: u8) -> Option<Vec<u8>> {
+        let channel = self.channel.unwrap_or(0);
+        match self.control {
+            Some(Control::ControlChange { controller, value }) if status == NOTE_ON_MSG => {
+                Some(vec![CONTROL_CHANGE_MSG | channel, controller.min(127), value.min(127)])
+            }
+            Some(Control::PitchBend(offset)) if status == NOTE_ON_MSG => {
+                let value = (offset as i32 + 8192).clamp(0, 16383) as u16;
+                Some(vec![PITCH_BEND_MSG | channel, (value & 0x7F) as u8, (value >> 7) as u8])
+            }
+            Some(Control::ProgramChange(program)) if status == NOTE_ON_MSG => {
+                Some(vec![PROGRAM_CHANGE_MSG | channel, program.min(127)])
+            }
+            Some(_) => None,
+            None => self.u8_maybe()
+                .filter(|pitch| *pitch <= 127)
+                .map(|pitch| {
+                    let velocity = match self.release_velocity {
+                        Some(release) if status == NOTE_OFF_MSG => release,
+                        _ => self.velocity,
+                    };
+                    vec![status | channel, pitch, velocity.min(127)]
+                }),
+        }
     }
 
+    /// Raises the note by `interval`. A note raised past `PITCH_RANGE` becomes a rest.
     pub fn transpose_up(&self, interval: Interval) -> Self {
-        self.set_pitch_u8(self.u8_maybe().map(|v| v + interval.steps()))
+        self.set_pitch_u8(self.u8_maybe().and_then(|v| v.checked_add(interval.steps())))
     }
 
+    /// Lowers the note by `interval`. A note lowered past `PITCH_RANGE` becomes a rest.
     pub fn transpose_down(&self, interval: Interval) -> Self {
-        self.set_pitch_u8(self.u8_maybe().map(|v| v - interval.steps()))
+        self.set_pitch_u8(self.u8_maybe().and_then(|v| v.checked_sub(interval.steps())))
+    }
+
+    /// Raises the note by `octaves` octaves, or by as many as fit below 127 so that it keeps its
+    /// pitch class. Rests are unchanged.
+    pub fn octave_up(&self, octaves: u8) -> Self {
+        self.set_pitch_u8(self.u8_maybe().map(|v| v + 12 * octaves.min((127 - v) / 12)))
+    }
+
+    /// Lowers the note by `octaves` octaves, or by as many as keep it in octave 0 or above (the
+    /// lowest that `oct` can describe) so that it keeps its pitch class. Rests are unchanged.
+    pub fn octave_down(&self, octaves: u8) -> Self {
+        self.set_pitch_u8(self.u8_maybe().map(|v| v - 12 * octaves.min(v.saturating_sub(12) / 12)))
+    }
+}
+
+/// Formats the note by name and octave, e.g. `C4` or `Db2`. Rests are formatted as `Rest`.
+impl Display for Midi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_rest() {
+            write!(f, "{}", self.tone)
+        } else {
+            write!(f, "{}{}", self.tone, self.oct)
+        }
+    }
+}
+
+/// Reads a note formatted by `Display`, e.g. `C4` or `Db2`, or `Rest` in any case. Notes outside
+/// `PITCH_RANGE` are an error.
+impl FromStr for Midi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("rest") {
+            return Ok(Midi::rest());
+        }
+        let (name, oct) = s.split_at(s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len()));
+        let tone = (0..12).map(Tone::from).find(|tone| tone.to_string() == name);
+        match (tone, oct.parse::<u8>()) {
+            (Some(tone), Ok(oct)) if tone.u8(oct).is_some_and(|v| PITCH_RANGE.contains(&v)) => {
+                Ok(tone.oct(oct))
+            }
+            _ => Err(format!("Unknown note: {}", s)),
+        }
     }
 }
 
@@ -141,6 +335,10 @@ pub trait ToMidi: ToChord {
         self.midi().set_duration(duration)
     }
 
+    fn set_channel(&self, channel: u8) -> Midi {
+        self.midi().set_channel(channel)
+    }
+
     fn set_pitch_u8(&self, val: Option<u8>) -> Midi {
         self.midi().set_pitch_u8(val)
     }
@@ -160,7 +358,7 @@ pub trait ToMidi: ToChord {
 
 impl ToMidi for Midi {
     fn midi(&self) -> Midi {
-        self.clone()
+        *self
     }
 
     fn is_rest(&self) -> bool {
@@ -179,6 +377,10 @@ impl ToMidi for Midi {
         self.set_duration(duration)
     }
 
+    fn set_channel(&self, channel: u8) -> Midi {
+        self.set_channel(channel)
+    }
+
     fn set_pitch_u8(&self, val: Option<u8>) -> Midi {
         self.set_pitch_u8(val)
     }
@@ -204,9 +406,37 @@ impl ToChord for Midi {
 
 #[cfg(test)]
 mod tests {
-    use crate::scale::{Degree, Scale};
+    use crate::midi::{Midi, NOTE_OFF_MSG, NOTE_ON_MSG};
+    use crate::scale::{AlteredDegree, Degree, Interval, Scale};
     use crate::tone::Tone;
 
+    #[test]
+    fn out_of_range_values_are_handled() {
+        assert_eq!(Tone::C.oct(4).set_velocity(200).velocity, 127);
+        let loud = Midi { velocity: 200, ..Tone::C.oct(4) };
+        assert_eq!(loud.message(NOTE_ON_MSG), Some(vec![NOTE_ON_MSG, 60, 127]));
+        // G9 is the highest MIDI note
+        assert_eq!(Tone::Ab.oct(9).message(NOTE_ON_MSG), None);
+        assert_eq!(Tone::G.oct(9).message(NOTE_ON_MSG), Some(vec![NOTE_ON_MSG, 127, 100]));
+
+        assert!((Tone::E.oct(9) + Interval::Maj3).is_rest());
+        assert!((Tone::D.oct(0) - Interval::Maj3).is_rest());
+        assert_eq!(Tone::E.oct(0) - Interval::Maj3, Tone::C.oct(0));
+        assert!(Midi::from(5).is_rest());
+        assert!(Midi::from(128).is_rest());
+        assert_eq!(Midi::from(12), Tone::C.oct(0));
+        assert_eq!(Tone::C.u8(30), None);
+    }
+
+    #[test]
+    fn release_velocity_is_sent_with_note_off() {
+        let note = Tone::C.oct(4).set_velocity(90);
+        assert_eq!(note.message(NOTE_OFF_MSG), Some(vec![NOTE_OFF_MSG, 60, 90]));
+        let released = note.set_release_velocity(200);
+        assert_eq!(released.message(NOTE_ON_MSG), Some(vec![NOTE_ON_MSG, 60, 90]));
+        assert_eq!(released.message(NOTE_OFF_MSG), Some(vec![NOTE_OFF_MSG, 60, 127]));
+    }
+
     #[test]
     fn tone() {
         assert_eq!(Tone::C.u8(4), Some(60));
@@ -223,6 +453,13 @@ mod tests {
         assert_eq!(Tone::B.u8(4), Some(71));
     }
 
+    #[test]
+    fn display() {
+        assert_eq!(Tone::C.oct(4).to_string(), "C4");
+        assert_eq!(Tone::Db.oct(2).to_string(), "Db2");
+        assert_eq!(Tone::Rest.oct(4).to_string(), "Rest");
+    }
+
     #[test]
     fn from() {
         assert_eq!(Tone::from(53), Tone::F);
@@ -299,6 +536,66 @@ mod tests {
         )
     }
 
+    #[test]
+    fn harmonize_altered() {
+        let scale = Scale::major(Tone::C);
+        assert_eq!(
+            scale.harmonize_up_altered(Tone::C.oct(4), AlteredDegree::sharp(Degree::Fourth)),
+            Some(Tone::Gb.oct(4))
+        );
+        assert_eq!(
+            scale.harmonize_up_altered(Tone::C.oct(4), "b3".parse().unwrap()),
+            Some(Tone::Eb.oct(4))
+        );
+        assert_eq!(
+            scale.harmonize_up_altered(Tone::D.oct(4), "#4".parse().unwrap()),
+            Some(Tone::Ab.oct(4))
+        );
+        assert_eq!(
+            scale.harmonize_down_altered(Tone::C.oct(4), AlteredDegree::flat(Degree::Fifth)),
+            Some(Tone::E.oct(3))
+        );
+        assert_eq!(
+            scale.harmonize_up_altered(Tone::C.oct(4), Degree::Fifth.into()),
+            Some(Tone::G.oct(4))
+        );
+        assert!("x4".parse::<AlteredDegree>().is_err());
+        assert!("#14".parse::<AlteredDegree>().is_err());
+    }
+
+    #[test]
+    fn triads_and_sevenths() {
+        let scale = Scale::major(Tone::C);
+        assert_eq!(scale.triad(Tone::C.oct(4), Degree::Unison),
+                   vec![Tone::C.oct(4), Tone::E.oct(4), Tone::G.oct(4)]);
+        assert_eq!(scale.triad(Tone::C.oct(4) * 4, Degree::Second),
+                   vec![Tone::D.oct(4) * 4, Tone::F.oct(4) * 4, Tone::A.oct(4) * 4]);
+        assert_eq!(scale.seventh(Tone::C.oct(4), Degree::Seventh),
+                   vec![Tone::B.oct(4), Tone::D.oct(5), Tone::F.oct(5), Tone::A.oct(5)]);
+        assert_eq!(scale.triad(Tone::Rest * 1, Degree::Fifth), vec![]);
+        assert_eq!(scale.triad(Tone::Db.oct(4), Degree::Unison), vec![]);
+    }
+
+    #[test]
+    fn progression() {
+        let scale = Scale::major(Tone::C);
+        assert_eq!(scale.progression("ii V I"), Ok(vec![
+            vec![Tone::D.oct(4), Tone::F.oct(4), Tone::A.oct(4)],
+            vec![Tone::G.oct(4), Tone::B.oct(4), Tone::D.oct(5)],
+            vec![Tone::C.oct(4), Tone::E.oct(4), Tone::G.oct(4)],
+        ]));
+        assert_eq!(scale.progression("V7 vi"), Ok(vec![
+            vec![Tone::G.oct(4), Tone::B.oct(4), Tone::D.oct(5), Tone::F.oct(5)],
+            vec![Tone::A.oct(4), Tone::C.oct(5), Tone::E.oct(5)],
+        ]));
+        // case overrides the diatonic third, e.g. the major II of a secondary dominant
+        assert_eq!(scale.progression("II"), Ok(vec![
+            vec![Tone::D.oct(4), Tone::Gb.oct(4), Tone::A.oct(4)],
+        ]));
+        assert_eq!(scale.progression("ii X"), Err("Unknown chord symbol: X".to_string()));
+        assert!(scale.progression("Vi").is_err());
+    }
+
     #[test]
     fn harmonize_down() {
         assert_eq!(
@@ -1,17 +1,30 @@
-use log::{debug, error, info};
-use std::collections::{HashMap, HashSet};
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::fs;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use crossbeam::atomic::AtomicCell;
 use ctrlc;
-use midir::{MidiOutput, MidiOutputConnection};
+use midir::MidiOutput;
 use crate::Midibox;
-use crate::meter::Meter;
-use crate::midi::{Midi, NOTE_OFF_MSG, NOTE_ON_MSG};
-use crate::router::{Router, StaticRouter};
+use crate::meter;
+use crate::meter::{Meter, Position, TimeSignature};
+use crate::midi::{ALL_NOTES_OFF_CC, CLOCK_MSG, CLOCK_PULSES_PER_TICK, CONTINUE_MSG, CONTROL_CHANGE_MSG, Control, Midi, NOTE_OFF_MSG, NOTE_ON_MSG, PITCH_RANGE, START_MSG, STOP_MSG};
+use crate::router::{NameMatch, NameRouter, PortNameError, Router, StaticRouter, VelocityCurve};
+use crate::sink::{MidiSink, MidirSink, PlaybackError};
+use crate::smf;
 
+/// The time division of exported MIDI files, in file ticks per beat.
+const SMF_DIVISION: u16 = 480;
+
+/// How many zero-duration control steps a channel may emit back to back on one tick before the
+/// player moves on, so that a channel of nothing but control events can't stall playback.
+const MAX_CONTROL_STEPS_PER_TICK: usize = 64;
 
 pub struct Player {
     /// Describes the time spent playing in ticks.
@@ -21,6 +34,229 @@ pub struct Player {
     /// A map from a sounding note's ID to the note, decorated with metadata about how the note was
     /// generated.
     playing_notes: HashMap<u64, PlayingNote>,
+    /// The tick at which each channel is next due to be polled, i.e. the end of the step it last
+    /// emitted. This is tracked separately from note-offs so that a short note in a chord is
+    /// released on time without the channel being polled before the step is over.
+    next_poll_tick: HashMap<usize, u64>,
+    /// No channel is polled before this tick.
+    count_in_ticks: u64,
+    /// Tied notes that have reached the end of their duration and are being held to see whether
+    /// the next step on their channel continues them.
+    held_ties: Vec<PlayingNote>,
+    /// Where internal events are recorded, if tracing is enabled.
+    trace: Option<Trace>,
+    /// The tempo in ticks per minute set by the last `Control::TempoChange`, which takes over
+    /// from the meter.
+    tempo: Option<f64>,
+    /// Notes sounding for this many ticks are released whatever their duration.
+    max_note_ticks: Option<u64>,
+    /// When the channels are polled.
+    sync_mode: SyncMode,
+    /// The channels, with the MIDI channel within each if the bend set one, whose pitch is
+    /// currently bent away from centre.
+    bent_channels: BTreeSet<(usize, Option<u8>)>,
+    /// How started notes are varied, and the generator drawing the variations.
+    humanize: Option<(Humanize, StdRng)>,
+    /// Notes pushed back by humanizing, waiting for the later tick they start on.
+    delayed_notes: Vec<PlayingNote>,
+    /// The channels in legato mode, with the most notes each may have sounding at once.
+    legato: HashMap<usize, usize>,
+    /// Notes cut short by their legato channel playing their pitch again, waiting to be released
+    /// before the notes that restart them.
+    restarted_notes: Vec<PlayingNote>,
+    /// The channels found to be finished, which aren't polled again.
+    finished: HashSet<usize>,
+    /// The semitones each transposed channel's notes are shifted by as they start.
+    transpose: HashMap<usize, i8>,
+    /// The channels whose notes aren't started.
+    muted: HashSet<usize>,
+    /// The groove template of each channel that has one, with the number of steps the channel
+    /// has been polled for.
+    groove: HashMap<usize, (GrooveTemplate, usize)>,
+}
+
+/// Random variation in when and how hard the player starts notes, so quantized sequences sound
+/// less mechanical.
+///
+/// A note can only be started once its channel has been polled, so notes are only ever moved
+/// later, by whole ticks. Each note still lasts its full duration from the tick it actually
+/// starts on. Notes continuing a tie, rests and control events aren't varied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Humanize {
+    /// The most ticks a note is delayed by.
+    pub max_delay_ticks: u64,
+    /// The most a velocity is moved in either direction.
+    pub velocity: u8,
+    /// Seeds the variations, so the same seed always humanizes a sequence the same way.
+    pub seed: u64,
+}
+
+/// Moves the notes of particular steps later, like the groove templates of a drum machine: a
+/// channel's `n`th step is delayed by the `n`th offset in ticks, with the offsets cycled, so a
+/// template as long as the pattern repeats with it.
+///
+/// As with `Humanize`, notes are only ever moved later, by whole ticks, and last their full
+/// duration from the tick they start on. To push one step ahead of the others, delay the others
+/// instead, and for offsets finer than a tick, write the pattern with more ticks to a step and
+/// play it at a faster tempo. Zero-duration control steps aren't counted, and notes continuing
+/// a tie, rests and control events aren't moved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrooveTemplate {
+    offsets: Vec<u64>,
+}
+
+impl GrooveTemplate {
+    pub fn new(offsets: Vec<u64>) -> Self {
+        GrooveTemplate { offsets }
+    }
+
+    /// The delay in ticks of the step at index `step`, counting from the channel's first.
+    pub fn offset(&self, step: usize) -> u64 {
+        if self.offsets.is_empty() {
+            0
+        } else {
+            self.offsets[step % self.offsets.len()]
+        }
+    }
+}
+
+/// What the player does when a message can't be delivered to a port.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ErrorPolicy {
+    /// Stops playback, releasing the sounding notes as far as possible, and returns the error.
+    #[default]
+    Abort,
+    /// Logs the error and stops sending to that port, so the other ports keep playing.
+    SkipPort,
+}
+
+/// How the player decides when to poll each channel for its next step.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SyncMode {
+    /// Each channel is polled when its previous step is over, so channels with different step
+    /// lengths drift against each other (polymeter). Every channel loops at its own length and
+    /// is never resynced, so e.g. a three-beat bass line and a four-beat melody only start
+    /// together again every twelve beats.
+    #[default]
+    Independent,
+    /// Every channel is polled together once every this many ticks, however long its notes are.
+    /// Notes that outlast the grid overlap the next step.
+    Locked(u64),
+}
+
+
+/// Whether a running player is moving forward, holding its position, or finishing up.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TransportState {
+    #[default]
+    Playing,
+    /// Time stands still and the sounding notes are silenced until playback resumes.
+    Paused,
+    /// The player releases its notes and returns, as if its running flag had been cleared.
+    Stopped,
+}
+
+/// A shared handle for controlling a running player's transport from another thread.
+///
+/// Clones share the same state, so a clone can be kept while the original is handed to the
+/// player through `PlayerConfig::with_transport`.
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    state: Arc<Mutex<TransportState>>,
+    /// Whether a panic has been asked for and not yet carried out.
+    panic: Arc<Mutex<bool>>,
+    /// Transpositions asked for and not yet handed to the player.
+    transpose: Arc<Mutex<HashMap<usize, i8>>>,
+    /// Which channels are muted and soloed.
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+#[derive(Debug, Default)]
+struct Mixer {
+    muted: HashSet<usize>,
+    soloed: HashSet<usize>,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> TransportState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set_state(&self, state: TransportState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn pause(&self) {
+        self.set_state(TransportState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.set_state(TransportState::Playing);
+    }
+
+    pub fn stop(&self) {
+        self.set_state(TransportState::Stopped);
+    }
+
+    /// Asks the player to silence everything at its next tick, for when notes are stuck: it
+    /// switches off every note it's tracking and sends All Notes Off on every MIDI channel of
+    /// every routed port. Playback carries on, with each channel starting again at its next step.
+    pub fn panic(&self) {
+        *self.panic.lock().unwrap() = true;
+    }
+
+    /// Whether a panic was asked for since the last call.
+    fn take_panic(&self) -> bool {
+        std::mem::take(&mut *self.panic.lock().unwrap())
+    }
+
+    /// Transposes `channel_id` by `semitones` from the player's next tick, e.g. for a key change
+    /// in the final chorus. See `Player::set_transpose`.
+    pub fn transpose(&self, channel_id: usize, semitones: i8) {
+        self.transpose.lock().unwrap().insert(channel_id, semitones);
+    }
+
+    /// The transpositions asked for since the last call.
+    fn take_transpositions(&self) -> HashMap<usize, i8> {
+        std::mem::take(&mut *self.transpose.lock().unwrap())
+    }
+
+    /// Silences `channel_id` from the player's next tick until it's unmuted. See
+    /// `Player::set_muted`.
+    pub fn mute(&self, channel_id: usize) {
+        self.mixer.lock().unwrap().muted.insert(channel_id);
+    }
+
+    /// Lets `channel_id` be heard again from the player's next tick, unless a solo leaves it out.
+    pub fn unmute(&self, channel_id: usize) {
+        self.mixer.lock().unwrap().muted.remove(&channel_id);
+    }
+
+    /// Solos `channel_id`: while any channel is soloed, every channel that isn't is muted.
+    pub fn solo(&self, channel_id: usize) {
+        self.mixer.lock().unwrap().soloed.insert(channel_id);
+    }
+
+    /// Takes `channel_id` out of the solo, which ends once no channel is soloed.
+    pub fn unsolo(&self, channel_id: usize) {
+        self.mixer.lock().unwrap().soloed.remove(&channel_id);
+    }
+
+    /// Whether `channel_id` is heard, i.e. isn't muted and isn't left out of a solo.
+    pub fn is_audible(&self, channel_id: usize) -> bool {
+        let mixer = self.mixer.lock().unwrap();
+        !mixer.muted.contains(&channel_id)
+            && (mixer.soloed.is_empty() || mixer.soloed.contains(&channel_id))
+    }
+
+    /// The channels out of the first `channel_count` that aren't heard.
+    fn muted_channels(&self, channel_count: usize) -> HashSet<usize> {
+        (0..channel_count).filter(|channel_id| !self.is_audible(*channel_id)).collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -30,90 +266,527 @@ pub struct PlayingNote {
     pub note: Midi,
 }
 
+/// What happens on one tick, as worked out by `Player::step`.
+#[derive(Debug, Clone, Default)]
+pub struct TickEvents {
+    /// Notes that end on this tick, including held ties that weren't continued.
+    pub note_offs: Vec<PlayingNote>,
+    /// Notes and control events that start on this tick.
+    pub note_ons: Vec<PlayingNote>,
+}
+
+/// Something the player did, stamped with the tick it happened on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEvent {
+    /// A channel was polled for its next step.
+    Poll { tick: u64, channel_id: usize },
+    /// A note was started. Rests and notes continuing a tie aren't recorded.
+    NoteOn { tick: u64, channel_id: usize, note: Midi },
+    /// A note was released. Rests aren't recorded.
+    NoteOff { tick: u64, channel_id: usize, note: Midi },
+    /// Time advanced; `tick` is the tick that has just begun.
+    Tick { tick: u64 },
+}
+
+/// An ordered record of the player's internal events, for checking scheduling behaviour.
+///
+/// Clones share the same record, so a clone can be kept while the original is handed to the
+/// player, and inspected once playback has finished.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn record(&self, event: TraceEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
 impl Player {
     pub fn new() -> Self {
         Player {
             tick_id: 0,
             note_id: 0,
             playing_notes: HashMap::new(),
+            next_poll_tick: HashMap::new(),
+            count_in_ticks: 0,
+            held_ties: Vec::new(),
+            trace: None,
+            tempo: None,
+            max_note_ticks: None,
+            sync_mode: SyncMode::Independent,
+            bent_channels: BTreeSet::new(),
+            humanize: None,
+            delayed_notes: Vec::new(),
+            legato: HashMap::new(),
+            restarted_notes: Vec::new(),
+            finished: HashSet::new(),
+            transpose: HashMap::new(),
+            muted: HashSet::new(),
+            groove: HashMap::new(),
+        }
+    }
+
+    /// Puts a channel in legato mode: its next step is polled as soon as the shortest note of the
+    /// last one ends, so longer notes carry on under the steps that follow, each released after
+    /// its own duration. While `max_voices` of its notes are sounding the channel isn't polled,
+    /// so it can't pile up notes without limit, though a chord is never cut down to fit under
+    /// the limit. A pitch that is still sounding when the channel plays it again is restarted
+    /// rather than doubled.
+    pub fn with_legato(mut self, channel_id: usize, max_voices: usize) -> Self {
+        self.legato.insert(channel_id, max_voices.max(1));
+        self
+    }
+
+    /// Whether a channel can start another note, which is always the case outside legato mode.
+    fn has_free_voice(&self, channel_id: usize) -> bool {
+        let Some(max_voices) = self.legato.get(&channel_id) else {
+            return true;
+        };
+        let sounding = self.playing_notes.values()
+            .chain(self.held_ties.iter())
+            .filter(|playing| playing.channel_id == channel_id && !playing.note.is_rest())
+            .count();
+        sounding < *max_voices
+    }
+
+    /// Shifts the notes `channel_id` starts from now on by `semitones`, replacing any earlier
+    /// transposition. Notes already sounding are released at the pitch they started on.
+    /// Pitches shifted past `PITCH_RANGE` are clamped to its ends, and rests and control events
+    /// are left alone.
+    pub fn set_transpose(&mut self, channel_id: usize, semitones: i8) {
+        self.transpose.insert(channel_id, semitones);
+    }
+
+    /// Mutes exactly `channels`, returning the notes of the newly muted ones to be released.
+    /// Muted channels are still polled, so they stay in step and come back in where they would
+    /// have been, but the notes they emit aren't started. Their control events still apply.
+    pub fn set_muted(&mut self, channels: HashSet<usize>) -> Vec<PlayingNote> {
+        let newly_muted: HashSet<usize> = channels.difference(&self.muted).copied().collect();
+        self.muted = channels;
+        if newly_muted.is_empty() {
+            return Vec::new();
+        }
+        self.delayed_notes.retain(|playing| !newly_muted.contains(&playing.channel_id));
+        let (mut released, held): (Vec<PlayingNote>, Vec<PlayingNote>) =
+            std::mem::take(&mut self.held_ties).into_iter()
+                .partition(|playing| newly_muted.contains(&playing.channel_id));
+        self.held_ties = held;
+        released.extend(self.clear_notes(|playing| newly_muted.contains(&playing.channel_id)));
+        self.record_note_offs(&released);
+        released
+    }
+
+    fn transposed(&self, channel_id: usize, note: Midi) -> Midi {
+        match (self.transpose.get(&channel_id), note.u8_maybe()) {
+            (Some(semitones), Some(pitch)) if note.control.is_none() => {
+                let (lowest, highest) = (*PITCH_RANGE.start() as i16, *PITCH_RANGE.end() as i16);
+                let pitch = (pitch as i16 + *semitones as i16).clamp(lowest, highest);
+                note.set_pitch_u8(Some(pitch as u8))
+            }
+            _ => note,
         }
     }
 
+    /// Delays and re-weights the notes the player starts at random, within the bounds of
+    /// `humanize`.
+    pub fn with_humanize(mut self, humanize: Humanize) -> Self {
+        self.humanize = Some((humanize, StdRng::seed_from_u64(humanize.seed)));
+        self
+    }
+
+    /// Varies a note that's about to start, per `with_humanize`.
+    fn humanized(&mut self, mut playing: PlayingNote) -> PlayingNote {
+        if let Some((humanize, rng)) = &mut self.humanize {
+            if !playing.note.is_rest() {
+                playing.start_tick_id += rng.gen_range(0..=humanize.max_delay_ticks);
+                let amount = humanize.velocity as i16;
+                let velocity = playing.note.velocity as i16 + rng.gen_range(-amount..=amount);
+                playing.note = playing.note.set_velocity(velocity.clamp(1, 127) as u8);
+            }
+        }
+        playing
+    }
+
+    /// Delays the notes of each of `channel_id`'s steps by `groove`. See `GrooveTemplate`.
+    pub fn with_groove(mut self, channel_id: usize, groove: GrooveTemplate) -> Self {
+        self.groove.insert(channel_id, (groove, 0));
+        self
+    }
+
+    /// The groove delay of the step `channel_id` is being polled for, moving on to its next step.
+    fn next_groove_offset(&mut self, channel_id: usize) -> u64 {
+        match self.groove.get_mut(&channel_id) {
+            Some((groove, step)) => {
+                let offset = groove.offset(*step);
+                *step += 1;
+                offset
+            }
+            None => 0,
+        }
+    }
+
+    /// Starts the delayed notes that are due on the current tick.
+    fn start_delayed_notes(&mut self) -> Vec<PlayingNote> {
+        let tick = self.tick_id;
+        let (due, waiting) = std::mem::take(&mut self.delayed_notes).into_iter()
+            .partition(|playing| playing.start_tick_id <= tick);
+        self.delayed_notes = waiting;
+        for playing in &due {
+            self.start_note(*playing);
+        }
+        due
+    }
+
+    /// Tracks a note that starts now so that it can be stopped after the number of ticks
+    /// equaling its duration have elapsed.
+    fn start_note(&mut self, playing: PlayingNote) {
+        self.note_id += 1;
+        self.playing_notes.insert(self.note_id, playing);
+        if !playing.note.is_rest() {
+            self.record(TraceEvent::NoteOn {
+                tick: self.tick_id,
+                channel_id: playing.channel_id,
+                note: playing.note,
+            });
+        }
+    }
+
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Records the player's internal events to `trace` as it runs.
+    pub fn with_trace(mut self, trace: Trace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    fn record(&self, event: TraceEvent) {
+        if let Some(trace) = &self.trace {
+            trace.record(event);
+        }
+    }
+
+    fn record_note_offs(&self, notes: &[PlayingNote]) {
+        for playing in notes.iter().filter(|playing| !playing.note.is_rest()) {
+            self.record(TraceEvent::NoteOff {
+                tick: self.tick_id,
+                channel_id: playing.channel_id,
+                note: playing.note,
+            });
+        }
+    }
+
+    /// Holds off polling any channel for the first `ticks` ticks, so the channels start together
+    /// once the count-in is over.
+    pub fn with_count_in(mut self, ticks: u64) -> Self {
+        self.count_in_ticks = ticks;
+        self
+    }
+
+    /// Force-releases any note that has been sounding for `max_note_ticks` ticks, as a safeguard
+    /// against notes left hanging.
+    pub fn with_watchdog(mut self, max_note_ticks: u64) -> Self {
+        self.max_note_ticks = Some(max_note_ticks.max(1));
+        self
+    }
+
     /// Increment and return the tick_id, after sleeping for the required duration.
     /// Meter describes the tempo that the player should use during playback.
     pub fn do_tick(&mut self, meter: &dyn Meter) -> u64 {
+        sleep(self.tick_duration(meter));
+        self.advance()
+    }
+
+    /// Moves on to the next tick straight away, for stepping through playback without waiting
+    /// (see `step`).
+    pub fn advance(&mut self) -> u64 {
         self.tick_id += 1;
-        sleep(meter.tick_duration());
+        self.record(TraceEvent::Tick { tick: self.tick_id });
         self.tick_id
     }
 
+    /// The real-time length of the current tick: the meter's, unless a tempo change has been
+    /// polled.
+    pub fn tick_duration(&self, meter: &dyn Meter) -> Duration {
+        match self.tempo {
+            Some(bpm) => Duration::from_secs_f64(60.0 / bpm),
+            None => meter.tick_duration(self.tick_id),
+        }
+    }
+
+    fn apply_control(&mut self, control: Control) {
+        match control {
+            Control::TempoChange(bpm) => {
+                if bpm > 0.0 {
+                    self.tempo = Some(bpm);
+                } else {
+                    error!("Ignoring tempo change to {} bpm", bpm);
+                }
+            }
+            // sent by whoever routes the started notes
+            Control::ControlChange { .. } | Control::PitchBend(_) | Control::ProgramChange(_) => {}
+        }
+    }
+
     /// Gets the current time in ticks since start
     pub fn time(&self) -> u64 {
         self.tick_id
     }
 
+    /// The musical position of the current tick in `signature`, counting from the first bar
+    /// after the count-in, or `None` during the count-in.
+    pub fn position(&self, signature: &TimeSignature) -> Option<Position> {
+        self.tick_id.checked_sub(self.count_in_ticks).map(|ticks| signature.position(ticks))
+    }
+
     /// Determines whether we need to poll the channel for new notes in the sequence
     /// Each channel may send a set of notes to the player -- but cannot send any more notes until
-    /// those are done playing. So check that there are no active notes for the channel.
+    /// the step they make up is over, which is when the longest of them is done playing.
     fn should_poll_channel(&self, channel_id: usize) -> bool {
-        self.playing_notes.values()
-            .filter(|v| v.channel_id == channel_id)
-            .count() == 0
+        if !self.has_free_voice(channel_id) {
+            return false;
+        }
+        match self.sync_mode {
+            SyncMode::Independent => {
+                self.tick_id >= self.next_poll_tick.get(&channel_id).copied().unwrap_or(self.count_in_ticks)
+            }
+            SyncMode::Locked(step_ticks) => {
+                self.tick_id >= self.count_in_ticks
+                    && (self.tick_id - self.count_in_ticks).is_multiple_of(step_ticks.max(1))
+            }
+        }
     }
 
-    /// TODO: Testing for multiple notes of different durations.
+    /// Polls each channel that is due for its next step and returns the notes that start now.
+    ///
+    /// A note that continues a held tie (see `Midi::tie`) is tracked but not returned, since it
+    /// is already sounding. Call `release_held_ties` afterwards to release any ties that weren't
+    /// continued, and `release_restarted_notes` for the notes legato channels played again.
+    /// Notes delayed by humanizing (see `with_humanize`) are returned on the tick they
+    /// start.
+    ///
     /// TODO: Sparse channel representations since snapshots of Player should be immutable.
     pub fn poll_channels(
         &mut self,
         channels: &mut [Box<dyn Midibox>]
     ) -> Vec<PlayingNote> {
+        let mut started = self.start_delayed_notes();
         for (channel_id, channel) in channels.iter_mut().enumerate() {
             if !self.should_poll_channel(channel_id) {
                 continue;
             }
+            if channel.is_finished() {
+                if self.finished.insert(channel_id) {
+                    info!("Channel {} finished", channel_id);
+                }
+                continue;
+            }
 
-            match channel.next() {
-                Some(notes) => {
-                    debug!("Channel {} sent notes {:?}", channel_id, notes);
-                    for note in notes {
-                        self.note_id += 1;
-                        let note_id = self.note_id;
-                        if note.duration == 0 {
-                            continue; // ignore zero-duration notes
+            // A zero-duration step made only of control events takes no time, so the channel's
+            // next step is polled straight away and starts on this same tick.
+            for _ in 0..=MAX_CONTROL_STEPS_PER_TICK {
+                self.record(TraceEvent::Poll { tick: self.tick_id, channel_id });
+                let notes = match channel.next() {
+                    Some(notes) => notes,
+                    None => {
+                        error!("No input from channel {}", channel_id);
+                        break;
+                    }
+                };
+                debug!("Channel {} sent notes {:?}", channel_id, notes);
+                let step_ticks = if self.legato.contains_key(&channel_id) {
+                    notes.iter().map(|n| n.duration).filter(|d| *d > 0).min().unwrap_or(0)
+                } else {
+                    notes.iter().map(|n| n.duration).max().unwrap_or(0)
+                };
+                let controls_only = !notes.is_empty() && notes.iter().all(|n| n.control.is_some());
+                let groove_ticks = if step_ticks == 0 && controls_only {
+                    0
+                } else {
+                    self.next_groove_offset(channel_id)
+                };
+                self.next_poll_tick.insert(channel_id, self.tick_id + step_ticks as u64);
+                for note in notes {
+                    let note = self.transposed(channel_id, note);
+                    if let Some(control) = note.control {
+                        self.apply_control(control);
+                        if let Control::PitchBend(offset) = control {
+                            let bent = (channel_id, note.channel);
+                            if offset == 0 {
+                                self.bent_channels.remove(&bent);
+                            } else {
+                                self.bent_channels.insert(bent);
+                            }
+                        }
+                        if note.message(NOTE_ON_MSG).is_none() {
+                            continue;
+                        }
+                    }
+                    if note.duration == 0 {
+                        if note.control.is_some() {
+                            // sent straight away, with nothing to release
+                            started.push(PlayingNote { channel_id, start_tick_id: self.tick_id, note });
                         }
-                        // track the note we're about to play so that we can stop it after the
-                        // number of ticks equaling the note's duration have elapsed.
-                        self.playing_notes.insert(note_id, PlayingNote {
-                            channel_id,
-                            start_tick_id: self.tick_id,
-                            note,
+                        continue; // ignore zero-duration notes
+                    }
+                    if self.muted.contains(&channel_id) && note.control.is_none() {
+                        continue;
+                    }
+                    let playing = PlayingNote {
+                        channel_id,
+                        start_tick_id: self.tick_id,
+                        note,
+                    };
+                    if self.continue_held_tie(&playing) {
+                        // already sounding, so only tracked
+                        self.note_id += 1;
+                        self.playing_notes.insert(self.note_id, playing);
+                        continue;
+                    }
+                    if self.legato.contains_key(&channel_id) && !note.is_rest() {
+                        // restarted below, so the earlier note's end mustn't cut it off
+                        let restarted = self.clear_notes(|sounding| {
+                            sounding.channel_id == channel_id && sounding.note.u8_maybe() == note.u8_maybe()
                         });
+                        self.record_note_offs(&restarted);
+                        self.restarted_notes.extend(restarted);
+                    }
+                    let mut playing = if note.control.is_none() { self.humanized(playing) } else { playing };
+                    if note.control.is_none() && !note.is_rest() {
+                        playing.start_tick_id += groove_ticks;
+                    }
+                    if playing.start_tick_id > self.tick_id {
+                        self.delayed_notes.push(playing);
+                    } else {
+                        self.start_note(playing);
+                        started.push(playing);
                     }
                 }
-                None => {
-                    error!("No input from channel {}", channel_id);
+                if step_ticks > 0 || !controls_only {
+                    break;
                 }
             }
         }
 
-        let mut notes: Vec<PlayingNote> = Vec::new();
-        notes.extend(
-            self.playing_notes
-                .values()
-                .filter(|note| note.start_tick_id == self.tick_id)
-        );
-        notes
+        started
+    }
+
+    /// Removes a held tie of the same pitch on the same channel as `playing`, if there is one.
+    fn continue_held_tie(&mut self, playing: &PlayingNote) -> bool {
+        if playing.note.is_rest() {
+            return false;
+        }
+        let position = self.held_ties.iter().position(|held| {
+            held.channel_id == playing.channel_id && held.note.u8_maybe() == playing.note.u8_maybe()
+        });
+        position.map(|i| self.held_ties.remove(i)).is_some()
+    }
+
+    /// Releases the tied notes that the steps polled this tick didn't continue.
+    pub fn release_held_ties(&mut self) -> Vec<PlayingNote> {
+        let released = std::mem::take(&mut self.held_ties);
+        self.record_note_offs(&released);
+        released
+    }
+
+    /// Releases the notes that legato channels cut short during the last poll to play their pitch
+    /// again, which must be sent before the notes that restart them.
+    pub fn release_restarted_notes(&mut self) -> Vec<PlayingNote> {
+        std::mem::take(&mut self.restarted_notes)
     }
 
+    /// Releases the notes whose duration has elapsed, including any whose end was skipped over.
+    /// Tied notes are held instead, until the next poll decides whether they continue.
+    ///
+    /// With a watchdog (see `with_watchdog`), notes that have sounded for too long are released
+    /// too, and aren't held even if tied.
     pub fn clear_elapsed_notes(&mut self) -> Vec<PlayingNote> {
         let current_tick = self.tick_id;
-        self.clear_notes(|note| {
-            note.start_tick_id + (note.note.duration as u64) == current_tick
-        })
+        let max_note_ticks = self.max_note_ticks;
+        let is_stuck = move |note: &PlayingNote| {
+            max_note_ticks.is_some_and(|max| current_tick - note.start_tick_id >= max)
+        };
+        let elapsed = self.clear_notes(|note| {
+            note.start_tick_id + (note.note.duration as u64) <= current_tick || is_stuck(note)
+        });
+        for stuck in elapsed.iter().filter(|playing| !playing.note.is_rest() && is_stuck(playing)) {
+            error!("Watchdog releasing stuck note {:?} on channel {}", stuck.note, stuck.channel_id);
+        }
+        let (held, released): (Vec<PlayingNote>, Vec<PlayingNote>) = elapsed.into_iter()
+            .partition(|playing| playing.note.tie && !playing.note.is_rest() && !is_stuck(playing));
+        self.held_ties.extend(held);
+        self.record_note_offs(&released);
+        released
+    }
+
+    /// Works out everything that happens on the current tick without waiting or sending
+    /// anything: releases the notes that have ended, polls the channels that are due, and then
+    /// releases the held ties their steps didn't continue and the notes they restarted. Send the
+    /// note-offs before the note-ons and call `advance` to move on, e.g. to test a sequence or
+    /// render it faster than real time.
+    pub fn step(&mut self, channels: &mut [Box<dyn Midibox>]) -> TickEvents {
+        let mut note_offs = self.clear_elapsed_notes();
+        let note_ons = self.poll_channels(channels);
+        note_offs.extend(self.release_restarted_notes());
+        note_offs.extend(self.release_held_ties());
+        TickEvents { note_offs, note_ons }
     }
 
+    /// Whether every one of `channels` has finished (see `Midibox::is_finished`) and all of their
+    /// notes, including rests, have played out.
+    pub fn has_finished(&self, channels: &[Box<dyn Midibox>]) -> bool {
+        (0..channels.len()).all(|channel_id| self.finished.contains(&channel_id))
+            && self.playing_notes.is_empty()
+            && self.held_ties.is_empty()
+            && self.delayed_notes.is_empty()
+    }
+
+    /// The notes currently sounding, including tied notes being held, ordered by channel and
+    /// start. Rests and controls aren't included.
+    pub fn sounding_notes(&self) -> Vec<PlayingNote> {
+        let mut notes: Vec<PlayingNote> = self.playing_notes.values()
+            .chain(self.held_ties.iter())
+            .filter(|playing| !playing.note.is_rest())
+            .copied()
+            .collect();
+        notes.sort_by_key(|playing| (playing.channel_id, playing.start_tick_id, playing.note.u8_maybe()));
+        notes
+    }
+
+    /// Releases every sounding note, and drops any delayed notes that haven't started yet.
     pub fn clear_all_notes(&mut self) -> Vec<PlayingNote> {
-        self.clear_notes(|_| true)
+        self.delayed_notes.clear();
+        let mut notes = self.release_restarted_notes();
+        notes.extend(self.release_held_ties());
+        let cleared = self.clear_notes(|_| true);
+        self.record_note_offs(&cleared);
+        notes.extend(cleared);
+        notes
+    }
+
+    /// Pitch bends back to centre for every channel left bent, to be sent (as starting notes)
+    /// when playback stops so that later notes on those MIDI channels aren't out of tune.
+    pub fn reset_pitch_bends(&mut self) -> Vec<PlayingNote> {
+        let tick = self.tick_id;
+        std::mem::take(&mut self.bent_channels).into_iter()
+            .map(|(channel_id, channel)| PlayingNote {
+                channel_id,
+                start_tick_id: tick,
+                note: Midi { channel, ..Midi::control(Control::PitchBend(0)) },
+            })
+            .collect()
     }
 
     fn clear_notes<F>(&mut self, should_clear: F) -> Vec<PlayingNote> where
@@ -131,6 +804,63 @@ impl Player {
     }
 }
 
+impl Player {
+    /// Plays `channels` for `ticks` ticks as fast as possible, without any MIDI output, and
+    /// returns what was played as a Type-1 Standard MIDI File with a track per channel after the
+    /// tempo track.
+    ///
+    /// The file's tempo is the meter's, and every tick's length is measured against it, so swing
+    /// and tempo changes are kept in the timing. Notes still sounding at the cutoff are released
+    /// there.
+    pub fn render_to_smf(
+        channels: &mut Vec<Box<dyn Midibox>>,
+        meter: &dyn Meter,
+        ticks: u64
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let beat_ticks = smf::TICKS_PER_BEAT as f64;
+        let base_tick = meter::beat_duration(meter).as_secs_f64() / beat_ticks;
+        if base_tick <= 0.0 {
+            return Err("The meter's tick duration must be positive".into());
+        }
+        // file ticks per crate tick at the base tempo
+        let resolution = SMF_DIVISION as f64 / beat_ticks;
+
+        let mut player = Player::new();
+        let mut tracks: Vec<Vec<(u64, Vec<u8>)>> = vec![Vec::new(); channels.len()];
+        let mut time = 0.0;
+        let mut record = |notes: Vec<PlayingNote>, status: u8, time: f64| {
+            for playing in notes {
+                if let Some(message) = playing.note.message(status) {
+                    tracks[playing.channel_id].push((time.round() as u64, message));
+                }
+            }
+        };
+        while player.time() < ticks {
+            let events = player.step(channels);
+            record(events.note_offs, NOTE_OFF_MSG, time);
+            record(events.note_ons, NOTE_ON_MSG, time);
+            time += player.tick_duration(meter).as_secs_f64() / base_tick * resolution;
+            player.advance();
+        }
+        record(player.clear_all_notes(), NOTE_OFF_MSG, time);
+        record(player.reset_pitch_bends(), NOTE_ON_MSG, time);
+
+        let micros_per_beat = (base_tick * beat_ticks * 1_000_000.0).round() as u32;
+        Ok(smf::write(SMF_DIVISION, micros_per_beat, &tracks, time.round() as u64))
+    }
+
+    /// Like `render_to_smf`, writing the file to `path`.
+    pub fn write_smf(
+        channels: &mut Vec<Box<dyn Midibox>>,
+        meter: &dyn Meter,
+        ticks: u64,
+        path: impl AsRef<Path>
+    ) -> Result<(), Box<dyn Error>> {
+        fs::write(path, Player::render_to_smf(channels, meter, ticks)?)?;
+        Ok(())
+    }
+}
+
 impl Default for Player {
     fn default() -> Self {
         Self::new()
@@ -138,36 +868,231 @@ impl Default for Player {
 }
 
 pub struct PlayerConfig {
-    router: Box<dyn Router>
+    router: Box<dyn Router>,
+    /// Wall-clock time after which the player stops itself, regardless of the running flag.
+    max_runtime: Option<Duration>,
+    /// The tick at which the player stops itself, regardless of the running flag.
+    max_ticks: Option<u64>,
+    /// Ticks to let pass before the channels start playing.
+    count_in_ticks: u64,
+    /// Where the player records its internal events, if tracing is enabled.
+    trace: Option<Trace>,
+    /// The velocity curve for each port that has one.
+    velocity_curves: HashMap<usize, VelocityCurve>,
+    /// The longest a note may sound before the player releases it regardless.
+    max_note_ticks: Option<u64>,
+    /// When the player polls the channels.
+    sync_mode: SyncMode,
+    /// What to do when a port can't be sent to.
+    error_policy: ErrorPolicy,
+    /// Pauses, resumes or stops the run alongside the running flag, if set.
+    transport: Option<Transport>,
+    /// How the started notes are varied, if at all.
+    humanize: Option<Humanize>,
+    /// The channels in legato mode, with the most notes each may have sounding at once.
+    legato: HashMap<usize, usize>,
+    /// The ports sent MIDI clock and start and stop messages.
+    clock_ports: Vec<usize>,
+    /// The MIDI channel each channel's notes are sent on when they don't set their own.
+    midi_channels: HashMap<usize, u8>,
+    /// The metronome played during the count-in, if any.
+    click: Option<Click>,
+    /// The semitones each transposed channel starts out shifted by.
+    transpose: HashMap<usize, i8>,
+    /// The groove template of each channel that has one.
+    groove: HashMap<usize, GrooveTemplate>,
+}
+
+/// A metronome note sent straight to a port every so many ticks of the count-in.
+#[derive(Debug, Clone, Copy)]
+struct Click {
+    port_id: usize,
+    note: Midi,
+    every_ticks: u64,
 }
 
 impl PlayerConfig {
     pub fn empty() -> Self {
-        PlayerConfig {
-            router: Box::new(StaticRouter::new(0))
-        }
+        Self::from_router(Box::new(StaticRouter::new(0)))
     }
 
     pub fn for_port(port_id: usize) -> Self {
-        PlayerConfig {
-            router: Box::new(StaticRouter::new(port_id))
-        }
+        Self::from_router(Box::new(StaticRouter::new(port_id)))
+    }
+
+    /// Sends every channel to the output port called `name`, looked up when playback starts so
+    /// that the config still works once the port's index has changed. See `NameRouter`.
+    pub fn for_port_name(name: &str) -> Self {
+        Self::for_port_name_matching(name, NameMatch::Exact)
+    }
+
+    /// Like `for_port_name`, matching `name` against the output ports as `matching` says, e.g.
+    /// with `NameMatch::Substring` to find a port by part of its name.
+    pub fn for_port_name_matching(name: &str, matching: NameMatch) -> Self {
+        Self::from_router(Box::new(NameRouter::for_all(name).with_matching(matching)))
     }
 
     pub fn from_router(router: Box<dyn Router>) -> Self {
         PlayerConfig {
-            router
+            router,
+            max_runtime: None,
+            max_ticks: None,
+            count_in_ticks: 0,
+            trace: None,
+            velocity_curves: HashMap::new(),
+            max_note_ticks: None,
+            sync_mode: SyncMode::Independent,
+            error_policy: ErrorPolicy::Abort,
+            transport: None,
+            humanize: None,
+            legato: HashMap::new(),
+            clock_ports: Vec::new(),
+            midi_channels: HashMap::new(),
+            click: None,
+            transpose: HashMap::new(),
+            groove: HashMap::new(),
         }
     }
+
+    /// Stops playback once `max_runtime` has elapsed since the player started, as if the running
+    /// flag had been cleared. Sounding notes are released as usual.
+    pub fn with_max_runtime(mut self, max_runtime: Duration) -> Self {
+        self.max_runtime = Some(max_runtime);
+        self
+    }
+
+    /// Stops the player once it has played for `ticks` ticks, counting any count-in.
+    pub fn with_max_ticks(mut self, ticks: u64) -> Self {
+        self.max_ticks = Some(ticks);
+        self
+    }
+
+    /// Counts in for `bars` bars of `bar_ticks` ticks each before the channels start, so that
+    /// recordings line up with a known downbeat. Time advances during the count-in but nothing is
+    /// played.
+    pub fn with_count_in(mut self, bars: u32, bar_ticks: u32) -> Self {
+        self.count_in_ticks = bars as u64 * bar_ticks as u64;
+        self
+    }
+
+    /// Plays `note` on `port_id` every `every_ticks` ticks of the count-in (see `with_count_in`),
+    /// starting on its first tick, as a metronome to play along to. The note is sent on its own
+    /// MIDI channel and sounds for its duration, up to the next click. The channels start on the
+    /// next beat after the last click, exactly where they would without one.
+    pub fn with_count_in_click(mut self, port_id: usize, note: Midi, every_ticks: u32) -> Self {
+        self.click = Some(Click { port_id, note, every_ticks: every_ticks.max(1) as u64 });
+        self
+    }
+
+    /// Records an ordered trace of the player's internal events (polls, note-ons, note-offs and
+    /// ticks) during the run. Keep a handle from `trace` to read the events afterwards.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Trace::new());
+        self
+    }
+
+    /// Applies `curve` to the velocity of every note-on sent to `port_id`, to calibrate for how
+    /// that port's instrument responds to velocity. Ports without a curve get velocities as-is.
+    pub fn with_velocity_curve(mut self, port_id: usize, curve: VelocityCurve) -> Self {
+        self.velocity_curves.insert(port_id, curve);
+        self
+    }
+
+    /// Releases any note that has sounded for `max_note_ticks` ticks, even if its duration says
+    /// it should still be playing. See `Player::with_watchdog`.
+    pub fn with_watchdog(mut self, max_note_ticks: u64) -> Self {
+        self.max_note_ticks = Some(max_note_ticks);
+        self
+    }
+
+    /// Chooses whether channels are polled independently or locked to a common grid.
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Chooses what happens when a message can't be delivered to a port. Channels without a
+    /// route are always logged and skipped.
+    pub fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Lets the run be paused and resumed through `transport`, which can also stop it. While
+    /// paused the player doesn't advance and its sounding notes are switched off; they are
+    /// switched back on once, for the rest of their duration, when playback resumes.
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Delays notes and varies their velocity at random within the bounds of `humanize`. See
+    /// `Humanize`.
+    pub fn with_humanize(mut self, humanize: Humanize) -> Self {
+        self.humanize = Some(humanize);
+        self
+    }
+
+    /// Lets a channel start new steps while its longer notes are still sounding, with at most
+    /// `max_voices` notes at once. See `Player::with_legato`.
+    pub fn with_legato(mut self, channel_id: usize, max_voices: usize) -> Self {
+        self.legato.insert(channel_id, max_voices);
+        self
+    }
+
+    /// Delays the notes of particular steps of a channel. See `GrooveTemplate`.
+    pub fn with_groove(mut self, channel_id: usize, groove: GrooveTemplate) -> Self {
+        self.groove.insert(channel_id, groove);
+        self
+    }
+
+    /// Makes the player the clock master for `ports`: they are sent a start message when playback
+    /// starts, MIDI clock at 24 pulses per quarter note in time with the meter, and stop and
+    /// continue messages when playback stops, pauses and resumes.
+    pub fn with_clock_out(mut self, ports: Vec<usize>) -> Self {
+        self.clock_ports = ports;
+        self
+    }
+
+    /// Sends the notes of `channel_id` on MIDI channel `midi_channel` (0-15, clamped), so that
+    /// several channels can address the parts of a multitimbral synth on a single port. Notes
+    /// that set their own MIDI channel keep it, and other channels are sent on channel 0.
+    pub fn with_midi_channel(mut self, channel_id: usize, midi_channel: u8) -> Self {
+        self.midi_channels.insert(channel_id, midi_channel.min(15));
+        self
+    }
+
+    /// Shifts the notes of `channel_id` by `semitones` from the start. Use
+    /// `Transport::transpose` to change it during playback. See `Player::set_transpose`.
+    pub fn with_transpose(mut self, channel_id: usize, semitones: i8) -> Self {
+        self.transpose.insert(channel_id, semitones);
+        self
+    }
+
+    /// The trace the run will be recorded to, when `with_trace` is set.
+    pub fn trace(&self) -> Option<Trace> {
+        self.trace.clone()
+    }
 }
 
 impl Router for PlayerConfig {
-    fn route(&self, channel_id: usize) -> Option<&usize> {
+    fn route(&self, channel_id: usize) -> Option<usize> {
         self.router.route(channel_id)
     }
 
+    fn route_note(&self, channel_id: usize, note: &Midi, midi_status: u8) -> Option<usize> {
+        self.router.route_note(channel_id, note, midi_status)
+    }
+
     fn required_ports(&self) -> HashSet<usize> {
-        self.router.required_ports()
+        let mut ports = self.router.required_ports();
+        ports.extend(&self.clock_ports);
+        ports.extend(self.click.map(|click| click.port_id));
+        ports
+    }
+
+    fn resolve_ports(&mut self, port_names: &[String]) -> Result<(), PortNameError> {
+        self.router.resolve_ports(port_names)
     }
 }
 
@@ -186,82 +1111,1506 @@ pub fn try_run(
         ctrlc_running.lock().unwrap().insert(name.to_string(), false);
     })?;
 
-    return try_run_ext(name, player_config, bpm, channels, &running);
+    try_run_ext(name, player_config, bpm, channels, &running)
+}
+
+/// Plays the channels like `try_run_ext` for `ticks` ticks, then releases every sounding note and
+/// returns. No Ctrl-C handler is installed, so this can be called more than once per process.
+pub fn try_run_for(
+    player_config: PlayerConfig,
+    meter: &dyn Meter,
+    channels: &mut Vec<Box<dyn Midibox>>,
+    ticks: u64
+) -> Result<(), Box<dyn Error>> {
+    let name = "Midibox";
+    let running = Arc::new(Mutex::new(HashMap::from([(name.to_string(), true)])));
+    try_run_ext(name, player_config.with_max_ticks(ticks), meter, channels, &running)
+}
+
+/// Like `try_run_for`, playing `bars` bars in a time signature of `beats_per_bar` beats of
+/// `beat_unit` notes each, e.g. 6 and 8 for 6/8. A tick is a sixteenth note, so `beat_unit` is
+/// one of 1, 2, 4, 8 or 16.
+pub fn try_run_for_bars(
+    player_config: PlayerConfig,
+    meter: &dyn Meter,
+    channels: &mut Vec<Box<dyn Midibox>>,
+    bars: u64,
+    beats_per_bar: u32,
+    beat_unit: u32
+) -> Result<(), Box<dyn Error>> {
+    try_run_for(player_config, meter, channels, bars * bar_ticks(beats_per_bar, beat_unit)?)
+}
+
+/// The length in ticks of a bar in the given time signature.
+fn bar_ticks(beats_per_bar: u32, beat_unit: u32) -> Result<u64, String> {
+    TimeSignature::new(beats_per_bar, beat_unit).map(|signature| signature.bar_ticks())
+}
+
+/// The MIDI output ports available now, as the index that `PlayerConfig::for_port` and the
+/// routers take, paired with the port's name. No port is connected to.
+pub fn list_output_ports() -> Result<Vec<(usize, String)>, Box<dyn Error>> {
+    let midi_out = MidiOutput::new("midibox port listing")?;
+    let mut ports = Vec::new();
+    for (i, port) in midi_out.ports().iter().enumerate() {
+        ports.push((i, midi_out.port_name(port)?));
+    }
+    Ok(ports)
 }
 
 pub fn try_run_ext(
     name: &str,
-    player_config: PlayerConfig,
+    mut player_config: PlayerConfig,
     bpm: &dyn Meter,
     channels: &mut Vec<Box<dyn Midibox>>,
     running: &Arc<Mutex<HashMap<String, bool>>>
 ) -> Result<(), Box<dyn Error>> {
-    let midi_out = MidiOutput::new("Midi Outputs")?;
-    let out_ports = midi_out.ports();
+    let port_names: Vec<String> = list_output_ports()?.into_iter().map(|(_, name)| name).collect();
+    player_config.resolve_ports(&port_names)?;
+    let mut sink = MidirSink::connect(&player_config.required_ports())?;
+    try_run_with_sink(name, player_config, bpm, channels, running, &mut sink)
+}
+
+/// Runs the player loop like `try_run_ext`, sending all messages to `sink` rather than opening
+/// MIDI output ports.
+pub fn try_run_with_sink(
+    name: &str,
+    player_config: PlayerConfig,
+    bpm: &dyn Meter,
+    channels: &mut Vec<Box<dyn Midibox>>,
+    running: &Arc<Mutex<HashMap<String, bool>>>,
+    sink: &mut dyn MidiSink
+) -> Result<(), Box<dyn Error>> {
+    let mut player = Player::new().with_count_in(player_config.count_in_ticks);
+    if let Some(trace) = player_config.trace() {
+        player = player.with_trace(trace);
+    }
+    if let Some(max_note_ticks) = player_config.max_note_ticks {
+        player = player.with_watchdog(max_note_ticks);
+    }
+    if let Some(humanize) = player_config.humanize {
+        player = player.with_humanize(humanize);
+    }
+    for (channel_id, max_voices) in &player_config.legato {
+        player = player.with_legato(*channel_id, *max_voices);
+    }
+    for (channel_id, semitones) in &player_config.transpose {
+        player.set_transpose(*channel_id, *semitones);
+    }
+    for (channel_id, groove) in &player_config.groove {
+        player = player.with_groove(*channel_id, groove.clone());
+    }
+    player = player.with_sync_mode(player_config.sync_mode);
+    let started = Instant::now();
+    let mut output = Output {
+        config: &player_config,
+        sink,
+        skipped_ports: HashSet::new(),
+        policy: player_config.error_policy,
+        click_sounding: false,
+        voices: HashMap::new(),
+    };
+    let mut aborted = output.send_clock(START_MSG).err();
+    let mut paused = false;
 
-    for (i, p) in out_ports.iter().enumerate() {
-        info!("{}: {}", i, midi_out.port_name(p).unwrap());
+    info!("Player Starting.");
+    while aborted.is_none() && *running.lock().unwrap().get(name).unwrap() {
+        let state = player_config.transport.as_ref().map_or(TransportState::Playing, |t| t.state());
+        if state == TransportState::Stopped {
+            info!("Player stopped by its transport.");
+            running.lock().unwrap().insert(name.to_string(), false);
+            break;
+        }
+        let mut routed = Ok(());
+        if let Some(transport) = &player_config.transport {
+            for (channel_id, semitones) in transport.take_transpositions() {
+                player.set_transpose(channel_id, semitones);
+            }
+            let muted = player.set_muted(transport.muted_channels(channels.len()));
+            routed = output.route(&muted, NOTE_OFF_MSG);
+        }
+        if player_config.transport.as_ref().is_some_and(|t| t.take_panic()) {
+            info!("Player panicking at tick {}.", player.time());
+            routed = routed
+                .and_then(|_| output.route(&player.clear_all_notes(), NOTE_OFF_MSG))
+                .and_then(|_| output.all_notes_off());
+        }
+        if state == TransportState::Paused {
+            if !paused {
+                info!("Player paused at tick {}.", player.time());
+                paused = true;
+                routed = routed
+                    .and_then(|_| output.route(&player.sounding_notes(), NOTE_OFF_MSG))
+                    .and_then(|_| output.send_clock(STOP_MSG));
+            }
+            sleep(player.tick_duration(bpm));
+        } else {
+            if paused {
+                info!("Player resuming at tick {}.", player.time());
+                paused = false;
+                routed = routed
+                    .and_then(|_| output.send_clock(CONTINUE_MSG))
+                    .and_then(|_| output.route(&player.sounding_notes(), NOTE_ON_MSG));
+            }
+            debug!("Time: {}", player.time());
+            let events = player.step(channels);
+            routed = routed
+                .and_then(|_| output.click(Some(player.time())))
+                .and_then(|_| output.route(&events.note_offs, NOTE_OFF_MSG))
+                .and_then(|_| output.route(&events.note_ons, NOTE_ON_MSG));
+        }
+        if routed.is_ok() && !paused {
+            routed = output.tick(&mut player, bpm)
+                .and_then(|_| output.route(&player.clear_elapsed_notes(), NOTE_OFF_MSG));
+        }
+        if let Err(err) = routed {
+            error!("Player stopping: {}", err);
+            aborted = Some(err);
+            break;
+        }
+        if player_config.max_runtime.is_some_and(|max| started.elapsed() >= max) {
+            info!("Player reached its maximum runtime.");
+            running.lock().unwrap().insert(name.to_string(), false);
+        }
+        if player_config.max_ticks.is_some_and(|max| player.time() >= max) {
+            info!("Player reached tick {}.", player.time());
+            running.lock().unwrap().insert(name.to_string(), false);
+        }
+        let bounded = player_config.max_ticks.is_some() || player_config.max_runtime.is_some();
+        if bounded && player.has_finished(channels) {
+            info!("Every channel finished by tick {}.", player.time());
+            running.lock().unwrap().insert(name.to_string(), false);
+        }
+    }
+    if aborted.is_some() {
+        // release everything that can still be reached
+        output.policy = ErrorPolicy::SkipPort;
+    }
+    let released = output.click(None)
+        .and_then(|_| output.route(&player.clear_all_notes(), NOTE_OFF_MSG))
+        .and_then(|_| output.release_voices())
+        .and_then(|_| output.route(&player.reset_pitch_bends(), NOTE_ON_MSG))
+        .and_then(|_| if paused { Ok(()) } else { output.send_clock(STOP_MSG) });
+    info!("Player Exiting.");
+    match aborted {
+        Some(err) => Err(Box::new(err)),
+        None => released.map_err(|err| err.into()),
     }
+}
 
-    let required_ports = player_config.required_ports();
-    let mut port_id_to_conn: HashMap<usize, MidiOutputConnection> =
-        HashMap::with_capacity(required_ports.len());
+/// Sends the player's notes to a sink, applying an error policy.
+struct Output<'a> {
+    config: &'a PlayerConfig,
+    sink: &'a mut dyn MidiSink,
+    /// Ports that failed under `ErrorPolicy::SkipPort` and are no longer sent to.
+    skipped_ports: HashSet<usize>,
+    policy: ErrorPolicy,
+    /// Whether the count-in click has been switched on and not yet off.
+    click_sounding: bool,
+    /// How many notes are sounding on each port, MIDI channel and pitch. Channels layering the
+    /// same pitch would otherwise have the first NOTE_OFF cut the notes still held, so only the
+    /// last one to end is sent.
+    voices: HashMap<(usize, u8, u8), usize>,
+}
 
-    for i in 0..out_ports.len() {
-        let port = out_ports.get(i).expect("Missing midi port");
-        let port_name = format!("midibox {}", i);
-        let output = MidiOutput::new(&port_name)?;
+impl Output<'_> {
+    fn route(&mut self, notes: &[PlayingNote], midi_status: u8) -> Result<(), PlaybackError> {
+        for note in notes {
+            if note.note.message(midi_status).is_none() {
+                continue; // resting, or a control event with nothing to send
+            }
+            let port_id = match self.config.route_note(note.channel_id, &note.note, midi_status) {
+                None => Err(PlaybackError::NoRoute { channel_id: note.channel_id }),
+                Some(port_id) if self.skipped_ports.contains(&port_id) => continue,
+                Some(port_id) => Ok(port_id),
+            };
+            let sent = port_id.and_then(|port_id| {
+                match note_message(self.config, port_id, note, midi_status) {
+                    Some(message) if self.is_last_voice(port_id, note, &message) => {
+                        self.sink.send(port_id, &message)
+                    }
+                    _ => Ok(()),
+                }
+            });
+            self.check(sent)?;
+        }
+        Ok(())
+    }
 
-        if required_ports.contains(&i) {
-            let conn = output.connect(port, &port_name)?;
-            port_id_to_conn.insert(i, conn);
+    /// Counts a note switching on or off in `voices`, returning whether its message should be
+    /// sent: always for a NOTE_ON, and for a NOTE_OFF only once no other note sounding the
+    /// same pitch is left.
+    fn is_last_voice(&mut self, port_id: usize, note: &PlayingNote, message: &[u8]) -> bool {
+        if note.note.control.is_some() {
+            return true;
+        }
+        let key = (port_id, message[0] & 0x0F, message[1]);
+        if message[0] & 0xF0 == NOTE_ON_MSG {
+            let count = self.voices.entry(key).or_default();
+            *count += 1;
+            if *count > 1 {
+                warn!(
+                    "Channel {} sounds pitch {} on MIDI channel {} of port {}, which {} other \
+                    note(s) already hold.",
+                    note.channel_id, key.2, key.1, port_id, *count - 1
+                );
+            }
+            return true;
+        }
+        match self.voices.get_mut(&key) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            _ => {
+                self.voices.remove(&key);
+                true
+            }
         }
     }
 
-    let mut player = Player::new();
+    /// Sends a one-byte clock or transport message to every clock port.
+    fn send_clock(&mut self, message: u8) -> Result<(), PlaybackError> {
+        let config = self.config;
+        for port_id in &config.clock_ports {
+            if !self.skipped_ports.contains(port_id) {
+                let sent = self.sink.send(*port_id, &[message]);
+                self.check(sent)?;
+            }
+        }
+        Ok(())
+    }
 
-    info!("Player Starting.");
-    while *running.lock().unwrap().get(name).unwrap() {
-        debug!("Time: {}", player.time());
-        for note in player.poll_channels(channels) {
-            route_note(&player_config, &mut port_id_to_conn, &note, NOTE_ON_MSG)
+    /// Switches the count-in click off and on as due on `tick`, or just off with `None`.
+    fn click(&mut self, tick: Option<u64>) -> Result<(), PlaybackError> {
+        let Some(click) = self.config.click else {
+            return Ok(());
+        };
+        let (release, start) = match tick {
+            None => (true, false),
+            Some(tick) => {
+                let since = tick % click.every_ticks;
+                let length = (click.note.duration.max(1) as u64).min(click.every_ticks);
+                (since == 0 || since == length, since == 0 && tick < self.config.count_in_ticks)
+            }
+        };
+        let mut statuses = Vec::new();
+        if release && self.click_sounding {
+            statuses.push(NOTE_OFF_MSG);
         }
-        player.do_tick(bpm);
-        for note in player.clear_elapsed_notes() {
-            route_note(&player_config, &mut port_id_to_conn, &note, NOTE_OFF_MSG)
+        if start {
+            statuses.push(NOTE_ON_MSG);
         }
+        for status in statuses {
+            self.click_sounding = status == NOTE_ON_MSG;
+            if let Some(message) = click.note.message(status) {
+                if !self.skipped_ports.contains(&click.port_id) {
+                    let sent = self.sink.send(click.port_id, &message);
+                    self.check(sent)?;
+                }
+            }
+        }
+        Ok(())
     }
-    for note in player.clear_all_notes() {
-        route_note(&player_config, &mut port_id_to_conn, &note, NOTE_OFF_MSG)
+
+    /// Sends All Notes Off on every MIDI channel of every port the router sends to.
+    fn all_notes_off(&mut self) -> Result<(), PlaybackError> {
+        let ports: BTreeSet<usize> = self.config.router.required_ports().into_iter().collect();
+        for port_id in ports {
+            for channel in 0..16 {
+                if !self.skipped_ports.contains(&port_id) {
+                    let sent = self.sink.send(port_id, &[CONTROL_CHANGE_MSG | channel, ALL_NOTES_OFF_CC, 0]);
+                    self.check(sent)?;
+                }
+            }
+        }
+        self.voices.clear();
+        Ok(())
+    }
+
+    /// Sends a NOTE_OFF for every pitch still counted in `voices`, so that no note is left
+    /// sounding once playback has stopped.
+    fn release_voices(&mut self) -> Result<(), PlaybackError> {
+        let keys: BTreeSet<(usize, u8, u8)> = self.voices.drain().map(|(key, _)| key).collect();
+        for (port_id, channel, pitch) in keys {
+            if !self.skipped_ports.contains(&port_id) {
+                let sent = self.sink.send(port_id, &[NOTE_OFF_MSG | channel, pitch, 0]);
+                self.check(sent)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the player on a tick, sending clock pulses evenly through it when there are clock
+    /// ports.
+    fn tick(&mut self, player: &mut Player, meter: &dyn Meter) -> Result<(), PlaybackError> {
+        if self.config.clock_ports.is_empty() {
+            player.do_tick(meter);
+            return Ok(());
+        }
+        let pulse = player.tick_duration(meter) / CLOCK_PULSES_PER_TICK;
+        for _ in 0..CLOCK_PULSES_PER_TICK {
+            self.send_clock(CLOCK_MSG)?;
+            sleep(pulse);
+        }
+        player.advance();
+        Ok(())
+    }
+
+    /// Applies the error policy to the result of a send.
+    fn check(&mut self, result: Result<(), PlaybackError>) -> Result<(), PlaybackError> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(err @ PlaybackError::NoRoute { .. }) => {
+                error!("{}", err);
+                Ok(())
+            }
+            Err(err) => match (self.policy, err.port_id()) {
+                (ErrorPolicy::SkipPort, Some(port_id)) => {
+                    error!("Skipping port {} from now on: {}", port_id, err);
+                    self.skipped_ports.insert(port_id);
+                    Ok(())
+                }
+                _ => Err(err),
+            },
+        }
     }
-    info!("Player Exiting.");
-    Ok(())
 }
 
-fn route_note(
+/// The message sending `playing` on `port_id`, after the port's velocity curve and the
+/// channel's MIDI channel have been applied.
+fn note_message(
     player_config: &PlayerConfig,
-    device_conn: &mut HashMap<usize, MidiOutputConnection>,
+    port_id: usize,
     playing: &PlayingNote,
     midi_status: u8
-) {
-    match playing.note.u8_maybe() {
-        None => { /* resting */ }
-        Some(v) => {
-            let note: [u8; 3] = [
-                midi_status, v, playing.note.velocity
-            ];
-
-            match player_config.route(playing.channel_id) {
-                None => {
-                    error!("No port configured for channel! channel_id = {}", playing.channel_id);
-                }
-                Some(port_id) => {
-                    device_conn.get_mut(port_id)
-                        .unwrap_or_else(|| panic!("Could not find connection for port {}", port_id))
-                        .send(&note)
-                        .unwrap_or_else(|err| panic!("Failed to send note to port {}, {}", port_id, err))
+) -> Option<Vec<u8>> {
+    let note = match player_config.velocity_curves.get(&port_id) {
+        Some(curve) if midi_status == NOTE_ON_MSG && playing.note.control.is_none() => {
+            playing.note.set_velocity(curve.apply(playing.note.velocity))
+        }
+        _ => playing.note,
+    };
+    let note = match player_config.midi_channels.get(&playing.channel_id) {
+        Some(midi_channel) if note.channel.is_none() => note.set_channel(*midi_channel),
+        _ => note,
+    };
+    note.message(midi_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use crate::meter::{Bpm, MIN_BPM, Position, SharedMeter, SwingMeter, TimeSignature};
+    use crate::midi::{ALL_NOTES_OFF_CC, CLOCK_MSG, CONTROL_CHANGE_MSG, Control, Midi, NOTE_OFF_MSG, NOTE_ON_MSG, PITCH_BEND_MSG, PROGRAM_CHANGE_MSG, START_MSG, STOP_MSG};
+    use crate::bend::PitchBend;
+    use crate::chord::Chord;
+    use crate::player::{ErrorPolicy, GrooveTemplate, Humanize, Player, PlayerConfig, PlayingNote, SyncMode, Trace, TraceEvent, Transport, TransportState, Output, bar_ticks, try_run_with_sink};
+    use crate::router::{MapRouter, NameMatch, Router, VelocityCurve};
+    use crate::sequences::Seq;
+    use crate::sink::{MidiSink, PlaybackError, RecordingSink};
+    use crate::smf;
+    use crate::tone::Tone;
+
+    fn route_note(
+        config: &PlayerConfig,
+        sink: &mut dyn MidiSink,
+        playing: &PlayingNote,
+        midi_status: u8
+    ) -> Result<(), PlaybackError> {
+        let mut output = Output {
+            config,
+            sink,
+            skipped_ports: HashSet::new(),
+            policy: ErrorPolicy::Abort,
+            click_sounding: false,
+            voices: HashMap::new(),
+        };
+        output.route(&[*playing], midi_status)
+    }
+
+    fn running(name: &str) -> Arc<Mutex<HashMap<String, bool>>> {
+        let mut map = HashMap::new();
+        map.insert(name.to_string(), true);
+        Arc::new(Mutex::new(map))
+    }
+
+    #[test]
+    fn max_runtime() {
+        let running = running("test");
+        let sink = RecordingSink::new();
+        let started = Instant::now();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_max_runtime(Duration::from_millis(50)),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 10_000]).midibox()],
+            &running,
+            &mut sink.clone()
+        ).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!running.lock().unwrap().get("test").unwrap());
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![vec![NOTE_ON_MSG, 60, 100], vec![NOTE_OFF_MSG, 60, 100]]);
+    }
+
+    #[test]
+    fn swing_delays_off_beats() {
+        let meter = SwingMeter::new(Box::new(Bpm::new(1200)), 1, 0.5);
+        let mut channels = vec![Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4)]).midibox()];
+        let mut player = Player::new();
+        let mut onsets: Vec<(Tone, Duration)> = Vec::new();
+        for _ in 0..4 {
+            for playing in player.step(&mut channels).note_ons {
+                onsets.push((playing.note.tone, player.tick_duration(&meter)));
+            }
+            player.advance();
+        }
+        // 50ms ticks swung by half: on-beats last 75ms and off-beats 25ms
+        let (on_beat, off_beat) = (Duration::from_millis(75), Duration::from_millis(25));
+        assert_eq!(onsets, vec![
+            (Tone::C, on_beat), (Tone::D, off_beat), (Tone::C, on_beat), (Tone::D, off_beat),
+        ]);
+    }
+
+    #[test]
+    fn short_notes_release_before_the_step_ends() {
+        let meter = Bpm::new(60_000);
+        let mut channels = vec![
+            Seq::chords(vec![
+                Chord::new(vec![Tone::C.oct(4) * 1, Tone::E.oct(4) * 3]),
+                Chord::note(Tone::G.oct(4) * 1),
+            ]).midibox()
+        ];
+        let mut player = Player::new();
+
+        assert_eq!(player.poll_channels(&mut channels).len(), 2);
+        player.do_tick(&meter);
+        let released = player.clear_elapsed_notes();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].note, Tone::C.oct(4) * 1);
+
+        // the channel isn't polled again until its step is over, even though it has a free voice
+        assert!(player.poll_channels(&mut channels).is_empty());
+        player.do_tick(&meter);
+        assert!(player.clear_elapsed_notes().is_empty());
+        assert!(player.poll_channels(&mut channels).is_empty());
+        player.do_tick(&meter);
+        assert_eq!(player.clear_elapsed_notes().len(), 1);
+
+        let polled = player.poll_channels(&mut channels);
+        assert_eq!(polled.len(), 1);
+        assert_eq!(polled[0].note, Tone::G.oct(4) * 1);
+        assert_eq!(polled[0].start_tick_id, 3);
+    }
+
+    #[test]
+    fn count_in() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4)]).midibox(),
+            Seq::new(vec![Tone::E.oct(4) * 2]).midibox(),
+        ];
+        let mut player = Player::new().with_count_in(3);
+
+        for _ in 0..3 {
+            assert!(player.poll_channels(&mut channels).is_empty());
+            player.advance();
+        }
+        let polled = player.poll_channels(&mut channels);
+        assert_eq!(polled.len(), 2);
+        assert!(polled.iter().all(|n| n.start_tick_id == 3));
+    }
+
+    #[test]
+    fn position_counts_bars_from_the_end_of_the_count_in() {
+        let signature = TimeSignature::new(7, 8).unwrap();
+        let mut player = Player::new().with_count_in(4);
+        assert_eq!(player.position(&signature), None);
+        for _ in 0..22 {
+            player.advance();
+        }
+        assert_eq!(player.position(&signature), Some(Position { bar: 1, beat: 2, tick: 0 }));
+    }
+
+    #[test]
+    fn count_in_delays_playback() {
+        let sink = RecordingSink::new();
+        let config = PlayerConfig::for_port(0)
+            .with_count_in(2, 4)
+            .with_max_ticks(10)
+            .with_trace();
+        let trace = config.trace().unwrap();
+        try_run_with_sink(
+            "test",
+            config,
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4)]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        // two bars of 4 ticks
+        let first = trace.events().into_iter().find_map(|event| match event {
+            TraceEvent::NoteOn { tick, .. } => Some(tick),
+            _ => None,
+        });
+        assert_eq!(first, Some(8));
+        assert_eq!(sink.messages()[0].message, vec![NOTE_ON_MSG, 60, 100]);
+    }
+
+    #[test]
+    fn count_in_click_plays_until_the_channels_start() {
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0)
+                .with_count_in(1, 4)
+                .with_count_in_click(1, Tone::A.oct(5).set_channel(9) * 1, 2)
+                .with_max_ticks(6),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 2]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<(usize, Vec<u8>)> = sink.messages().into_iter()
+            .map(|m| (m.port_id, m.message))
+            .collect();
+        assert_eq!(messages, vec![
+            (1, vec![NOTE_ON_MSG | 9, 81, 100]),
+            (1, vec![NOTE_OFF_MSG | 9, 81, 100]),
+            (1, vec![NOTE_ON_MSG | 9, 81, 100]),
+            (1, vec![NOTE_OFF_MSG | 9, 81, 100]),
+            // on the beat after the last click
+            (0, vec![NOTE_ON_MSG, 60, 100]),
+            (0, vec![NOTE_OFF_MSG, 60, 100]),
+        ]);
+        assert!(PlayerConfig::for_port(0)
+            .with_count_in_click(3, Tone::A.oct(5) * 1, 4)
+            .required_ports()
+            .contains(&3));
+    }
+
+    #[test]
+    fn tied_loop_ending_is_not_retriggered() {
+        let meter = Bpm::new(60_000);
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4) * 2, Tone::E.oct(4) * 2, Tone::C.oct(4) * 2])
+                .sustain_across_loop()
+                .midibox()
+        ];
+        let mut player = Player::new();
+        let mut note_ons: Vec<(u64, Tone)> = Vec::new();
+        let mut note_offs: Vec<(u64, Tone)> = Vec::new();
+        for _ in 0..12 {
+            note_ons.extend(player.poll_channels(&mut channels).iter().map(|n| (player.time(), n.note.tone)));
+            note_offs.extend(player.release_held_ties().iter().map(|n| (player.time(), n.note.tone)));
+            player.do_tick(&meter);
+            note_offs.extend(player.clear_elapsed_notes().iter().map(|n| (player.time(), n.note.tone)));
+        }
+
+        assert_eq!(note_ons, vec![
+            (0, Tone::C), (2, Tone::E), (4, Tone::C), (8, Tone::E), (10, Tone::C)
+        ]);
+        assert_eq!(note_offs, vec![
+            (2, Tone::C), (4, Tone::E), (8, Tone::C), (10, Tone::E)
+        ]);
+        // the final note is still being held for the next loop
+        assert_eq!(player.clear_all_notes().len(), 1);
+    }
+
+    #[test]
+    fn trace_records_events_in_order() {
+        let meter = Bpm::new(60_000);
+        let config = PlayerConfig::for_port(0).with_trace();
+        let trace = config.trace().unwrap();
+        let mut channels = vec![Seq::new(vec![Tone::C.oct(4), Tone::E.oct(4)]).midibox()];
+        let mut player = Player::new().with_trace(trace.clone());
+        for _ in 0..2 {
+            player.poll_channels(&mut channels);
+            player.release_held_ties();
+            player.do_tick(&meter);
+            player.clear_elapsed_notes();
+        }
+
+        let c = Tone::C.oct(4);
+        let e = Tone::E.oct(4);
+        assert_eq!(trace.events(), vec![
+            TraceEvent::Poll { tick: 0, channel_id: 0 },
+            TraceEvent::NoteOn { tick: 0, channel_id: 0, note: c },
+            TraceEvent::Tick { tick: 1 },
+            TraceEvent::NoteOff { tick: 1, channel_id: 0, note: c },
+            TraceEvent::Poll { tick: 1, channel_id: 0 },
+            TraceEvent::NoteOn { tick: 1, channel_id: 0, note: e },
+            TraceEvent::Tick { tick: 2 },
+            TraceEvent::NoteOff { tick: 2, channel_id: 0, note: e },
+        ]);
+    }
+
+    #[test]
+    fn tempo_change_alters_tick_duration() {
+        let meter = Bpm::new(60);
+        let mut channels = vec![
+            Seq::new(vec![
+                Tone::C.oct(4),
+                Midi::control(Control::TempoChange(240.0)),
+                Tone::D.oct(4),
+            ]).midibox()
+        ];
+        let mut player = Player::new();
+        assert_eq!(player.tick_duration(&meter), Duration::from_secs(1));
+
+        let polled = player.poll_channels(&mut channels);
+        assert_eq!(polled.len(), 1);
+        assert_eq!(player.tick_duration(&meter), Duration::from_secs(1));
+        player.tick_id += 1;
+        player.clear_elapsed_notes();
+
+        // the zero-duration tempo change takes no time, so the next note starts on the same tick
+        let polled = player.poll_channels(&mut channels);
+        assert_eq!(polled.len(), 1);
+        assert_eq!(polled[0].note, Tone::D.oct(4));
+        assert_eq!(player.tick_duration(&meter), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn shared_meter_changes_apply_from_the_next_tick() {
+        let meter = SharedMeter::new(6000.0);
+        let tap = meter.clone();
+        let mut player = Player::new();
+        assert_eq!(player.tick_duration(&meter).as_micros(), 10_000);
+        player.do_tick(&meter);
+        tap.set_bpm(12000.0);
+        assert_eq!(player.tick_duration(&meter).as_micros(), 5_000);
+        assert_eq!(player.do_tick(&meter), 2);
+        assert_eq!(meter.bpm(), 12000.0);
+
+        tap.set_bpm(0.0);
+        assert_eq!(meter.bpm(), MIN_BPM);
+        assert_eq!(player.tick_duration(&meter), Duration::from_secs(60));
+        tap.set_bpm(f64::NAN);
+        assert_eq!(player.tick_duration(&meter), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn port_names_can_match_part_of_the_name() {
+        let port_names = vec!["Midi Through 14:0".to_string(), "drumlogue:drumlogue MIDI 1 28:0".to_string()];
+        let mut config = PlayerConfig::for_port_name_matching("drumlogue", NameMatch::Substring);
+        config.resolve_ports(&port_names).unwrap();
+        assert_eq!(config.route(0), Some(1));
+        assert!(PlayerConfig::for_port_name("drumlogue").resolve_ports(&port_names).is_err());
+    }
+
+    #[test]
+    fn velocity_curves_apply_per_port() {
+        let config = PlayerConfig::from_router(Box::new(MapRouter::new(HashMap::from([(0, 0), (1, 1)]))))
+            .with_velocity_curve(0, VelocityCurve::Gamma(2.0))
+            .with_velocity_curve(1, VelocityCurve::Fixed(90));
+        let sink = RecordingSink::new();
+        for channel_id in [0, 1] {
+            let playing = PlayingNote { channel_id, start_tick_id: 0, note: Tone::C.oct(4).set_velocity(64) };
+            route_note(&config, &mut sink.clone(), &playing, NOTE_ON_MSG).unwrap();
+            route_note(&config, &mut sink.clone(), &playing, NOTE_OFF_MSG).unwrap();
+        }
+
+        let messages: Vec<(usize, Vec<u8>)> = sink.messages().into_iter().map(|m| (m.port_id, m.message)).collect();
+        assert_eq!(messages, vec![
+            (0, vec![NOTE_ON_MSG, 60, 32]),
+            (0, vec![NOTE_OFF_MSG, 60, 64]),
+            (1, vec![NOTE_ON_MSG, 60, 90]),
+            (1, vec![NOTE_OFF_MSG, 60, 64]),
+        ]);
+    }
+
+    #[test]
+    fn skipping_past_a_note_end_still_releases_it() {
+        let mut channels = vec![Seq::new(vec![Tone::C.oct(4) * 2]).midibox()];
+        let mut player = Player::new();
+        player.poll_channels(&mut channels);
+        player.tick_id = 5;
+
+        let released = player.clear_elapsed_notes();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].note, Tone::C.oct(4) * 2);
+    }
+
+    #[test]
+    fn watchdog_releases_stuck_notes() {
+        let meter = Bpm::new(60_000);
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4).set_tie(true) * 100]).midibox(),
+        ];
+        let mut player = Player::new().with_watchdog(3);
+        player.poll_channels(&mut channels);
+        for _ in 0..2 {
+            player.do_tick(&meter);
+            assert!(player.clear_elapsed_notes().is_empty());
+        }
+        player.do_tick(&meter);
+        assert_eq!(player.clear_elapsed_notes().len(), 1);
+        assert!(player.clear_all_notes().is_empty());
+    }
+
+    #[test]
+    fn locked_channels_advance_together() {
+        let meter = Bpm::new(60_000);
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4) * 1, Tone::D.oct(4) * 1]).midibox(),
+            Seq::new(vec![Tone::E.oct(4) * 3, Tone::F.oct(4) * 3]).midibox(),
+        ];
+        let mut player = Player::new().with_sync_mode(SyncMode::Locked(2));
+        let mut note_ons: Vec<(u64, Tone)> = Vec::new();
+        for _ in 0..6 {
+            note_ons.extend(player.poll_channels(&mut channels).iter().map(|n| (player.time(), n.note.tone)));
+            player.do_tick(&meter);
+            player.clear_elapsed_notes();
+        }
+
+        assert_eq!(note_ons, vec![
+            (0, Tone::C), (0, Tone::E), (2, Tone::D), (2, Tone::F), (4, Tone::C), (4, Tone::E)
+        ]);
+        // the second channel's notes outlast the grid, so the E polled on tick 4 is still sounding
+        assert_eq!(player.clear_all_notes().len(), 1);
+    }
+
+    #[test]
+    fn render_to_smf() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4) * 2, Tone::Rest * 1, Tone::D.oct(4).set_channel(2) * 1]).midibox(),
+            Seq::new(vec![Tone::E.oct(4) * 4]).midibox(),
+        ];
+        let bytes = Player::render_to_smf(&mut channels, &Bpm::new(480), 6).unwrap();
+        assert_eq!(&bytes[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 3, 0x01, 0xE0]);
+        // 120 beats per minute
+        assert_eq!(&bytes[22..32], &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x85, 0x50, 0xFF]);
+
+        let file = smf::parse(&bytes).unwrap();
+        let notes: Vec<Vec<(u64, u64, u8, u8)>> = file.tracks.iter()
+            .map(|t| t.notes.iter().map(|n| (n.start, n.end, n.key, n.channel)).collect())
+            .collect();
+        assert_eq!(notes, vec![
+            vec![],
+            vec![(0, 240, 60, 0), (360, 480, 62, 2), (480, 720, 60, 0)],
+            // the second note is still sounding at the cutoff
+            vec![(0, 480, 64, 0), (480, 720, 64, 0)],
+        ]);
+        assert!(file.tracks.iter().all(|t| t.end == 720));
+    }
+
+    #[test]
+    fn step_works_out_each_tick_without_waiting() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4).set_tie(true) * 1, Tone::C.oct(4) * 1, Tone::D.oct(4) * 1]).midibox(),
+        ];
+        let mut player = Player::new();
+        let pitches = |notes: &[PlayingNote]| -> Vec<u8> {
+            notes.iter().filter_map(|playing| playing.note.u8_maybe()).collect()
+        };
+        let mut ticks = Vec::new();
+        for _ in 0..4 {
+            let events = player.step(&mut channels);
+            ticks.push((player.time(), pitches(&events.note_offs), pitches(&events.note_ons)));
+            player.advance();
+        }
+        assert_eq!(ticks, vec![
+            (0, vec![], vec![60]),
+            // continued by the tie, so neither released nor restarted
+            (1, vec![], vec![]),
+            (2, vec![60], vec![62]),
+            (3, vec![62], vec![60]),
+        ]);
+    }
+
+    #[test]
+    fn transposed_channels_shift_the_notes_they_start() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4), Tone::G.oct(9), Tone::Rest * 1, Tone::C.oct(4)]).midibox(),
+            Seq::new(vec![Tone::C.oct(4) * 4]).midibox(),
+        ];
+        let mut player = Player::new();
+        player.set_transpose(0, 2);
+        let mut ons = Vec::new();
+        for tick in 0..4 {
+            if tick == 2 {
+                player.set_transpose(0, -1);
+            }
+            let events = player.step(&mut channels);
+            ons.push(events.note_ons.iter().map(|playing| playing.note.u8_maybe()).collect::<Vec<_>>());
+            player.advance();
+        }
+        // G9 can't go any higher, and the rest stays a rest
+        assert_eq!(ons, vec![vec![Some(62), Some(60)], vec![Some(127)], vec![None], vec![Some(59)]]);
+
+        let transport = Transport::new();
+        transport.transpose(0, 1);
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_max_ticks(1).with_transpose(0, 12).with_transport(transport),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 1]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![vec![NOTE_ON_MSG, 61, 100], vec![NOTE_OFF_MSG, 61, 100]]);
+    }
+
+    #[test]
+    fn muted_channels_keep_their_place_and_release_their_notes() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4), Tone::E.oct(4)]).midibox(),
+            Seq::new(vec![Tone::G.oct(4) * 4]).midibox(),
+        ];
+        let mut player = Player::new();
+        let pitches = |notes: &[PlayingNote]| -> Vec<u8> {
+            notes.iter().filter_map(|playing| playing.note.u8_maybe()).collect()
+        };
+        assert_eq!(pitches(&player.step(&mut channels).note_ons), vec![60, 67]);
+        player.advance();
+        player.clear_elapsed_notes();
+        assert_eq!(pitches(&player.set_muted(HashSet::from([0, 1]))), vec![67]);
+        assert!(player.step(&mut channels).note_ons.is_empty());
+        player.advance();
+        assert!(player.set_muted(HashSet::new()).is_empty());
+        // the first channel played on silently, so comes back in on its third step
+        assert_eq!(pitches(&player.step(&mut channels).note_ons), vec![64]);
+
+        let transport = Transport::new();
+        transport.mute(2);
+        assert_eq!(transport.muted_channels(3), HashSet::from([2]));
+        transport.solo(1);
+        assert_eq!(transport.muted_channels(3), HashSet::from([0, 2]));
+        transport.unsolo(1);
+        transport.unmute(2);
+        assert!(transport.is_audible(0) && transport.is_audible(2));
+    }
+
+    #[test]
+    fn channels_of_different_lengths_loop_at_their_own_periods() {
+        // a three-tick bass line against a four-tick melody only lines up again every 12 ticks
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(3) * 2, Tone::D.oct(3) * 1]).midibox(),
+            Seq::new(vec![Tone::C.oct(5) * 1, Tone::D.oct(5) * 1, Tone::E.oct(5) * 2]).midibox(),
+        ];
+        let mut player = Player::new();
+        let mut starts = Vec::new();
+        for _ in 0..36 {
+            for playing in player.poll_channels(&mut channels) {
+                starts.push((player.time(), playing.channel_id, playing.note.u8_maybe().unwrap()));
+            }
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+
+        let expected: Vec<(u64, usize, u8)> = (0..36_u64)
+            .flat_map(|tick| {
+                let bass = match tick % 3 { 0 => Some(48), 2 => Some(50), _ => None };
+                let melody = match tick % 4 { 0 => Some(72), 1 => Some(74), 2 => Some(76), _ => None };
+                bass.map(|pitch| (tick, 0, pitch)).into_iter()
+                    .chain(melody.map(|pitch| (tick, 1, pitch)))
+            })
+            .collect();
+        assert_eq!(starts, expected);
+        let downbeats: Vec<u64> = starts.iter()
+            .filter(|(tick, _, pitch)| *pitch == 48 && starts.contains(&(*tick, 1, 72)))
+            .map(|(tick, _, _)| *tick)
+            .collect();
+        assert_eq!(downbeats, vec![0, 12, 24]);
+    }
+
+    #[test]
+    fn finished_channels_are_no_longer_polled() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4) * 2, Midi::rest() * 1]).once(),
+            Seq::new(vec![Tone::D.oct(4) * 2]).midibox(),
+        ];
+        let mut player = Player::new().with_trace(Trace::new());
+        let trace = player.trace.clone().unwrap();
+        for _ in 0..6 {
+            player.poll_channels(&mut channels);
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+        let polls: Vec<(u64, usize)> = trace.events().into_iter()
+            .filter_map(|event| match event {
+                TraceEvent::Poll { tick, channel_id } => Some((tick, channel_id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(polls, vec![(0, 0), (0, 1), (2, 0), (2, 1), (4, 1)]);
+        assert!(!player.has_finished(&channels));
+        assert!(player.has_finished(&channels[..1]));
+    }
+
+    #[test]
+    fn bounded_runs_end_once_every_channel_has_finished() {
+        let config = PlayerConfig::for_port(0).with_trace().with_max_ticks(1000);
+        let trace = config.trace().unwrap();
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            config,
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 2, Tone::D.oct(4) * 1]).once()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 62, 100],
+            vec![NOTE_OFF_MSG, 62, 100],
+        ]);
+        let last_tick = trace.events().into_iter()
+            .rev()
+            .find_map(|event| match event {
+                TraceEvent::Tick { tick } => Some(tick),
+                _ => None,
+            });
+        // the channel is found to be finished when it's next due, at tick 3
+        assert_eq!(last_tick, Some(4));
+    }
+
+    #[test]
+    fn channels_are_sent_on_their_midi_channel() {
+        let config = PlayerConfig::for_port(0)
+            .with_midi_channel(1, 9)
+            .with_midi_channel(2, 40);
+        let sink = RecordingSink::new();
+        let notes = [
+            PlayingNote { channel_id: 0, note: Tone::C.oct(4), start_tick_id: 0 },
+            PlayingNote { channel_id: 1, note: Tone::C.oct(4), start_tick_id: 0 },
+            PlayingNote { channel_id: 1, note: Tone::C.oct(4).set_channel(2), start_tick_id: 0 },
+            PlayingNote { channel_id: 2, note: Midi::control(Control::ProgramChange(5)), start_tick_id: 0 },
+        ];
+        for note in &notes {
+            route_note(&config, &mut sink.clone(), note, NOTE_ON_MSG).unwrap();
+        }
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_ON_MSG | 9, 60, 100],
+            vec![NOTE_ON_MSG | 2, 60, 100],
+            vec![PROGRAM_CHANGE_MSG | 15, 5],
+        ]);
+    }
+
+    #[test]
+    fn layered_notes_are_released_by_the_last_to_end() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4) * 4]).midibox(),
+            Seq::new(vec![Tone::C.oct(4) * 2, Tone::Rest * 2]).midibox(),
+            Seq::new(vec![Tone::C.oct(4).set_channel(1) * 1, Tone::Rest * 3]).midibox(),
+        ];
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_max_ticks(4),
+            &Bpm::new(60_000),
+            &mut channels,
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        // the second channel's C ends first, but the first channel's is still sounding
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_ON_MSG | 1, 60, 100],
+            vec![NOTE_OFF_MSG | 1, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+        ]);
+    }
+
+    #[test]
+    fn layered_legato_restarts_are_released() {
+        let mut channels = vec![
+            Seq::chords(vec![
+                Chord::new(vec![Tone::C.oct(4) * 4, Tone::E.oct(4) * 1]),
+                Chord::note(Tone::C.oct(4) * 1),
+            ]).midibox(),
+            Seq::new(vec![Tone::C.oct(4) * 4]).midibox(),
+        ];
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_legato(0, 4).with_max_ticks(2),
+            &Bpm::new(60_000),
+            &mut channels,
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        // the first channel's restarted C is held under the second channel's, which releases
+        // it when playback stops
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 64, 100],
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 64, 100],
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+        ]);
+    }
+
+    #[test]
+    fn control_changes_are_sent_without_a_note_off() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4).set_channel(3) * 2, Tone::D.oct(4).set_channel(3) * 2])
+                .automate(74, &[20, 90])
+                .midibox()
+        ];
+        let mut player = Player::new();
+        let config = PlayerConfig::for_port(0);
+        let sink = RecordingSink::new();
+        for _ in 0..4 {
+            for note in player.poll_channels(&mut channels) {
+                route_note(&config, &mut sink.clone(), &note, NOTE_ON_MSG).unwrap();
+            }
+            player.advance();
+            for note in player.clear_elapsed_notes() {
+                route_note(&config, &mut sink.clone(), &note, NOTE_OFF_MSG).unwrap();
+            }
+        }
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![
+            vec![CONTROL_CHANGE_MSG | 3, 74, 20],
+            vec![NOTE_ON_MSG | 3, 60, 100],
+            vec![NOTE_OFF_MSG | 3, 60, 100],
+            vec![CONTROL_CHANGE_MSG | 3, 74, 90],
+            vec![NOTE_ON_MSG | 3, 62, 100],
+            vec![NOTE_OFF_MSG | 3, 62, 100],
+        ]);
+    }
+
+    #[test]
+    fn pitch_bends_ramp_and_reset_when_playback_stops() {
+        let mut channels = vec![
+            PitchBend::ramp_on_channel(2, 0, 8191, 3),
+            Seq::new(vec![Tone::C.oct(4) * 3]).midibox(),
+        ];
+        let bytes = Player::render_to_smf(&mut channels, &Bpm::new(480), 2).unwrap();
+        // the channel's track comes after the tempo track
+        let tempo_track_len = u32::from_be_bytes([bytes[18], bytes[19], bytes[20], bytes[21]]);
+        let track = &bytes[22 + tempo_track_len as usize..];
+        assert_eq!(&track[..4], b"MTrk");
+        assert_eq!(&track[8..22], &[
+            0x00, PITCH_BEND_MSG | 2, 0x00, 0x40,
+            0x78, PITCH_BEND_MSG | 2, 0x00, 0x60,
+            // stopped mid-glide, so the bend is put back to centre
+            0x78, PITCH_BEND_MSG | 2, 0x00, 0x40,
+            0x00, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn pitch_bends_reset_on_the_mapped_midi_channel() {
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_midi_channel(0, 5).with_max_ticks(2),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Midi::control(Control::PitchBend(4096)).set_duration(2)]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert!(messages.iter().all(|m| m[0] == PITCH_BEND_MSG | 5), "{:?}", messages);
+        assert_eq!(messages.last(), Some(&vec![PITCH_BEND_MSG | 5, 0x00, 0x40]));
+    }
+
+    #[test]
+    fn program_changes_go_out_before_the_notes_of_the_same_tick() {
+        let mut channels = vec![
+            Seq::new(vec![Tone::C.oct(4).set_channel(1) * 2, Tone::D.oct(4).set_channel(1) * 2])
+                .fast_forward(1)
+                .program_change(5)
+                .midibox()
+        ];
+        let mut player = Player::new();
+        let mut started: Vec<(u64, Vec<u8>)> = Vec::new();
+        for _ in 0..4 {
+            let tick = player.time();
+            started.extend(player.poll_channels(&mut channels).iter()
+                .filter_map(|n| n.note.message(NOTE_ON_MSG))
+                .map(|message| (tick, message)));
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+
+        assert_eq!(started, vec![
+            (0, vec![PROGRAM_CHANGE_MSG | 1, 5]),
+            (0, vec![NOTE_ON_MSG | 1, 62, 100]),
+            (2, vec![NOTE_ON_MSG | 1, 60, 100]),
+        ]);
+    }
+
+    /// Records messages, except that sending to `failing_port` always fails.
+    struct FlakySink {
+        recording: RecordingSink,
+        failing_port: usize,
+    }
+
+    impl MidiSink for FlakySink {
+        fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+            if port_id == self.failing_port {
+                return Err(PlaybackError::SendFailed { port_id, reason: "unplugged".to_string() });
+            }
+            self.recording.send(port_id, message)
+        }
+    }
+
+    fn run_with_failing_port(policy: ErrorPolicy) -> (Result<(), String>, Vec<Vec<u8>>) {
+        let recording = RecordingSink::new();
+        let result = try_run_with_sink(
+            "test",
+            PlayerConfig::from_router(Box::new(MapRouter::new(HashMap::from([(0, 0), (1, 1)]))))
+                .with_error_policy(policy)
+                .with_max_runtime(Duration::from_millis(20)),
+            &Bpm::new(60_000),
+            &mut vec![
+                Seq::new(vec![Tone::C.oct(4) * 1]).midibox(),
+                Seq::new(vec![Tone::E.oct(4) * 1]).midibox(),
+            ],
+            &running("test"),
+            &mut FlakySink { recording: recording.clone(), failing_port: 1 }
+        ).map_err(|err| err.to_string());
+        (result, recording.messages().into_iter().map(|m| m.message).collect())
+    }
+
+    #[test]
+    fn send_failures_follow_the_error_policy() {
+        let (result, messages) = run_with_failing_port(ErrorPolicy::Abort);
+        assert_eq!(result, Err("failed to send to port 1: unplugged".to_string()));
+        // the note already started on the working port is still released
+        assert_eq!(messages, vec![vec![NOTE_ON_MSG, 60, 100], vec![NOTE_OFF_MSG, 60, 100]]);
+
+        let (result, messages) = run_with_failing_port(ErrorPolicy::SkipPort);
+        assert_eq!(result, Ok(()));
+        assert!(messages.len() > 2, "the working port stopped playing");
+        assert!(messages.iter().all(|m| m[1] == 60));
+    }
+
+    /// Records messages, pausing the transport as soon as the first one is sent and resuming it
+    /// with the second, which is the pause silencing the first.
+    struct PausingSink {
+        recording: RecordingSink,
+        transport: Transport,
+    }
+
+    impl MidiSink for PausingSink {
+        fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+            match self.recording.messages().len() {
+                0 => self.transport.pause(),
+                1 => {
+                    assert_eq!(self.transport.state(), TransportState::Paused);
+                    self.transport.resume();
                 }
+                _ => {}
+            }
+            self.recording.send(port_id, message)
+        }
+    }
+
+    #[test]
+    fn pausing_holds_position_and_resumes_notes_once() {
+        let recording = RecordingSink::new();
+        let transport = Transport::new();
+        let config = PlayerConfig::for_port(0)
+            .with_transport(transport.clone())
+            .with_trace()
+            .with_max_ticks(10);
+        let trace = config.trace().unwrap();
+        try_run_with_sink(
+            "test",
+            config,
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 8]).midibox()],
+            &running("test"),
+            &mut PausingSink { recording: recording.clone(), transport }
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = recording.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages[..4], [
+            vec![NOTE_ON_MSG, 60, 100],
+            // silenced by the pause
+            vec![NOTE_OFF_MSG, 60, 100],
+            // sounded again on resume, and not polled again until its step is over
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+        ]);
+        // no time passed while paused
+        let first_off = trace.events().into_iter()
+            .find_map(|event| match event {
+                TraceEvent::NoteOff { tick, .. } => Some(tick),
+                _ => None,
+            });
+        assert_eq!(first_off, Some(8));
+    }
+
+    struct PanickingSink {
+        recording: RecordingSink,
+        transport: Transport,
+    }
+
+    impl MidiSink for PanickingSink {
+        fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+            if self.recording.messages().is_empty() {
+                self.transport.panic();
             }
+            self.recording.send(port_id, message)
         }
     }
+
+    #[test]
+    fn panicking_silences_everything_and_playback_carries_on() {
+        let recording = RecordingSink::new();
+        let transport = Transport::new();
+        let config = PlayerConfig::for_port(0)
+            .with_transport(transport.clone())
+            .with_max_ticks(6);
+        try_run_with_sink(
+            "test",
+            config,
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 4]).midibox()],
+            &running("test"),
+            &mut PanickingSink { recording: recording.clone(), transport }
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = recording.messages().into_iter().map(|m| m.message).collect();
+        let all_notes_off: Vec<Vec<u8>> = (0..16)
+            .map(|channel| vec![CONTROL_CHANGE_MSG | channel, ALL_NOTES_OFF_CC, 0])
+            .collect();
+        assert_eq!(messages[..2], [vec![NOTE_ON_MSG, 60, 100], vec![NOTE_OFF_MSG, 60, 100]]);
+        assert_eq!(messages[2..18], all_notes_off[..]);
+        // the channel isn't polled again until its step is over
+        assert_eq!(messages[18..], [vec![NOTE_ON_MSG, 60, 100], vec![NOTE_OFF_MSG, 60, 100]]);
+    }
+
+    #[test]
+    fn stopping_the_transport_ends_the_run() {
+        let transport = Transport::new();
+        transport.stop();
+        let running = running("test");
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_transport(transport),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 1]).midibox()],
+            &running,
+            &mut RecordingSink::new()
+        ).unwrap();
+        assert!(!running.lock().unwrap()["test"]);
+    }
+
+    #[test]
+    fn max_ticks_stops_the_run_and_flushes_notes() {
+        let sink = RecordingSink::new();
+        let config = PlayerConfig::for_port(0).with_trace().with_max_ticks(6);
+        let trace = config.trace().unwrap();
+        try_run_with_sink(
+            "test",
+            config,
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 4]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        assert_eq!(trace.events().into_iter().rfind(|e| matches!(e, TraceEvent::Tick { .. })),
+                   Some(TraceEvent::Tick { tick: 6 }));
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 60, 100],
+            // cut short at the limit
+            vec![NOTE_OFF_MSG, 60, 100],
+        ]);
+    }
+
+    #[test]
+    fn bar_ticks_follow_the_time_signature() {
+        assert_eq!(bar_ticks(4, 4), Ok(16));
+        assert_eq!(bar_ticks(3, 4), Ok(12));
+        assert_eq!(bar_ticks(6, 8), Ok(12));
+        assert_eq!(bar_ticks(7, 16), Ok(7));
+        assert!(bar_ticks(3, 3).is_err());
+        assert!(bar_ticks(3, 32).is_err());
+    }
+
+    fn humanized_trace(seed: u64) -> Vec<TraceEvent> {
+        let trace = Trace::new();
+        let mut player = Player::new()
+            .with_trace(trace.clone())
+            .with_humanize(Humanize { max_delay_ticks: 2, velocity: 10, seed });
+        let mut channels = vec![Seq::new(vec![Tone::C.oct(4) * 2, Tone::Rest * 2]).midibox()];
+        while player.time() < 64 {
+            player.poll_channels(&mut channels);
+            player.release_held_ties();
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+        trace.events()
+    }
+
+    #[test]
+    fn humanize_delays_notes_without_shortening_them() {
+        let events = humanized_trace(11);
+        assert_eq!(events, humanized_trace(11));
+        let ons: Vec<(u64, u8)> = events.iter().filter_map(|event| match event {
+            TraceEvent::NoteOn { tick, note, .. } => Some((*tick, note.velocity)),
+            _ => None,
+        }).collect();
+        let offs: Vec<u64> = events.iter().filter_map(|event| match event {
+            TraceEvent::NoteOff { tick, .. } => Some(*tick),
+            _ => None,
+        }).collect();
+        assert_eq!(ons.len(), 16);
+        for (i, (on, velocity)) in ons.iter().enumerate() {
+            // each step is polled on time and its note starts up to two ticks late
+            assert!((i as u64 * 4..=i as u64 * 4 + 2).contains(on), "note {} started at {}", i, on);
+            assert!((90..=110).contains(velocity));
+            assert_eq!(offs[i], on + 2, "note {} was cut short", i);
+        }
+        assert!(ons.iter().any(|(on, _)| on % 4 != 0), "no note was delayed");
+        assert!(ons.iter().any(|(_, velocity)| *velocity != 100), "no velocity was varied");
+    }
+
+    #[test]
+    fn groove_delays_the_notes_of_each_step_in_turn() {
+        let trace = Trace::new();
+        let mut player = Player::new()
+            .with_trace(trace.clone())
+            .with_groove(0, GrooveTemplate::new(vec![0, 1, 0, 2]));
+        let mut channels = vec![Seq::new(vec![
+            Tone::C.oct(4) * 3, Tone::Rest * 3, Tone::D.oct(4) * 3, Tone::E.oct(4) * 3, Tone::G.oct(4) * 3,
+        ]).midibox()];
+        while player.time() < 20 {
+            player.poll_channels(&mut channels);
+            player.release_held_ties();
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+        let events: Vec<(bool, u64, Tone)> = trace.events().into_iter().filter_map(|event| match event {
+            TraceEvent::NoteOn { tick, note, .. } => Some((true, tick, note.tone)),
+            TraceEvent::NoteOff { tick, note, .. } => Some((false, tick, note.tone)),
+            _ => None,
+        }).collect();
+        assert_eq!(events, vec![
+            (true, 0, Tone::C),
+            (false, 3, Tone::C),
+            // the rest takes up the second offset
+            (true, 6, Tone::D),
+            (false, 9, Tone::D),
+            (true, 11, Tone::E),
+            // the template starts over on the fifth step
+            (true, 12, Tone::G),
+            (false, 14, Tone::E),
+            (false, 15, Tone::G),
+            (true, 16, Tone::C),
+            (false, 19, Tone::C),
+        ]);
+    }
+
+    fn legato_trace(max_voices: usize) -> Vec<(bool, u64, Option<u8>)> {
+        let trace = Trace::new();
+        let mut player = Player::new().with_trace(trace.clone()).with_legato(0, max_voices);
+        let mut channels = vec![Seq::chords(vec![
+            Chord::new(vec![Tone::C.oct(3) * 8, Tone::E.oct(4) * 2]),
+            Chord::note(Tone::G.oct(4) * 2),
+            Chord::note(Tone::E.oct(4) * 2),
+        ]).midibox()];
+        while player.time() < 8 {
+            player.poll_channels(&mut channels);
+            player.release_restarted_notes();
+            player.release_held_ties();
+            player.advance();
+            player.clear_elapsed_notes();
+        }
+        trace.events().into_iter().filter_map(|event| match event {
+            TraceEvent::NoteOn { tick, note, .. } => Some((true, tick, note.u8_maybe())),
+            TraceEvent::NoteOff { tick, note, .. } => Some((false, tick, note.u8_maybe())),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn legato_channels_overlap_their_steps() {
+        // the bass note sounds on under the melody that follows it, and is restarted rather
+        // than doubled when it comes round again before it has ended
+        assert_eq!(legato_trace(4), vec![
+            (true, 0, Some(48)), (true, 0, Some(64)),
+            (false, 2, Some(64)), (true, 2, Some(67)),
+            (false, 4, Some(67)), (true, 4, Some(64)),
+            (false, 6, Some(64)), (false, 6, Some(48)), (true, 6, Some(48)), (true, 6, Some(64)),
+            (false, 8, Some(64)),
+        ]);
+        // with a single voice the melody waits for the bass note to end
+        assert_eq!(legato_trace(1), vec![
+            (true, 0, Some(48)), (true, 0, Some(64)),
+            (false, 2, Some(64)),
+            (false, 8, Some(48)),
+        ]);
+    }
+
+    #[test]
+    fn legato_restarts_are_released_first() {
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_legato(0, 4).with_max_ticks(2),
+            &Bpm::new(60_000),
+            &mut vec![Seq::chords(vec![
+                Chord::new(vec![Tone::C.oct(4) * 4, Tone::E.oct(4) * 1]),
+                Chord::note(Tone::C.oct(4) * 1),
+            ]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+
+        let messages: Vec<Vec<u8>> = sink.messages().into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 64, 100],
+            vec![NOTE_OFF_MSG, 64, 100],
+            // the C still sounding is released before it's played again
+            vec![NOTE_OFF_MSG, 60, 100],
+            vec![NOTE_ON_MSG, 60, 100],
+            vec![NOTE_OFF_MSG, 60, 100],
+        ]);
+    }
+
+    #[test]
+    fn clock_out_follows_the_ticks() {
+        let sink = RecordingSink::new();
+        try_run_with_sink(
+            "test",
+            PlayerConfig::for_port(0).with_clock_out(vec![1]).with_max_ticks(2),
+            &Bpm::new(60_000),
+            &mut vec![Seq::new(vec![Tone::C.oct(4) * 1]).midibox()],
+            &running("test"),
+            &mut sink.clone()
+        ).unwrap();
+        let clock: Vec<u8> = sink.messages().into_iter()
+            .filter(|m| m.port_id == 1)
+            .map(|m| m.message[0])
+            .collect();
+        let mut expected = vec![START_MSG];
+        expected.extend([CLOCK_MSG; 12]);
+        expected.push(STOP_MSG);
+        assert_eq!(clock, expected);
+        assert_eq!(sink.messages().iter().filter(|m| m.port_id == 0).count(), 4);
+    }
 }
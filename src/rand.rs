@@ -1,16 +1,52 @@
+use std::collections::BTreeMap;
 use crate::Midibox;
-use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use crate::midi::Midi;
+use crate::scale::{Degree, Scale};
 
 pub struct RandomVelocity {
     factor: f64,
+    /// When set, the factor is only re-randomized once every `hold_ticks` ticks of output rather
+    /// than on every emission.
+    hold_ticks: Option<u32>,
+    /// Ticks of output produced so far, summed from the duration of each emission.
+    position: u64,
+    /// The hold window the current factor was drawn in.
+    window: Option<u64>,
+    rng: StdRng,
     midibox: Box<dyn Midibox>,
 }
 
 impl RandomVelocity {
     pub fn wrap(midibox: Box<dyn Midibox>) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, None, StdRng::from_entropy())
+    }
+
+    /// Like `wrap`, with the same velocities every time for the same seed.
+    pub fn wrap_seeded(midibox: Box<dyn Midibox>, seed: u64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, None, StdRng::seed_from_u64(seed))
+    }
+
+    /// Like `wrap`, but holds each random factor for `hold_ticks` ticks (e.g. one beat) so that
+    /// tick-split sequences don't change velocity on every tick.
+    pub fn wrap_held(midibox: Box<dyn Midibox>, hold_ticks: u32) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, Some(hold_ticks.max(1)), StdRng::from_entropy())
+    }
+
+    /// Like `wrap_held`, with the same velocities every time for the same seed.
+    pub fn wrap_held_seeded(midibox: Box<dyn Midibox>, hold_ticks: u32, seed: u64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, Some(hold_ticks.max(1)), StdRng::seed_from_u64(seed))
+    }
+
+    fn wrap_with_rng(midibox: Box<dyn Midibox>, hold_ticks: Option<u32>, rng: StdRng) -> Box<dyn Midibox> {
         Box::new(RandomVelocity {
             factor: 1_f64,
+            hold_ticks,
+            position: 0,
+            window: None,
+            rng,
             midibox
         })
     }
@@ -18,14 +54,618 @@ impl RandomVelocity {
 
 impl Midibox for RandomVelocity {
     fn next(&mut self) -> Option<Vec<Midi>> {
-        let v = rand::thread_rng().gen_range(0..99);
-        self.factor = (v as f64) / (100_f64);
+        let window = self.hold_ticks.map(|hold| self.position / hold as u64);
+        if window.is_none() || window != self.window {
+            let v = self.rng.gen_range(0..99);
+            self.factor = (v as f64) / (100_f64);
+            self.window = window;
+        }
+        let notes = self.midibox.next();
+        if let Some(notes) = &notes {
+            self.position += notes.iter().map(|n| n.duration).max().unwrap_or(0) as u64;
+        }
+        notes.map(|it|
+                it.into_iter()
+                    .map(|note| {
+                        note.set_velocity((note.velocity as f64 * self.factor) as u8)
+                    }).collect::<Vec<Midi>>()
+            )
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// Nudges velocities up or down by a random amount so repeated notes don't all hit identically.
+pub struct HumanizeVelocity {
+    /// The most a velocity is moved in either direction.
+    amount: u8,
+    /// When set, all notes emitted together share one offset, so chords keep their internal
+    /// balance and only vary as a whole.
+    chord_coherent: bool,
+    rng: StdRng,
+    midibox: Box<dyn Midibox>,
+}
+
+impl HumanizeVelocity {
+    pub fn wrap(midibox: Box<dyn Midibox>, amount: u8) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, amount, false, StdRng::from_entropy())
+    }
+
+    /// Like `wrap`, with the same offsets every time for the same seed.
+    pub fn wrap_seeded(midibox: Box<dyn Midibox>, amount: u8, seed: u64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, amount, false, StdRng::seed_from_u64(seed))
+    }
+
+    pub fn wrap_chord_coherent(midibox: Box<dyn Midibox>, amount: u8) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, amount, true, StdRng::from_entropy())
+    }
+
+    /// Like `wrap_chord_coherent`, with the same offsets every time for the same seed.
+    pub fn wrap_chord_coherent_seeded(midibox: Box<dyn Midibox>, amount: u8, seed: u64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, amount, true, StdRng::seed_from_u64(seed))
+    }
+
+    fn wrap_with_rng(
+        midibox: Box<dyn Midibox>,
+        amount: u8,
+        chord_coherent: bool,
+        rng: StdRng
+    ) -> Box<dyn Midibox> {
+        Box::new(HumanizeVelocity {
+            amount,
+            chord_coherent,
+            rng,
+            midibox
+        })
+    }
+
+    fn offset(&mut self) -> i16 {
+        let amount = self.amount as i16;
+        self.rng.gen_range(-amount..=amount)
+    }
+}
+
+impl Midibox for HumanizeVelocity {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let shared = self.offset();
         self.midibox.next()
             .map(|it|
                 it.into_iter()
                     .map(|note| {
-                        note.set_velocity((note.velocity as f64 * self.factor) as u8)
+                        if note.is_rest() {
+                            return note;
+                        }
+                        let offset = if self.chord_coherent { shared } else { self.offset() };
+                        note.set_velocity((note.velocity as i16 + offset).clamp(1, 127) as u8)
                     }).collect::<Vec<Midi>>()
             )
     }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// Mutes each note the wrapped midibox emits at random, keeping only a `probability` share of
+/// them. Muted notes become rests of the same duration so the rhythm is unchanged; rests and
+/// control events are passed through. A probability that isn't finite counts as zero.
+pub struct ProbabilityGate {
+    probability: f64,
+    rng: StdRng,
+    midibox: Box<dyn Midibox>,
+}
+
+impl ProbabilityGate {
+    pub fn wrap(midibox: Box<dyn Midibox>, probability: f64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, probability, StdRng::from_entropy())
+    }
+
+    /// Like `wrap`, with the same notes muted every time for the same seed.
+    pub fn wrap_seeded(midibox: Box<dyn Midibox>, probability: f64, seed: u64) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, probability, StdRng::seed_from_u64(seed))
+    }
+
+    fn wrap_with_rng(midibox: Box<dyn Midibox>, probability: f64, rng: StdRng) -> Box<dyn Midibox> {
+        Box::new(ProbabilityGate {
+            probability: if probability.is_finite() { probability.clamp(0.0, 1.0) } else { 0.0 },
+            rng,
+            midibox
+        })
+    }
+}
+
+impl Midibox for ProbabilityGate {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        self.midibox.next()
+            .map(|it|
+                it.into_iter()
+                    .map(|note| {
+                        if note.is_rest() || self.rng.gen_bool(self.probability) {
+                            note
+                        } else {
+                            Midi::rest().set_duration(note.duration)
+                        }
+                    }).collect::<Vec<Midi>>()
+            )
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// Now and then plays the wrapped midibox's last emission again instead of moving on, for the
+/// glitchy repeats of electronic music.
+///
+/// Before each pull from the wrapped midibox, the last emission is repeated with likelihood
+/// `probability`, up to `max_repeats` times in a row. Repeats are exact copies, durations
+/// included, so each one adds the length of the repeated step to the timeline and the wrapped
+/// midibox carries on from where it was afterwards. A probability that isn't finite counts as
+/// zero.
+pub struct Stutter {
+    probability: f64,
+    max_repeats: usize,
+    /// How many times in a row `last` has been repeated.
+    repeats: usize,
+    last: Option<Vec<Midi>>,
+    rng: StdRng,
+    midibox: Box<dyn Midibox>,
+}
+
+impl Stutter {
+    pub fn wrap(midibox: Box<dyn Midibox>, probability: f64, max_repeats: usize) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, probability, max_repeats, StdRng::from_entropy())
+    }
+
+    /// Like `wrap`, with the same steps repeated every time for the same seed.
+    pub fn wrap_seeded(
+        midibox: Box<dyn Midibox>,
+        probability: f64,
+        max_repeats: usize,
+        seed: u64
+    ) -> Box<dyn Midibox> {
+        Self::wrap_with_rng(midibox, probability, max_repeats, StdRng::seed_from_u64(seed))
+    }
+
+    fn wrap_with_rng(
+        midibox: Box<dyn Midibox>,
+        probability: f64,
+        max_repeats: usize,
+        rng: StdRng
+    ) -> Box<dyn Midibox> {
+        Box::new(Stutter {
+            probability: if probability.is_finite() { probability.clamp(0.0, 1.0) } else { 0.0 },
+            max_repeats,
+            repeats: 0,
+            last: None,
+            rng,
+            midibox,
+        })
+    }
+}
+
+impl Midibox for Stutter {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        if let Some(last) = &self.last {
+            if self.repeats < self.max_repeats && self.rng.gen_bool(self.probability) {
+                self.repeats += 1;
+                return Some(last.clone());
+            }
+        }
+        let emission = self.midibox.next()?;
+        self.last = Some(emission.clone());
+        self.repeats = 0;
+        Some(emission)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.midibox.is_finished()
+    }
+}
+
+/// A generative melody that wanders up and down a scale, emitting one random in-key note per
+/// step.
+///
+/// The walk covers `range` scale degrees above `root`, which must be in the scale. Each step
+/// moves by a number of degrees drawn from the step weights, up or down at random, turning back
+/// at either end of the range. Every note keeps the velocity, duration and channel of `root`.
+pub struct RandomScaleWalk {
+    scale: Scale,
+    root: Midi,
+    range: usize,
+    /// The relative likelihood of moving by each number of degrees, starting with staying put.
+    step_weights: Vec<u32>,
+    /// The current scale degree above the root.
+    position: usize,
+    rng: StdRng,
+}
+
+impl RandomScaleWalk {
+    pub fn new(scale: Scale, root: Midi, range: usize) -> Self {
+        RandomScaleWalk {
+            scale,
+            root,
+            range,
+            step_weights: vec![1, 4, 2, 1],
+            position: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Makes the walk take the same steps every time for the same seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Sets how likely each step size is: `weights[n]` is the weight of moving `n` degrees. The
+    /// default favours moving by a single degree, now and then by a third or a fourth.
+    pub fn with_step_weights(mut self, weights: Vec<u32>) -> Self {
+        if weights.iter().any(|w| *w > 0) {
+            self.step_weights = weights;
+        }
+        self
+    }
+
+    fn step_size(&mut self) -> usize {
+        let total: u32 = self.step_weights.iter().sum();
+        let mut pick = self.rng.gen_range(0..total);
+        self.step_weights.iter()
+            .position(|weight| {
+                if pick < *weight {
+                    return true;
+                }
+                pick -= weight;
+                false
+            })
+            .unwrap_or(0)
+    }
+
+    /// The note `degrees` scale degrees above the root, going up whole octaves past the
+    /// thirteenth. A rest if it falls outside the MIDI range.
+    fn pitch(&self, degrees: usize) -> Midi {
+        let octaves = (degrees / 7) as u8;
+        let degree = Degree::from_number((degrees % 7) as u8 + 1).unwrap_or(Degree::Unison);
+        let pitch = self.scale.harmonize_up(self.root, degree)
+            .and_then(|note| note.u8_maybe())
+            .and_then(|pitch| octaves.checked_mul(12).and_then(|up| pitch.checked_add(up)))
+            .filter(|pitch| *pitch <= 127);
+        self.root.set_pitch_u8(pitch)
+    }
+}
+
+impl Midibox for RandomScaleWalk {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let step = self.step_size();
+        let coin = self.rng.gen_bool(0.5);
+        let room_up = self.range - self.position;
+        let room_down = self.position;
+        // turn back at the ends, and shorten a step that doesn't fit either way
+        let up = match (step <= room_up, step <= room_down) {
+            (true, true) => coin,
+            (false, false) => room_up >= room_down,
+            (fits_up, _) => fits_up,
+        };
+        self.position = if up {
+            self.position + step.min(room_up)
+        } else {
+            self.position - step.min(room_down)
+        };
+        Some(vec![self.pitch(self.position)])
+    }
+}
+
+/// Emits one of several candidate notes at a time, each picked with a likelihood in proportion
+/// to its weight, e.g. a step that is usually a kick but now and then a tom or a rest.
+///
+/// Rests are candidates like any other. Weights that are negative or not a number count as zero,
+/// and with no positive weight at all nothing is emitted: the midibox is finished from the
+/// start (see `Midibox::is_finished`).
+pub struct WeightedChoice {
+    choices: Vec<Midi>,
+    /// Picks an index into `choices`, or `None` when no choice can be picked.
+    weights: Option<WeightedIndex<f64>>,
+    rng: StdRng,
+}
+
+impl WeightedChoice {
+    pub fn new(choices: Vec<(Midi, f64)>) -> Self {
+        let weights = WeightedIndex::new(choices.iter().map(|(_, weight)| {
+            if *weight > 0.0 && weight.is_finite() { *weight } else { 0.0 }
+        })).ok();
+        WeightedChoice {
+            choices: choices.into_iter().map(|(note, _)| note).collect(),
+            weights,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Makes the same picks every time for the same seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl Midibox for WeightedChoice {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let index = self.weights.as_ref()?.sample(&mut self.rng);
+        Some(vec![self.choices[index]])
+    }
+
+    fn is_finished(&self) -> bool {
+        self.weights.is_none()
+    }
+}
+
+/// Generates an endless melody from first-order transitions learned from a training sequence.
+///
+/// Each note is followed by one of the notes that followed its pitch in the training sequence,
+/// chosen with the same frequency, and is emitted as it was written there. A pitch that is never
+/// followed by anything, such as the last note, moves to one of the training pitches chosen
+/// uniformly instead. Rests count as a pitch. The melody starts on the first training note, and
+/// the same training sequence and seed always produce the same melody.
+pub struct MarkovMidibox {
+    training: Vec<Midi>,
+    /// For each pitch, the index in the training sequence of every note that followed it.
+    transitions: BTreeMap<Option<u8>, Vec<usize>>,
+    /// The index in the training sequence of the first note of each distinct pitch.
+    pitches: Vec<usize>,
+    rng: StdRng,
+    /// The index of the note emitted last, if any.
+    current: Option<usize>,
+}
+
+impl MarkovMidibox {
+    pub fn new(training: Vec<Midi>, seed: u64) -> Self {
+        let mut transitions: BTreeMap<Option<u8>, Vec<usize>> = BTreeMap::new();
+        for (i, pair) in training.windows(2).enumerate() {
+            transitions.entry(pair[0].u8_maybe()).or_default().push(i + 1);
+        }
+        let mut pitches: Vec<usize> = Vec::new();
+        for (i, note) in training.iter().enumerate() {
+            if pitches.iter().all(|p| training[*p].u8_maybe() != note.u8_maybe()) {
+                pitches.push(i);
+            }
+        }
+        MarkovMidibox {
+            training,
+            transitions,
+            pitches,
+            rng: StdRng::seed_from_u64(seed),
+            current: None,
+        }
+    }
+}
+
+impl Midibox for MarkovMidibox {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let next = match self.current {
+            None if self.training.is_empty() => return None,
+            None => 0,
+            Some(current) => {
+                let choices = self.transitions.get(&self.training[current].u8_maybe())
+                    .unwrap_or(&self.pitches);
+                choices[self.rng.gen_range(0..choices.len())]
+            }
+        };
+        self.current = Some(next);
+        Some(vec![self.training[next]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::Midibox;
+    use crate::collect_emissions;
+    use crate::midi::Midi;
+    use crate::rand::{HumanizeVelocity, MarkovMidibox, ProbabilityGate, RandomScaleWalk, RandomVelocity, Stutter, WeightedChoice};
+    use crate::scale::Scale;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn held_factor_is_stable_within_a_beat() {
+        let mut midibox = RandomVelocity::wrap_held(
+            Seq::new(vec![Tone::C.oct(4) * 1]).velocity(100).midibox(),
+            4
+        );
+        let beats: Vec<Vec<u8>> = (0..16)
+            .map(|_| (0..4).map(|_| midibox.next().unwrap()[0].velocity).collect())
+            .collect();
+
+        for beat in &beats {
+            assert!(beat.iter().all(|v| *v == beat[0]), "velocity changed within a beat: {:?}", beat);
+        }
+        assert!(beats.iter().any(|beat| beat[0] != beats[0][0]), "velocity never changed across beats");
+    }
+
+    #[test]
+    fn seeded_boxes_repeat_their_streams() {
+        let seq = Seq::new(vec![Tone::C.oct(4) * 1, Tone::E.oct(4) * 1]).velocity(100);
+        let velocities = |mut midibox: Box<dyn Midibox>| -> Vec<u8> {
+            (0..32).map(|_| midibox.next().unwrap()[0].velocity).collect()
+        };
+        let random = velocities(RandomVelocity::wrap_seeded(seq.midibox(), 11));
+        assert_eq!(random, velocities(RandomVelocity::wrap_seeded(seq.midibox(), 11)));
+        assert_ne!(random, velocities(RandomVelocity::wrap_seeded(seq.midibox(), 12)));
+        assert_eq!(
+            velocities(HumanizeVelocity::wrap_seeded(seq.midibox(), 20, 5)),
+            velocities(HumanizeVelocity::wrap_seeded(seq.midibox(), 20, 5))
+        );
+
+        let walk = |seed| collect_emissions(
+            &mut RandomScaleWalk::new(Scale::major(Tone::C), Tone::C.oct(4), 7).with_seed(seed),
+            32
+        );
+        assert_eq!(walk(3), walk(3));
+    }
+
+    #[test]
+    fn chord_coherent_humanize_keeps_chords_balanced() {
+        let chord = Chord::new(vec![Tone::C.oct(4), Tone::E.oct(4), Tone::G.oct(4)]);
+        let mut midibox = HumanizeVelocity::wrap_chord_coherent(
+            Seq::chords(vec![chord]).velocity(64).midibox(),
+            20
+        );
+        let mut chord_velocities: Vec<u8> = Vec::new();
+        for _ in 0..32 {
+            let velocities: Vec<u8> = midibox.next().unwrap().iter().map(|n| n.velocity).collect();
+            let spread = velocities.iter().max().unwrap() - velocities.iter().min().unwrap();
+            assert!(spread <= 1, "chord spread {:?}", velocities);
+            assert!(velocities.iter().all(|v| (44..=84).contains(v)));
+            chord_velocities.push(velocities[0]);
+        }
+        let between = chord_velocities.iter().max().unwrap() - chord_velocities.iter().min().unwrap();
+        assert!(between > 1, "chords never varied: {:?}", chord_velocities);
+    }
+
+    #[test]
+    fn humanize_leaves_rests_alone() {
+        let mut midibox = HumanizeVelocity::wrap(Seq::new(vec![Tone::Rest * 1]).midibox(), 20);
+        for _ in 0..8 {
+            assert_eq!(midibox.next(), Some(vec![Tone::Rest * 1]));
+        }
+    }
+
+    #[test]
+    fn scale_walk_stays_in_key_and_in_range() {
+        let scale = Scale::major(Tone::C);
+        let root = Tone::C.oct(4).set_velocity(80) * 2;
+        let mut walk = RandomScaleWalk::new(scale.clone(), root, 9).with_step_weights(vec![0, 1]);
+        let in_key = scale.tones();
+        let mut previous = 0_usize;
+        for _ in 0..64 {
+            let note = walk.next().unwrap()[0];
+            assert!(in_key.contains(&note.tone), "{:?} is out of key", note);
+            assert_eq!((note.velocity, note.duration), (80, 2));
+            let pitch = note.u8_maybe().unwrap();
+            // nine degrees above middle C is the E an octave up
+            assert!((60..=76).contains(&pitch), "{} is out of range", pitch);
+            let degree = scale.midi(4).into_iter().chain(scale.midi(5))
+                .position(|m| m.u8_maybe() == Some(pitch))
+                .unwrap();
+            assert_eq!(degree.abs_diff(previous), 1, "moved by more than a step");
+            previous = degree;
+        }
+    }
+
+    #[test]
+    fn scale_walk_keeps_long_steps_in_range() {
+        let scale = Scale::major(Tone::C);
+        for seed in 0..16 {
+            // mostly steps of three degrees, which from the middle of the range fit neither way
+            let mut walk = RandomScaleWalk::new(scale.clone(), Tone::C.oct(4), 4)
+                .with_step_weights(vec![0, 1, 2, 4])
+                .with_seed(seed);
+            for _ in 0..32 {
+                let pitch = walk.next().unwrap()[0].u8_maybe().unwrap();
+                // four degrees above middle C is the G
+                assert!((60..=67).contains(&pitch), "{} is out of range", pitch);
+            }
+        }
+    }
+
+    #[test]
+    fn markov_melody_follows_learned_transitions() {
+        let training = vec![
+            Tone::C.oct(4) * 1, Tone::D.oct(4) * 2, Tone::C.oct(4) * 1, Tone::E.oct(4) * 1,
+            Tone::Rest * 1, Tone::G.oct(4) * 4,
+        ];
+        let melody = collect_emissions(&mut MarkovMidibox::new(training.clone(), 7), 64);
+        assert_eq!(melody, collect_emissions(&mut MarkovMidibox::new(training.clone(), 7), 64));
+        assert_ne!(melody, collect_emissions(&mut MarkovMidibox::new(training.clone(), 8), 64));
+
+        assert_eq!(melody[0], vec![Tone::C.oct(4) * 1]);
+        for pair in melody.windows(2) {
+            let (from, to) = (pair[0][0], pair[1][0]);
+            assert!(training.contains(&to));
+            match from.tone {
+                Tone::C => assert!(to == Tone::D.oct(4) * 2 || to == Tone::E.oct(4) * 1),
+                Tone::D => assert_eq!(to, Tone::C.oct(4) * 1),
+                Tone::E => assert!(to.is_rest()),
+                Tone::Rest => assert_eq!(to, Tone::G.oct(4) * 4),
+                _ => {}
+            }
+        }
+        assert!(MarkovMidibox::new(vec![], 7).next().is_none());
+    }
+
+    #[test]
+    fn probability_gate_mutes_notes_in_proportion() {
+        let chord = Chord::new(vec![Tone::C.oct(4) * 2, Tone::E.oct(4) * 3]);
+        let gated = |seed| collect_emissions(
+            ProbabilityGate::wrap_seeded(Seq::chords(vec![chord.clone()]).midibox(), 0.25, seed).as_mut(),
+            2000
+        );
+        let emissions = gated(3);
+        assert_eq!(emissions, gated(3));
+        for emission in &emissions {
+            // muted notes keep their duration
+            assert_eq!(emission.iter().map(|n| n.duration).collect::<Vec<u32>>(), vec![2, 3]);
+        }
+        let kept = emissions.iter().flatten().filter(|note| !note.is_rest()).count();
+        assert!((900..1100).contains(&kept), "kept {} of 4000 notes", kept);
+
+        assert!(collect_emissions(ProbabilityGate::wrap(Seq::chords(vec![chord.clone()]).midibox(), 0.0).as_mut(), 8)
+            .iter().flatten().all(|note| note.is_rest()));
+        // not a probability, so nothing gets through
+        assert!(collect_emissions(ProbabilityGate::wrap(Seq::chords(vec![chord]).midibox(), f64::NAN).as_mut(), 8)
+            .iter().flatten().all(|note| note.is_rest()));
+    }
+
+    #[test]
+    fn weighted_choice_picks_in_proportion_to_the_weights() {
+        let choices = vec![
+            (Tone::C.oct(2), 3.0),
+            (Tone::D.oct(2), 0.0),
+            (Midi::rest(), 1.0),
+            (Tone::E.oct(2), -2.0),
+        ];
+        let picks = |seed| collect_emissions(
+            &mut WeightedChoice::new(choices.clone()).with_seed(seed),
+            4000
+        );
+        let emissions = picks(5);
+        assert_eq!(emissions, picks(5));
+        assert!(emissions.iter().all(|e| e == &vec![Tone::C.oct(2)] || e == &vec![Midi::rest()]));
+        let rests = emissions.iter().filter(|e| e[0].is_rest()).count();
+        assert!((900..1100).contains(&rests), "picked {} rests of 4000", rests);
+
+        assert!(WeightedChoice::new(vec![(Tone::C.oct(2), 0.0)]).next().is_none());
+        assert!(WeightedChoice::new(vec![]).next().is_none());
+        assert!(WeightedChoice::new(vec![]).is_finished());
+        assert!(!WeightedChoice::new(choices).is_finished());
+    }
+
+    #[test]
+    fn stutter_repeats_whole_steps_a_limited_number_of_times() {
+        let seq = Seq::new(vec![Tone::C.oct(4) * 2, Tone::D.oct(4) * 1, Tone::E.oct(4) * 3]);
+        let stuttered = |seed| collect_emissions(Stutter::wrap_seeded(seq.midibox(), 0.5, 2, seed).as_mut(), 300);
+        let emissions = stuttered(9);
+        assert_eq!(emissions, stuttered(9));
+        assert_eq!(emissions.len(), 300);
+        let steps = seq.midibox().cycle().unwrap();
+        let mut step = 0;
+        let mut run = 0;
+        for (i, emission) in emissions.iter().enumerate() {
+            // each emission is either a repeat of the last, durations and all, or the next step
+            if i > 0 && emission == &emissions[i - 1] && run < 2 {
+                run += 1;
+            } else {
+                assert_eq!(emission, &steps[step % steps.len()]);
+                step += 1;
+                run = 0;
+            }
+        }
+        assert!(step < 300, "nothing was repeated");
+
+        let never = collect_emissions(Stutter::wrap_seeded(seq.midibox(), 1.0, 0, 1).as_mut(), 6);
+        assert_eq!(never, collect_emissions(seq.midibox().as_mut(), 6));
+        let unset = collect_emissions(Stutter::wrap_seeded(seq.midibox(), f64::NAN, 2, 1).as_mut(), 6);
+        assert_eq!(unset, collect_emissions(seq.midibox().as_mut(), 6));
+    }
 }
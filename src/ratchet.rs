@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// Retriggers the steps the wrapped midibox emits, splitting each into a number of equal,
+/// shorter copies for rolls and stutters.
+///
+/// `pattern` gives the number of retriggers for each step in turn, and repeats; a count of 1
+/// (or 0) leaves the step alone. Pitch and velocity are kept, and the copies add up to the
+/// step's length, with any remainder going to the first ones. A note shorter than a copy stays
+/// as long as it was, and copies are never tied. A step can't be split into copies shorter than
+/// a tick. Control events in a split step are sent once, with the first copy. Rests don't sound
+/// but still take their place in the pattern, while zero-duration steps such as control events
+/// pass through without using one up.
+pub struct Ratchet {
+    pattern: Vec<u32>,
+    /// The index into the pattern for the next step.
+    position: usize,
+    /// The copies of the current step not yet emitted.
+    pending: VecDeque<Vec<Midi>>,
+    midibox: Box<dyn Midibox>,
+}
+
+impl Ratchet {
+    pub fn wrap(midibox: Box<dyn Midibox>, pattern: Vec<u32>) -> Box<dyn Midibox> {
+        Box::new(Ratchet {
+            pattern,
+            position: 0,
+            pending: VecDeque::new(),
+            midibox,
+        })
+    }
+
+    fn next_count(&mut self) -> u32 {
+        if self.pattern.is_empty() {
+            return 1;
+        }
+        let count = self.pattern[self.position % self.pattern.len()];
+        self.position = (self.position + 1) % self.pattern.len();
+        count
+    }
+}
+
+impl Midibox for Ratchet {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        if let Some(copy) = self.pending.pop_front() {
+            return Some(copy);
+        }
+        let notes = self.midibox.next()?;
+        let step_ticks = notes.iter().map(|n| n.duration).max().unwrap_or(0);
+        if step_ticks == 0 {
+            return Some(notes);
+        }
+        let count = self.next_count().clamp(1, step_ticks);
+        if count == 1 || notes.iter().all(|note| note.is_rest()) {
+            return Some(notes);
+        }
+        self.pending = (0..count).map(|i| {
+            let length = step_ticks / count + if i < step_ticks % count { 1 } else { 0 };
+            notes.iter()
+                .filter(|note| i == 0 || note.control.is_none())
+                .map(|note| match note.control {
+                    Some(_) => *note,
+                    None => note.set_duration(note.duration.min(length)).set_tie(false),
+                })
+                .collect()
+        }).collect();
+        self.pending.pop_front()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.midibox.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collect_emissions;
+    use crate::midi::{Control, Midi};
+    use crate::ratchet::Ratchet;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+
+    #[test]
+    fn ratchets_follow_the_pattern() {
+        let c = Tone::C.oct(2).set_velocity(90);
+        let seq = Seq::new(vec![c * 4, c * 4, Tone::Rest * 2, c * 5, c * 2]);
+        let emissions = collect_emissions(Ratchet::wrap(seq.midibox(), vec![1, 4, 2, 2, 3]).as_mut(), 12);
+        assert_eq!(emissions, vec![
+            vec![c * 4],
+            vec![c * 1], vec![c * 1], vec![c * 1], vec![c * 1],
+            vec![Tone::Rest * 2],
+            vec![c * 3], vec![c * 2],
+            // can't split two ticks three ways
+            vec![c * 1], vec![c * 1],
+            // the pattern starts over with the sequence
+            vec![c * 4],
+            vec![c * 1],
+        ]);
+    }
+
+    #[test]
+    fn control_events_go_with_the_first_copy() {
+        let c = Tone::C.oct(2);
+        let bend = Midi::control(Control::PitchBend(512));
+        let seq = Seq::from_chords(vec![vec![c * 2, bend]]);
+        let emissions = collect_emissions(Ratchet::wrap(seq.midibox(), vec![2]).as_mut(), 2);
+        assert_eq!(emissions, vec![vec![c * 1, bend], vec![c * 1]]);
+    }
+
+    #[test]
+    fn copies_are_not_tied() {
+        let c = Tone::C.oct(2).set_tie(true);
+        let seq = Seq::new(vec![c * 4]);
+        let emissions = collect_emissions(Ratchet::wrap(seq.midibox(), vec![2]).as_mut(), 2);
+        assert_eq!(emissions, vec![vec![c.set_tie(false) * 2], vec![c.set_tie(false) * 2]]);
+    }
+
+    #[test]
+    fn short_notes_keep_their_length() {
+        let (c, e) = (Tone::C.oct(2), Tone::E.oct(2));
+        let seq = Seq::from_chords(vec![vec![c * 4, e * 1]]);
+        let emissions = collect_emissions(Ratchet::wrap(seq.midibox(), vec![2]).as_mut(), 2);
+        assert_eq!(emissions, vec![vec![c * 2, e * 1], vec![c * 2, e * 1]]);
+    }
+}
@@ -1,8 +1,26 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+use crate::midi::{Midi, NOTE_OFF_MSG, NOTE_ON_MSG};
 
 pub trait Router: Send + Sync {
-    fn route(&self, channel_id: usize) -> Option<&usize>;
+    fn route(&self, channel_id: usize) -> Option<usize>;
     fn required_ports(&self) -> HashSet<usize>;
+
+    /// The port for one message of a note on a channel, `midi_status` being `NOTE_ON_MSG` or
+    /// `NOTE_OFF_MSG`. The player routes every message through this, so routers that place each
+    /// note individually can follow note-ons and note-offs; by default it's the channel's port.
+    fn route_note(&self, channel_id: usize, _note: &Midi, _midi_status: u8) -> Option<usize> {
+        self.route(channel_id)
+    }
+
+    /// Called with the name of every output port, by index, before any is connected to, for
+    /// routers that pick their ports by name. Does nothing by default.
+    fn resolve_ports(&mut self, _port_names: &[String]) -> Result<(), PortNameError> {
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -19,8 +37,8 @@ impl StaticRouter {
 }
 
 impl Router for StaticRouter {
-    fn route(&self, _: usize) -> Option<&usize> {
-        Some(&(self.port_id))
+    fn route(&self, _: usize) -> Option<usize> {
+        Some(self.port_id)
     }
 
     fn required_ports(&self) -> HashSet<usize> {
@@ -44,8 +62,8 @@ impl MapRouter {
 }
 
 impl Router for MapRouter {
-    fn route(&self, channel_id: usize) -> Option<&usize>  {
-        return self.channel_id_to_port_id.get(&channel_id);
+    fn route(&self, channel_id: usize) -> Option<usize>  {
+        self.channel_id_to_port_id.get(&channel_id).copied()
     }
 
     fn required_ports(&self) -> HashSet<usize> {
@@ -55,3 +73,337 @@ impl Router for MapRouter {
     }
 }
 
+/// A channel-to-port mapping that can be changed while the player is running, e.g. to move a
+/// part to a different synth mid-song.
+///
+/// Clones share the same mapping, so a clone can be kept to make edits while the original is
+/// handed to the player; each note is routed by the mapping as it stands when it's sent. Only
+/// the ports that are required when playback starts are opened, so list any port a channel
+/// might move to later with `with_spare_ports`.
+#[derive(Clone, Default)]
+pub struct DynamicRouter {
+    channel_id_to_port_id: Arc<Mutex<HashMap<usize, usize>>>,
+    spare_ports: HashSet<usize>,
+}
+
+impl DynamicRouter {
+    pub fn new(channel_id_to_port_id: HashMap<usize, usize>) -> Self {
+        DynamicRouter {
+            channel_id_to_port_id: Arc::new(Mutex::new(channel_id_to_port_id)),
+            spare_ports: HashSet::new(),
+        }
+    }
+
+    /// Adds ports that aren't mapped to yet to the required ports, so they're open for when a
+    /// channel is moved to them.
+    pub fn with_spare_ports(mut self, ports: impl IntoIterator<Item = usize>) -> Self {
+        self.spare_ports.extend(ports);
+        self
+    }
+
+    /// Sends `channel_id` to `port_id` from now on.
+    pub fn set(&self, channel_id: usize, port_id: usize) {
+        self.channel_id_to_port_id.lock().unwrap().insert(channel_id, port_id);
+    }
+
+    /// Stops routing `channel_id`, so its notes are dropped.
+    pub fn remove(&self, channel_id: usize) {
+        self.channel_id_to_port_id.lock().unwrap().remove(&channel_id);
+    }
+}
+
+impl Router for DynamicRouter {
+    fn route(&self, channel_id: usize) -> Option<usize> {
+        self.channel_id_to_port_id.lock().unwrap().get(&channel_id).copied()
+    }
+
+    fn required_ports(&self) -> HashSet<usize> {
+        let mut distinct_port_ids: HashSet<usize> = self.spare_ports.clone();
+        distinct_port_ids.extend(self.channel_id_to_port_id.lock().unwrap().values());
+        distinct_port_ids
+    }
+}
+
+/// A velocity response applied to every note sent to a port, to even out synths that react to
+/// velocity differently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    /// Velocities are sent unchanged.
+    Linear,
+    /// Velocities are raised to this power, relative to the full range: values above 1 soften
+    /// quiet notes, values below 1 bring them up.
+    Gamma(f64),
+    /// Every note is sent at this velocity, for instruments without velocity response.
+    Fixed(u8),
+    /// Velocities are pulled towards an S-shaped curve by this amount from 0 to 1, spreading out
+    /// the middle of the range and squeezing the extremes.
+    SCurve(f64),
+}
+
+impl VelocityCurve {
+    /// Maps a note-on velocity through the curve. Notes that sound stay above zero, since a
+    /// zero-velocity note-on is read as a note-off.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let curved = match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Gamma(gamma) => {
+                (127.0 * (velocity.min(127) as f64 / 127.0).powf(*gamma)).round() as u8
+            }
+            VelocityCurve::Fixed(fixed) => *fixed,
+            VelocityCurve::SCurve(amount) => {
+                let x = velocity.min(127) as f64 / 127.0;
+                let smooth = x * x * (3.0 - 2.0 * x);
+                let amount = amount.clamp(0.0, 1.0);
+                (127.0 * (x + (smooth - x) * amount)).round() as u8
+            }
+        };
+        curved.clamp(1, 127)
+    }
+}
+
+/// How a `NameRouter` matches the port names it's given against the output ports.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NameMatch {
+    /// The output port's name must be the given name.
+    #[default]
+    Exact,
+    /// The output port's name must contain the given name, ignoring case, e.g. "drumlogue"
+    /// for "drumlogue:drumlogue MIDI 1 28:0".
+    Substring,
+}
+
+impl NameMatch {
+    fn matches(&self, name: &str, port_name: &str) -> bool {
+        match self {
+            NameMatch::Exact => port_name == name,
+            NameMatch::Substring => port_name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+/// Why a `NameRouter` couldn't find the port for a name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortNameError {
+    /// No output port matches the name.
+    NotFound { name: String, available: Vec<String> },
+    /// More than one output port matches the name.
+    Ambiguous { name: String, candidates: Vec<String> },
+}
+
+impl Display for PortNameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortNameError::NotFound { name, available } => write!(
+                f, "no MIDI output port matches {:?}; available: {}", name, available.join(", ")
+            ),
+            PortNameError::Ambiguous { name, candidates } => write!(
+                f, "several MIDI output ports match {:?}: {}", name, candidates.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for PortNameError {}
+
+/// Routes channels to output ports by name rather than index, as indices change when devices
+/// are plugged in and out.
+///
+/// The names are looked up when playback starts (see `Router::resolve_ports`); until then no
+/// channel has a port. Each name must match exactly one port.
+pub struct NameRouter {
+    /// The port name of each channel routed on its own.
+    channel_id_to_name: HashMap<usize, String>,
+    /// The port name of every other channel.
+    default_name: Option<String>,
+    matching: NameMatch,
+    /// The resolved port of each channel in `channel_id_to_name`.
+    channel_id_to_port_id: HashMap<usize, usize>,
+    default_port_id: Option<usize>,
+}
+
+impl NameRouter {
+    pub fn new(channel_id_to_name: HashMap<usize, String>) -> Self {
+        NameRouter {
+            channel_id_to_name,
+            default_name: None,
+            matching: NameMatch::default(),
+            channel_id_to_port_id: HashMap::new(),
+            default_port_id: None,
+        }
+    }
+
+    /// Routes every channel to the port called `name`.
+    pub fn for_all(name: &str) -> Self {
+        NameRouter { default_name: Some(name.to_string()), ..Self::new(HashMap::new()) }
+    }
+
+    pub fn with_matching(mut self, matching: NameMatch) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    fn find(&self, name: &str, port_names: &[String]) -> Result<usize, PortNameError> {
+        let candidates: Vec<usize> = (0..port_names.len())
+            .filter(|i| self.matching.matches(name, &port_names[*i]))
+            .collect();
+        match candidates[..] {
+            [port_id] => Ok(port_id),
+            [] => Err(PortNameError::NotFound { name: name.to_string(), available: port_names.to_vec() }),
+            _ => Err(PortNameError::Ambiguous {
+                name: name.to_string(),
+                candidates: candidates.iter().map(|i| port_names[*i].clone()).collect(),
+            }),
+        }
+    }
+}
+
+impl Router for NameRouter {
+    fn route(&self, channel_id: usize) -> Option<usize> {
+        self.channel_id_to_port_id.get(&channel_id).copied().or(self.default_port_id)
+    }
+
+    fn required_ports(&self) -> HashSet<usize> {
+        self.channel_id_to_port_id.values().copied().chain(self.default_port_id).collect()
+    }
+
+    fn resolve_ports(&mut self, port_names: &[String]) -> Result<(), PortNameError> {
+        let mut channel_id_to_port_id = HashMap::new();
+        for (channel_id, name) in &self.channel_id_to_name {
+            channel_id_to_port_id.insert(*channel_id, self.find(name, port_names)?);
+        }
+        self.default_port_id = match &self.default_name {
+            Some(name) => Some(self.find(name, port_names)?),
+            None => None,
+        };
+        self.channel_id_to_port_id = channel_id_to_port_id;
+        Ok(())
+    }
+}
+
+/// Spreads the notes of each channel across a pool of ports in turn, so several identical
+/// monophonic synths can play chords and overlapping notes between them.
+///
+/// Each note-on goes to the port after the one the channel's previous note went to, and its
+/// note-off follows it there. Control events go to the first port in the pool.
+pub struct RoundRobinRouter {
+    pool: Vec<usize>,
+    state: Mutex<RoundRobin>,
+}
+
+#[derive(Default)]
+struct RoundRobin {
+    /// The index into the pool of each channel's next port.
+    next: HashMap<usize, usize>,
+    /// The ports of the notes sounding on each channel and pitch, oldest first.
+    sounding: HashMap<(usize, Option<u8>), VecDeque<usize>>,
+}
+
+impl RoundRobinRouter {
+    pub fn new(pool: Vec<usize>) -> Self {
+        RoundRobinRouter {
+            pool,
+            state: Mutex::new(RoundRobin::default()),
+        }
+    }
+}
+
+impl Router for RoundRobinRouter {
+    fn route(&self, _: usize) -> Option<usize> {
+        self.pool.first().copied()
+    }
+
+    fn required_ports(&self) -> HashSet<usize> {
+        self.pool.iter().copied().collect()
+    }
+
+    fn route_note(&self, channel_id: usize, note: &Midi, midi_status: u8) -> Option<usize> {
+        if note.is_rest() || note.control.is_some() || self.pool.is_empty() {
+            return self.route(channel_id);
+        }
+        let mut state = self.state.lock().unwrap();
+        let key = (channel_id, note.u8_maybe());
+        match midi_status {
+            NOTE_ON_MSG => {
+                let next = state.next.entry(channel_id).or_insert(0);
+                let port_id = self.pool[*next % self.pool.len()];
+                *next = (*next + 1) % self.pool.len();
+                state.sounding.entry(key).or_default().push_back(port_id);
+                Some(port_id)
+            }
+            NOTE_OFF_MSG => state.sounding.get_mut(&key)
+                .and_then(|ports| ports.pop_front())
+                .or_else(|| self.route(channel_id)),
+            _ => self.route(channel_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use crate::midi::{Control, Midi, NOTE_OFF_MSG, NOTE_ON_MSG};
+    use crate::router::{DynamicRouter, NameMatch, NameRouter, PortNameError, RoundRobinRouter, Router};
+    use crate::tone::Tone;
+
+    #[test]
+    fn dynamic_routes_follow_live_edits() {
+        let router = DynamicRouter::new(HashMap::from([(0, 1), (1, 1)])).with_spare_ports([4]);
+        let remote = router.clone();
+        assert_eq!(router.route(0), Some(1));
+        assert_eq!(router.required_ports(), HashSet::from([1, 4]));
+
+        remote.set(0, 2);
+        remote.remove(1);
+        assert_eq!(router.route(0), Some(2));
+        assert_eq!(router.route(1), None);
+        assert_eq!(router.required_ports(), HashSet::from([2, 4]));
+    }
+
+    #[test]
+    fn round_robin_spreads_notes_and_follows_them_off() {
+        let router = RoundRobinRouter::new(vec![3, 5, 7]);
+        assert_eq!(router.required_ports(), HashSet::from([3, 5, 7]));
+        let c = Tone::C.oct(4);
+        let e = Tone::E.oct(4);
+        assert_eq!(router.route_note(0, &c, NOTE_ON_MSG), Some(3));
+        assert_eq!(router.route_note(0, &e, NOTE_ON_MSG), Some(5));
+        assert_eq!(router.route_note(0, &c, NOTE_ON_MSG), Some(7));
+        // another channel keeps its own turn
+        assert_eq!(router.route_note(1, &c, NOTE_ON_MSG), Some(3));
+        assert_eq!(router.route_note(0, &e, NOTE_ON_MSG), Some(3));
+
+        assert_eq!(router.route_note(0, &c, NOTE_OFF_MSG), Some(3));
+        assert_eq!(router.route_note(0, &c, NOTE_OFF_MSG), Some(7));
+        assert_eq!(router.route_note(0, &e, NOTE_OFF_MSG), Some(5));
+        assert_eq!(router.route_note(1, &c, NOTE_OFF_MSG), Some(3));
+        assert_eq!(router.route_note(0, &Midi::control(Control::ProgramChange(2)), NOTE_ON_MSG), Some(3));
+    }
+
+    #[test]
+    fn name_routes_resolve_to_the_matching_port() {
+        let ports: Vec<String> = ["Midi Through Port-0", "drumlogue MIDI 1", "Minilogue xd MIDI 1"]
+            .iter().map(|name| name.to_string()).collect();
+        let mut router = NameRouter::new(HashMap::from([(1, "drumlogue".to_string())]))
+            .with_matching(NameMatch::Substring);
+        assert_eq!(router.route(1), None);
+        router.resolve_ports(&ports).unwrap();
+        assert_eq!(router.route(1), Some(1));
+        assert_eq!(router.route(0), None);
+        assert_eq!(router.required_ports(), HashSet::from([1]));
+
+        let mut exact = NameRouter::for_all("Minilogue xd MIDI 1");
+        exact.resolve_ports(&ports).unwrap();
+        assert_eq!(exact.route(3), Some(2));
+        assert_eq!(
+            NameRouter::for_all("minilogue").resolve_ports(&ports),
+            Err(PortNameError::NotFound { name: "minilogue".to_string(), available: ports.clone() })
+        );
+        assert_eq!(
+            NameRouter::for_all("midi 1").with_matching(NameMatch::Substring).resolve_ports(&ports),
+            Err(PortNameError::Ambiguous {
+                name: "midi 1".to_string(),
+                candidates: vec![ports[1].clone(), ports[2].clone()],
+            })
+        );
+    }
+}
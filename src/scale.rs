@@ -1,4 +1,5 @@
-use crate::midi::{Midi};
+use std::str::FromStr;
+use crate::midi::{Midi, PITCH_RANGE};
 use crate::tone::Tone;
 
 #[derive(Debug, Clone)]
@@ -41,7 +42,7 @@ impl Scale {
     pub fn harmonize_up(&self, midi: Midi, harmonize: Degree) -> Option<Midi> {
         let tones = self.tones();
         let degree_maybe = tones.into_iter().position(|t| t.eq(&midi.tone));
-        return match degree_maybe {
+        match degree_maybe {
             None => None,
             Some(pos) => {
                 let steps_to_raise: u8 = self.intervals
@@ -51,18 +52,114 @@ impl Scale {
                     .take(harmonize.steps())
                     .sum();
                 let new = Midi::from_option(midi.u8_maybe().map(|v| v + steps_to_raise));
-                return Some(midi.set_pitch(
+                Some(midi.set_pitch(
                     new.tone,
                     new.oct,
-                ));
+                ))
             }
+        }
+    }
+
+    /// Moves `note` onto the nearest tone of the scale, keeping everything but its pitch. A note
+    /// halfway between two scale tones goes to the lower one, unless that would fall below
+    /// octave 0. Notes already in the scale, and rests, are unchanged.
+    pub fn snap(&self, note: Midi) -> Midi {
+        let Some(pitch) = note.u8_maybe() else {
+            return note;
         };
+        let tones = self.tones();
+        let fits = |candidate: i16| candidate >= 0
+            && PITCH_RANGE.contains(&(candidate as u8))
+            && tones.contains(&Tone::from(candidate as u8));
+        let pitch = pitch as i16;
+        (0..12)
+            .flat_map(|distance| [pitch - distance, pitch + distance])
+            .find(|candidate| fits(*candidate))
+            .map_or(note, |snapped| note.set_pitch_u8(Some(snapped as u8)))
+    }
+
+    /// The diatonic triad built on the note `degree` scale degrees above `root`: that note with
+    /// the scale's third and fifth above it. Empty if `root` is a rest or isn't in the scale.
+    pub fn triad(&self, root: Midi, degree: Degree) -> Vec<Midi> {
+        self.stack(root, degree, &[Degree::Third, Degree::Fifth])
+    }
+
+    /// Like `triad`, adding the scale's seventh above the chord's root.
+    pub fn seventh(&self, root: Midi, degree: Degree) -> Vec<Midi> {
+        self.stack(root, degree, &[Degree::Third, Degree::Fifth, Degree::Seventh])
+    }
+
+    fn stack(&self, root: Midi, degree: Degree, upper: &[Degree]) -> Vec<Midi> {
+        if root.is_rest() {
+            return Vec::new();
+        }
+        match self.harmonize_up(root, degree) {
+            None => Vec::new(),
+            Some(chord_root) => std::iter::once(chord_root)
+                .chain(upper.iter().filter_map(|d| self.harmonize_up(chord_root, *d)))
+                .collect(),
+        }
+    }
+
+    /// Parses a progression of roman numerals separated by whitespace, such as `"ii V I vi"`,
+    /// into the chord for each numeral, rooted in octave 4.
+    ///
+    /// Chords are stacked in thirds from the scale degree with the scale's own fifth. Uppercase
+    /// numerals have a major third and lowercase numerals a minor third, and a trailing `7` adds
+    /// the scale's seventh above the root.
+    pub fn progression(&self, numerals: &str) -> Result<Vec<Vec<Midi>>, String> {
+        numerals.split_whitespace().map(|numeral| self.numeral_chord(numeral)).collect()
+    }
+
+    fn numeral_chord(&self, numeral: &str) -> Result<Vec<Midi>, String> {
+        let (roman, seventh) = match numeral.strip_suffix('7') {
+            Some(roman) => (roman, true),
+            None => (numeral, false),
+        };
+        let unknown = || format!("Unknown chord symbol: {}", numeral);
+        let index = ["i", "ii", "iii", "iv", "v", "vi", "vii"].iter()
+            .position(|n| n.eq_ignore_ascii_case(roman))
+            .ok_or_else(unknown)?;
+        let major = if roman.chars().all(|c| c.is_ascii_uppercase()) {
+            true
+        } else if roman.chars().all(|c| c.is_ascii_lowercase()) {
+            false
+        } else {
+            return Err(unknown());
+        };
+
+        let degree = Degree::from_number(index as u8 + 1).ok_or_else(unknown)?;
+        let root = self.harmonize_up(self.root.oct(4), degree).ok_or_else(unknown)?;
+        let third = root.set_pitch_u8(root.u8_maybe().map(|v| v + if major { 4 } else { 3 }));
+        let mut chord = vec![root, third];
+        let mut upper = vec![Degree::Fifth];
+        if seventh {
+            upper.push(Degree::Seventh);
+        }
+        for degree in upper {
+            chord.push(self.harmonize_up(root, degree).ok_or_else(unknown)?);
+        }
+        Ok(chord)
+    }
+
+    /// Like `harmonize_up`, then raises or lowers the result by the degree's alteration.
+    /// Returns `None` if the altered note falls outside the MIDI range.
+    pub fn harmonize_up_altered(&self, midi: Midi, harmonize: AlteredDegree) -> Option<Midi> {
+        self.harmonize_up(midi, harmonize.degree)
+            .and_then(|m| alter(m, harmonize.semitones))
+    }
+
+    /// Like `harmonize_down`, then raises or lowers the result by the degree's alteration.
+    /// Returns `None` if the altered note falls outside the MIDI range.
+    pub fn harmonize_down_altered(&self, midi: Midi, harmonize: AlteredDegree) -> Option<Midi> {
+        self.harmonize_down(midi, harmonize.degree)
+            .and_then(|m| alter(m, harmonize.semitones))
     }
 
     pub fn harmonize_down(&self, midi: Midi, harmonize: Degree) -> Option<Midi> {
         let tones = self.tones();
         let degree_maybe = tones.into_iter().position(|t| t.eq(&midi.tone));
-        return match degree_maybe {
+        match degree_maybe {
             None => None,
             Some(pos) => {
                 let scale_at_pos: Vec<&u8> = self.intervals
@@ -79,16 +176,25 @@ impl Scale {
                     .take(harmonize.steps())
                     .sum();
                 let new = Midi::from_option(midi.u8_maybe().map(|v| v - steps_to_lower));
-                return Some(midi.set_pitch(
+                Some(midi.set_pitch(
                     new.tone,
                     new.oct,
-                ));
+                ))
             }
-        };
+        }
     }
 }
 
 
+fn alter(midi: Midi, semitones: i8) -> Option<Midi> {
+    let altered = midi.u8_maybe()? as i16 + semitones as i16;
+    if (0..=127).contains(&altered) {
+        Some(midi.set_pitch_u8(Some(altered as u8)))
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Degree {
     Unison,
@@ -107,6 +213,26 @@ pub enum Degree {
 }
 
 impl Degree {
+    /// The degree for its conventional number, counting the unison as 1.
+    pub fn from_number(number: u8) -> Option<Degree> {
+        match number {
+            1 => Some(Degree::Unison),
+            2 => Some(Degree::Second),
+            3 => Some(Degree::Third),
+            4 => Some(Degree::Fourth),
+            5 => Some(Degree::Fifth),
+            6 => Some(Degree::Sixth),
+            7 => Some(Degree::Seventh),
+            8 => Some(Degree::Octave),
+            9 => Some(Degree::Ninth),
+            10 => Some(Degree::Tenth),
+            11 => Some(Degree::Eleventh),
+            12 => Some(Degree::Twelveth),
+            13 => Some(Degree::Thirteenth),
+            _ => None,
+        }
+    }
+
     fn steps(&self) -> usize {
         match self {
             Degree::Unison => 0,
@@ -127,6 +253,50 @@ impl Degree {
 }
 
 
+/// A scale degree raised or lowered chromatically, e.g. the `b3` or `#4` of a major scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlteredDegree {
+    pub degree: Degree,
+    /// Semitones added to the diatonic degree; negative values lower it.
+    pub semitones: i8,
+}
+
+impl AlteredDegree {
+    pub fn natural(degree: Degree) -> Self {
+        AlteredDegree { degree, semitones: 0 }
+    }
+
+    pub fn flat(degree: Degree) -> Self {
+        AlteredDegree { degree, semitones: -1 }
+    }
+
+    pub fn sharp(degree: Degree) -> Self {
+        AlteredDegree { degree, semitones: 1 }
+    }
+}
+
+impl From<Degree> for AlteredDegree {
+    fn from(degree: Degree) -> Self {
+        AlteredDegree::natural(degree)
+    }
+}
+
+/// Parses degrees written like `3`, `b3`, `#4` or `bb7`.
+impl FromStr for AlteredDegree {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = s.trim_start_matches(['b', '#']);
+        let semitones = s[..s.len() - number.len()].chars()
+            .map(|c| if c == '#' { 1 } else { -1 })
+            .sum();
+        number.parse::<u8>().ok()
+            .and_then(Degree::from_number)
+            .map(|degree| AlteredDegree { degree, semitones })
+            .ok_or_else(|| format!("Unknown scale degree: {}", s))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Interval {
     Unison,
@@ -1,8 +1,17 @@
 use std::ops::{Add, Sub};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
 use crate::Midibox;
 use crate::chord::Chord;
-use crate::midi::{Midi, MutMidi};
+use crate::meter;
+use crate::meter::{Meter, NoteValue};
+use crate::midi::{Control, Midi, MutMidi, PITCH_RANGE};
 use crate::scale::{Degree, Interval, Scale};
+use crate::smf;
+use crate::smf::{ParseError, Smf, SmfNote, SmfTrack};
+use crate::spec;
+use crate::spec::{NoteSpec, SeqSpec, SpecError, StepSpec};
 use crate::tone::Tone;
 
 #[macro_export]
@@ -18,10 +27,12 @@ macro_rules! seq {
     };
 }
 
-// A looping sequence of statically defined notes.
+/// A looping sequence of statically defined notes. Each step is a chord of the notes that start
+/// together (see `Seq::chords` and the `seq!` macro), which the transformations apply to as a
+/// whole, so pads and harmonies can be written directly.
 #[derive(Debug, Clone)]
 pub struct Seq {
-    /// The notes that can be produced by a sequence
+    /// The notes that can be produced by a sequence, one chord per step
     notes: Vec<Chord>,
     /// The index of the play head into notes
     head_position: usize,
@@ -30,7 +41,7 @@ pub struct Seq {
 impl Seq {
     pub fn new(notes: Vec<Midi>) -> Self {
         Seq {
-            notes: notes.into_iter().map(|n| Chord::note(n)).collect(),
+            notes: notes.into_iter().map(Chord::note).collect(),
             head_position: 0,
         }
     }
@@ -42,6 +53,184 @@ impl Seq {
         }
     }
 
+    /// A sequence playing each chord in turn, such as those from `Scale::triad` or
+    /// `Scale::progression`. An empty chord becomes a rest of the default duration.
+    pub fn from_chords(chords: Vec<Vec<Midi>>) -> Self {
+        Seq::chords(chords.into_iter().map(|notes| {
+            if notes.is_empty() { Chord::note(Midi::rest()) } else { Chord::new(notes) }
+        }).collect())
+    }
+
+    /// Builds a sequence from a piano-roll style grid.
+    ///
+    /// Each row of `grid` corresponds to the pitch at the same index in `pitches`, and each column
+    /// is one step of `step_ticks` ticks. A cell is either `None` (the pitch is silent) or
+    /// `Some(velocity)`. All pitches sounding in a column play together as a chord; a column with
+    /// no sounding pitches becomes a rest.
+    pub fn from_grid(pitches: Vec<Midi>, grid: Vec<Vec<Option<u8>>>, step_ticks: u32) -> Self {
+        let steps = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        Seq::chords((0..steps).map(|step| {
+            let notes: Vec<Midi> = pitches.iter()
+                .zip(grid.iter())
+                .filter_map(|(pitch, row)| row.get(step).copied().flatten().map(|velocity| {
+                    pitch.set_velocity(velocity).set_duration(step_ticks)
+                }))
+                .collect();
+            if notes.is_empty() {
+                Chord::note(Midi::rest().set_duration(step_ticks))
+            } else {
+                Chord::new(notes)
+            }
+        }).collect())
+    }
+
+    /// A metronome for a bar of `beats_per_bar` quarter-note beats (see `smf::TICKS_PER_BEAT`):
+    /// `accent` on the downbeat and `beat` on every other beat, each followed by a rest for the
+    /// rest of its beat. The notes keep their own velocities and durations, cut down to a beat,
+    /// so the accent is set by giving it a higher velocity.
+    pub fn metronome(beats_per_bar: u32, accent: Midi, beat: Midi) -> Self {
+        let beat_ticks = smf::TICKS_PER_BEAT;
+        let mut clicks = Vec::new();
+        for i in 0..beats_per_bar.max(1) {
+            let note = if i == 0 { accent } else { beat };
+            let ticks = note.duration.clamp(1, beat_ticks);
+            clicks.push(note.set_duration(ticks));
+            if ticks < beat_ticks {
+                clicks.push(Midi::rest().set_duration(beat_ticks - ticks));
+            }
+        }
+        Seq::new(clicks)
+    }
+
+    /// A Euclidean rhythm: `pulses` hits of `note` spread as evenly as possible over `steps`
+    /// steps using Bjorklund's algorithm, with rests of the same duration in between. E(3, 8)
+    /// is `x..x..x.`. See `euclidean_rotated` to shift the pattern.
+    pub fn euclidean(pulses: usize, steps: usize, note: Midi) -> Self {
+        Self::euclidean_rotated(pulses, steps, 0, note)
+    }
+
+    /// Like `euclidean`, with the pattern rotated to start `rotation` steps in.
+    pub fn euclidean_rotated(pulses: usize, steps: usize, rotation: usize, note: Midi) -> Self {
+        let mut pattern = bjorklund(pulses, steps);
+        if !pattern.is_empty() {
+            pattern.rotate_left(rotation % steps);
+        }
+        Seq::new(pattern.into_iter().map(|hit| {
+            if hit { note } else { Midi::rest().set_duration(note.duration) }
+        }).collect())
+    }
+
+    /// Reads every track of a Standard MIDI File into its own sequence, named after the track
+    /// (or `Track <n>` if it has no name), so that each part can be routed separately.
+    ///
+    /// Times are converted to the nearest crate tick (see `smf::TICKS_PER_BEAT`). Notes starting
+    /// together become a chord, gaps become rests, and a note is cut short if the next one
+    /// starts before it ends. Each note carries the MIDI channel it was recorded on. Tracks
+    /// without notes, such as a tempo track, are left out.
+    pub fn from_smf_multi(bytes: &[u8]) -> Result<Vec<(String, Seq)>, ParseError> {
+        let smf = smf::parse(bytes)?;
+        Ok(smf.tracks.iter()
+            .enumerate()
+            .filter(|(_, track)| !track.notes.is_empty())
+            .map(|(i, track)| {
+                let name = track.name.clone().unwrap_or_else(|| format!("Track {}", i));
+                (name, Seq::from_smf_track(&smf, track))
+            })
+            .collect())
+    }
+
+    /// Reads the monophonic track at index `track` of a Standard MIDI File, counting from the
+    /// first track in the file, e.g. a tempo track.
+    ///
+    /// Times are rounded to the nearest crate tick (see `smf::TICKS_PER_BEAT`), and the number of
+    /// notes that moved is returned along with the sequence. Gaps between notes become rests. A
+    /// track where notes overlap is an error, since a note would otherwise be lost.
+    pub fn from_smf(bytes: &[u8], track: usize) -> Result<(Seq, usize), ParseError> {
+        let smf = smf::parse(bytes)?;
+        let notes = &smf.tracks.get(track).ok_or(ParseError::MissingTrack(track))?.notes;
+        if let Some(overlap) = notes.windows(2).find(|pair| pair[1].start < pair[0].end) {
+            return Err(ParseError::Polyphonic { track, at: overlap[1].start });
+        }
+        let quantized = notes.iter()
+            .filter(|note| !smf.is_on_crate_tick(note.start) || !smf.is_on_crate_tick(note.end))
+            .count();
+        Ok((Seq::from_smf_track(&smf, &smf.tracks[track]), quantized))
+    }
+
+    fn from_smf_track(smf: &Smf, track: &SmfTrack) -> Seq {
+        let mut onsets: Vec<(u32, Vec<&SmfNote>)> = Vec::new();
+        for note in &track.notes {
+            let start = smf.to_crate_ticks(note.start);
+            match onsets.last_mut() {
+                Some((onset, notes)) if *onset == start => notes.push(note),
+                _ => onsets.push((start, vec![note])),
+            }
+        }
+
+        let mut chords = Vec::new();
+        let mut position = 0;
+        for (i, (onset, notes)) in onsets.iter().enumerate() {
+            if *onset > position {
+                chords.push(Chord::note(Midi::rest().set_duration(onset - position)));
+            }
+            let next = onsets.get(i + 1).map(|(next, _)| *next);
+            let chord = Chord::new(notes.iter().map(|note| {
+                let end = smf.to_crate_ticks(note.end).max(onset + 1);
+                Midi::from(note.key)
+                    .set_velocity(note.velocity)
+                    .set_channel(note.channel)
+                    .set_duration(next.map_or(end, |next| end.min(next)) - onset)
+            }).collect());
+            position = onset + chord.total_duration();
+            chords.push(chord);
+        }
+        let end = smf.to_crate_ticks(track.end);
+        if end > position {
+            chords.push(Chord::note(Midi::rest().set_duration(end - position)));
+        }
+        Seq::chords(chords)
+    }
+
+    /// Reads a sequence written as JSON. See `SeqSpec` for the format.
+    pub fn from_json(text: &str) -> Result<Seq, SpecError> {
+        Ok(Seq::from_spec(&spec::parse_json(text)?))
+    }
+
+    /// Reads a sequence written as RON. See `SeqSpec` for the format.
+    pub fn from_ron(text: &str) -> Result<Seq, SpecError> {
+        Ok(Seq::from_spec(&spec::parse_ron(text)?))
+    }
+
+    pub fn from_spec(spec: &SeqSpec) -> Seq {
+        Seq::chords(spec.steps.iter().map(|step| match step {
+            StepSpec::Note(note) => Chord::note(note.midi()),
+            StepSpec::Chord(notes) => Chord::new(notes.iter().map(|note| note.midi()).collect()),
+        }).collect())
+    }
+
+    /// Writes the sequence out as JSON that `Seq::from_json` reads back, one step after another
+    /// from the first. See `Seq::to_spec` for what's left out.
+    pub fn to_json(&self) -> String {
+        spec::write_json(&self.to_spec())
+    }
+
+    /// Writes the sequence out as RON that `Seq::from_ron` reads back, one step after another
+    /// from the first. See `Seq::to_spec` for what's left out.
+    pub fn to_ron(&self) -> String {
+        spec::write_ron(&self.to_spec())
+    }
+
+    /// The pitch, duration and velocity of every note, step by step. Channels, ties and release
+    /// velocities aren't kept, and control events become rests.
+    pub fn to_spec(&self) -> SeqSpec {
+        SeqSpec {
+            steps: self.notes.iter().map(|chord| match &chord.notes[..] {
+                [note] => StepSpec::Note(NoteSpec::from_midi(note)),
+                notes => StepSpec::Chord(notes.iter().map(NoteSpec::from_midi).collect()),
+            }).collect(),
+        }
+    }
+
     pub fn empty() -> Self {
         Seq {
             notes: Vec::new(),
@@ -50,15 +239,11 @@ impl Seq {
     }
 
     pub fn render(&self) -> IterSeq {
+        let steps: Vec<Vec<Midi>> = self.notes.iter().map(|c| c.notes.clone()).collect();
+        let position = if steps.is_empty() { 0 } else { self.head_position % steps.len() };
         IterSeq {
-            iter: Box::new(
-                self.notes
-                    .clone()
-                    .into_iter()
-                    .map(|m| m.notes.clone())
-                    .cycle()
-                    .skip(self.head_position)
-            )
+            steps,
+            position,
         }
     }
 
@@ -66,6 +251,60 @@ impl Seq {
         Box::new(self.render())
     }
 
+    /// Plays the sequence through once from the play head, for intros and fills. It's then
+    /// finished (see `Midibox::is_finished`), and if polled anyway rests for good: each later
+    /// step is a rest as long as the whole sequence.
+    pub fn once(&self) -> Box<dyn Midibox> {
+        let IterSeq { steps, position } = self.render();
+        Box::new(OneShot {
+            steps: steps.into_iter().skip(position).collect(),
+            position: 0,
+            rest_ticks: self.total_duration().max(1),
+        })
+    }
+
+    /// A compact text timeline of one cycle of this sequence, for debugging without MIDI
+    /// hardware. See `render_preview`.
+    pub fn preview(&self) -> String {
+        render_preview(&self.render()).unwrap_or_default()
+    }
+
+    /// Writes the sequence out as ABC notation, with each tick as a sixteenth note (see
+    /// `smf::TICKS_PER_BEAT`) and the tempo taken from `meter`.
+    ///
+    /// Notes starting together are written as a chord lasting as long as the step. There are no
+    /// bar lines, since a sequence doesn't know its time signature, and ties and control events
+    /// are left out.
+    pub fn to_abc(&self, meter: &dyn Meter) -> String {
+        let beat = meter::beat_duration(meter).as_secs_f64();
+        let mut abc = format!(
+            "X:1\nM:none\nL:1/{}\nQ:1/4={}\nK:C\n",
+            smf::TICKS_PER_BEAT * 4,
+            (60.0 / beat).round()
+        );
+        let steps: Vec<String> = self.notes.iter()
+            .filter(|chord| chord.total_duration() > 0)
+            .map(|chord| {
+                let pitches: Vec<String> = chord.notes.iter()
+                    .filter(|note| !note.is_rest() && note.control.is_none())
+                    .map(abc_pitch)
+                    .collect();
+                let symbol = match pitches.len() {
+                    0 => "z".to_string(),
+                    1 => pitches[0].clone(),
+                    _ => format!("[{}]", pitches.concat()),
+                };
+                match chord.total_duration() {
+                    1 => symbol,
+                    ticks => format!("{}{}", symbol, ticks),
+                }
+            })
+            .collect();
+        abc.push_str(&steps.join(" "));
+        abc.push('\n');
+        abc
+    }
+
     pub fn len(&self) -> usize {
         self.notes.len()
     }
@@ -75,24 +314,104 @@ impl Seq {
     }
 
     pub fn total_duration(&self) -> u32 {
-        return self.notes.iter().map(|it| it.total_duration()).sum()
+        self.notes.iter().map(|it| it.total_duration()).sum()
     }
 
+    /// Moves the play head on by `ticks` steps, wrapping round. An empty sequence is unchanged.
     pub fn fast_forward(mut self, ticks: usize) -> Self {
-        self.head_position = (self.head_position + ticks) % self.notes.len();
+        if !self.notes.is_empty() {
+            self.head_position = (self.head_position + ticks) % self.notes.len();
+        }
         self
     }
 
+    /// Cuts the sequence into bars of `bar_ticks` ticks each. A note that straddles a bar line
+    /// is split in two, with the first half tied (see `Midi::tie`) into the second so that it
+    /// still sounds as one note. The last bar may be shorter than the rest.
+    pub fn split_bars(self, bar_ticks: u32) -> Vec<Seq> {
+        let bar_ticks = bar_ticks.max(1);
+        let mut bars: Vec<Vec<Chord>> = Vec::new();
+        let mut bar: Vec<Chord> = Vec::new();
+        let mut position_in_bar = 0;
+        for chord in self.notes {
+            let step_ticks = chord.total_duration();
+            if step_ticks == 0 {
+                bar.push(chord);
+                continue;
+            }
+            let mut offset = 0;
+            while offset < step_ticks {
+                let take = (bar_ticks - position_in_bar).min(step_ticks - offset);
+                bar.push(Chord::new(chord.notes.iter()
+                    .filter(|m| m.duration > offset)
+                    .map(|m| {
+                        let remaining = m.duration - offset;
+                        m.set_duration(remaining.min(take))
+                            .set_tie(m.tie || (remaining > take && !m.is_rest()))
+                    })
+                    .collect()));
+                offset += take;
+                position_in_bar += take;
+                if position_in_bar == bar_ticks {
+                    bars.push(std::mem::take(&mut bar));
+                    position_in_bar = 0;
+                }
+            }
+        }
+        if !bar.is_empty() {
+            bars.push(bar);
+        }
+        bars.into_iter().map(Seq::chords).collect()
+    }
+
     pub fn duration(mut self, duration: u32) -> Self {
         self.notes = self.notes.into_iter().map(|c| c.duration(duration)).collect();
         self
     }
 
+    /// Sets the duration of each step from `note_values`, which are cycled to the length of the
+    /// sequence, so rhythms can be written musically (see `NoteValue`).
+    ///
+    /// Steps end on the tick nearest to where they would end exactly, rather than each being
+    /// rounded on its own, so three eighth-note triplets last 1, 2 and 1 ticks and still fill a
+    /// quarter note. Every step lasts at least a tick. Control events keep their durations, and
+    /// an empty list changes nothing.
+    pub fn durations_from(mut self, note_values: Vec<NoteValue>) -> Self {
+        if note_values.is_empty() {
+            return self;
+        }
+        let mut exact_end = 0.0;
+        let mut start = 0;
+        for (chord, value) in self.notes.iter_mut().zip(note_values.iter().cycle()) {
+            exact_end += value.exact_ticks();
+            let end = (exact_end.round() as u32).max(start + 1);
+            for note in chord.notes.iter_mut().filter(|n| n.control.is_none()) {
+                *note = note.set_duration(end - start);
+            }
+            start = end;
+        }
+        self
+    }
+
     pub fn velocity(mut self, velocity: u8) -> Self {
         self.notes = self.notes.into_iter().map(|c| c.velocity(velocity)).collect();
         self
     }
 
+    /// Sets the velocity every note is released with (see `Midi::release_velocity`).
+    pub fn release_velocity(self, velocity: u8) -> Self {
+        self.map_notes(|note| note.set_release_velocity(velocity))
+    }
+
+    /// Ties the notes of the last step into the first step of the next loop, so that a sustained
+    /// note that starts the sequence again isn't retriggered at the loop boundary.
+    pub fn sustain_across_loop(mut self) -> Self {
+        if let Some(last) = self.notes.pop() {
+            self.notes.push(Chord::new(last.notes.into_iter().map(|m| m.set_tie(true)).collect()));
+        }
+        self
+    }
+
     pub fn scale_duration(mut self, factor: u32) -> Self {
         self.notes = self.notes.into_iter().map(|c| c.scale_duration(factor)).collect();
         self
@@ -105,6 +424,120 @@ impl Seq {
         self
     }
 
+    /// Joins `other` onto the end of this sequence with a fade between them: notes starting in
+    /// this sequence's last `fade_ticks` ticks get steadily quieter, and notes starting in the
+    /// first `fade_ticks` ticks of `other` steadily louder.
+    ///
+    /// Velocities are scaled in proportion to how far a note starts from the junction. The ticks
+    /// either side of it keep `1 / fade_ticks` of their velocity, so the fade never goes silent.
+    pub fn fade_join(self, other: &Self, fade_ticks: u32) -> Self {
+        let fade_ticks = fade_ticks.max(1);
+        let end = self.total_duration();
+        let outgoing = self.scale_velocities(|onset| {
+            end.saturating_sub(onset).min(fade_ticks) as f64 / fade_ticks as f64
+        });
+        let incoming = other.clone().scale_velocities(|onset| {
+            (onset + 1).min(fade_ticks) as f64 / fade_ticks as f64
+        });
+        outgoing.extend(&incoming)
+    }
+
+    /// Scales each note's velocity by `factor` of the tick its step starts on.
+    fn scale_velocities(mut self, factor: impl Fn(u32) -> f64) -> Self {
+        let mut onset = 0;
+        for chord in self.notes.iter_mut() {
+            let factor = factor(onset);
+            onset += chord.total_duration();
+            for note in chord.notes.iter_mut().filter(|note| !note.is_rest()) {
+                let velocity = (note.velocity as f64 * factor).round().max(1.0);
+                *note = note.set_velocity(velocity as u8);
+            }
+        }
+        self
+    }
+
+    /// Snaps every velocity to the centre of one of `levels` evenly spaced bands across the MIDI
+    /// velocity range, e.g. five levels for pp, p, mf, f and ff. Cleans up the noisy velocities
+    /// of a recorded performance.
+    pub fn quantize_dynamics(mut self, levels: usize) -> Self {
+        if levels == 0 {
+            return self;
+        }
+        let band = 128.0 / levels as f64;
+        for chord in self.notes.iter_mut() {
+            for note in chord.notes.iter_mut().filter(|note| !note.is_rest()) {
+                let level = ((note.velocity as f64 / band) as usize).min(levels - 1);
+                let centre = ((level as f64 + 0.5) * band).round().clamp(1.0, 127.0);
+                *note = note.set_velocity(centre as u8);
+            }
+        }
+        self
+    }
+
+    /// Swaps the emphasis between the beats and the off-beats, for reggae and ska feels that push
+    /// the upbeats. Notes starting on one of the four beats of each `bar_ticks` bar are moved by
+    /// the difference between the average off-beat and on-beat velocities, and off-beat notes by
+    /// the same amount the other way. Applying it twice gives back the original dynamics, except
+    /// where velocities had to be clamped.
+    pub fn invert_dynamics(mut self, bar_ticks: u32) -> Self {
+        let beat_ticks = (bar_ticks / 4).max(1);
+        let mut on_beat: Vec<bool> = Vec::new();
+        let mut onset = 0;
+        for chord in &self.notes {
+            on_beat.push(onset % beat_ticks == 0);
+            onset += chord.total_duration();
+        }
+        let average = |on: bool| {
+            let velocities: Vec<f64> = self.notes.iter()
+                .zip(&on_beat)
+                .filter(|(_, is_on_beat)| **is_on_beat == on)
+                .flat_map(|(chord, _)| chord.notes.iter().filter(|n| !n.is_rest()))
+                .map(|n| n.velocity as f64)
+                .collect();
+            (!velocities.is_empty()).then(|| velocities.iter().sum::<f64>() / velocities.len() as f64)
+        };
+        let (Some(on), Some(off)) = (average(true), average(false)) else {
+            return self;
+        };
+        let shift = (off - on).round() as i16;
+        for (chord, is_on_beat) in self.notes.iter_mut().zip(on_beat) {
+            let shift = if is_on_beat { shift } else { -shift };
+            for note in chord.notes.iter_mut().filter(|n| !n.is_rest()) {
+                *note = note.set_velocity((note.velocity as i16 + shift).clamp(1, 127) as u8);
+            }
+        }
+        self
+    }
+
+    /// Adds controller automation: each step also sets `controller` to the next of `values`,
+    /// cycling through them, just before its notes start, on the MIDI channel of its first note.
+    /// The automation takes no time of its own, so the sequence's timing is unchanged.
+    pub fn automate(mut self, controller: u8, values: &[u8]) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+        for (chord, value) in self.notes.iter_mut().zip(values.iter().cycle()) {
+            let control = Control::ControlChange { controller, value: *value };
+            let channel = chord.notes.first().and_then(|note| note.channel);
+            chord.notes.insert(0, Midi { channel, ..Midi::control(control) });
+        }
+        self
+    }
+
+    /// Inserts a program change at the play head, on the MIDI channel of the step there, so the
+    /// instrument switches before that step plays, and again each time the loop comes round.
+    ///
+    /// The change takes no time: the player sends it and then polls the step straight away, so
+    /// it goes out on the same tick just before that step's notes.
+    pub fn program_change(mut self, program: u8) -> Self {
+        let channel = self.notes.get(self.head_position)
+            .and_then(|chord| chord.notes.first())
+            .and_then(|note| note.channel);
+        let change = Midi { channel, ..Midi::control(Control::ProgramChange(program)) };
+        self.notes.insert(self.head_position.min(self.notes.len()), Chord::note(change));
+        self
+    }
+
     pub fn repeat(mut self, times: usize) -> Self {
         let mut new_notes: Vec<Chord> = Vec::with_capacity(
             self.notes.len() * times
@@ -121,6 +554,241 @@ impl Seq {
         self
     }
 
+    /// Plays the sequence backwards, as `reverse` does, with each step keeping its own duration
+    /// and ties (see `Midi::tie`) turned round so that tied notes still sound as one: a note tied
+    /// into the next step becomes a note tied from that step's matching note instead.
+    pub fn retrograde(mut self) -> Self {
+        let mut steps = self.notes.clone();
+        steps.iter_mut()
+            .flat_map(|chord| chord.notes.iter_mut())
+            .for_each(|note| note.tie = false);
+        for (i, chord) in self.notes.iter().enumerate() {
+            let next = (i + 1) % steps.len();
+            for note in chord.notes.iter().filter(|note| note.tie && !note.is_rest()) {
+                let continued = steps[next].notes.iter_mut()
+                    .find(|other| !other.is_rest() && other.u8_maybe() == note.u8_maybe());
+                if let Some(continued) = continued {
+                    continued.tie = true;
+                }
+            }
+        }
+        self.notes = steps.into_iter().rev().collect();
+        self
+    }
+
+    /// Mirrors every pitch around `axis`, so that a note a major third above it ends up a major
+    /// third below, as in a twelve-tone inversion. Pitches mirrored out of the MIDI range are
+    /// moved back into it by whole octaves. Rests, and everything for a rest axis, are unchanged.
+    pub fn invert(self, axis: Midi) -> Self {
+        let Some(axis) = axis.u8_maybe() else {
+            return self;
+        };
+        self.map_notes(|note| match note.u8_maybe() {
+            None => note,
+            Some(pitch) => {
+                let mut inverted = 2 * axis as i16 - pitch as i16;
+                while inverted > *PITCH_RANGE.end() as i16 {
+                    inverted -= 12;
+                }
+                while inverted < *PITCH_RANGE.start() as i16 {
+                    inverted += 12;
+                }
+                note.set_pitch_u8(Some(inverted as u8))
+            }
+        })
+    }
+
+    /// Shifts the steps round by `steps`: positive values move them left, so the sequence starts
+    /// that many steps in, and negative values move them right. Unlike `fast_forward`, the
+    /// stored order itself changes, whatever the play head.
+    pub fn rotate(mut self, steps: isize) -> Self {
+        if !self.notes.is_empty() {
+            let shift = steps.rem_euclid(self.notes.len() as isize) as usize;
+            self.notes.rotate_left(shift);
+        }
+        self
+    }
+
+    /// Sounds each step's notes for only `fraction` of their duration (staccato), followed by a
+    /// rest for the remainder of the step, so the notes are released early while the sequence's
+    /// timing is unchanged.
+    ///
+    /// `fraction` is clamped to (0, 1], and gated notes still sound for at least a tick. Rests,
+    /// control events and tied notes are left as they are.
+    pub fn gate(mut self, fraction: f64) -> Self {
+        let fraction = if fraction.is_nan() { 1.0 } else { fraction.clamp(f64::MIN_POSITIVE, 1.0) };
+        let mut gated: Vec<Chord> = Vec::with_capacity(self.notes.len());
+        let mut head_position = 0;
+        for (i, chord) in self.notes.into_iter().enumerate() {
+            if i == self.head_position {
+                head_position = gated.len();
+            }
+            let step_ticks = chord.total_duration();
+            let gateable = chord.notes.iter().any(|n| !n.is_rest())
+                && chord.notes.iter().all(|n| n.control.is_none() && !n.tie);
+            if !gateable {
+                gated.push(chord);
+                continue;
+            }
+            let notes: Vec<Midi> = chord.notes.iter()
+                .map(|n| {
+                    let ticks = (n.duration as f64 * fraction).round() as u32;
+                    n.set_duration(ticks.clamp(1, n.duration.max(1)))
+                })
+                .collect();
+            let sounding = Chord::new(notes);
+            let remainder = step_ticks - sounding.total_duration();
+            gated.push(sounding);
+            if remainder > 0 {
+                gated.push(Chord::note(Midi::rest().set_duration(remainder)));
+            }
+        }
+        self.notes = gated;
+        self.head_position = head_position;
+        self
+    }
+
+    /// Merges each run of consecutive steps playing the same notes into a single step lasting
+    /// the whole run, so that a note repeated across the join of two `extend`ed sequences sounds
+    /// once rather than being retriggered. Runs of rests merge too.
+    ///
+    /// Only steps whose notes are identical apart from duration, last the whole step and carry
+    /// no control events are merged. `extend` and `+` keep every step, for when the retriggers
+    /// are wanted.
+    pub fn tie_repeats(mut self) -> Self {
+        let mut merged: Vec<Chord> = Vec::new();
+        let mut head_position = 0;
+        for (i, chord) in self.notes.into_iter().enumerate() {
+            match merged.last_mut() {
+                Some(last) if is_repeat(last, &chord) => {
+                    let duration = last.total_duration() + chord.total_duration();
+                    *last = last.clone().duration(duration);
+                }
+                _ => merged.push(chord),
+            }
+            if i == self.head_position {
+                head_position = merged.len() - 1;
+            }
+        }
+        self.notes = merged;
+        self.head_position = head_position;
+        self
+    }
+
+    /// Plays the sequence forward and then backward. With `keep_endpoints` off, the last step
+    /// isn't repeated at the turnaround and the first isn't repeated when the loop starts over,
+    /// so `A B C D` becomes `A B C D C B`.
+    pub fn ping_pong(mut self, keep_endpoints: bool) -> Self {
+        let skip = if keep_endpoints { 0 } else { 1 };
+        let back: Vec<Chord> = self.notes.iter()
+            .rev()
+            .skip(skip)
+            .take(self.notes.len().saturating_sub(2 * skip))
+            .cloned()
+            .collect();
+        self.notes.extend(back);
+        self
+    }
+
+    /// Shuffles the order of steps within each window of `window_ticks` ticks, leaving the
+    /// windows themselves in place so the overall contour is kept. Steps belong to the window
+    /// they start in. The same seed always produces the same order.
+    pub fn shuffle_within(mut self, window_ticks: u32, seed: u64) -> Self {
+        let window_ticks = window_ticks.max(1);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut windows: Vec<Vec<Chord>> = Vec::new();
+        let mut position = 0;
+        let mut current_window = None;
+        for chord in self.notes {
+            let window = position / window_ticks;
+            if current_window != Some(window) {
+                windows.push(Vec::new());
+                current_window = Some(window);
+            }
+            position += chord.total_duration();
+            windows.last_mut().unwrap().push(chord);
+        }
+        self.notes = windows.into_iter().flat_map(|mut window| {
+            window.shuffle(&mut rng);
+            window
+        }).collect();
+        self
+    }
+
+    /// Moves the notes that land on each beat earlier (negative offset, anticipating) or later
+    /// (positive offset, laying back) by that beat's offset in ticks, for a deliberate feel.
+    ///
+    /// `beat_offsets` holds one offset per beat of a bar of `bar_ticks` ticks. Notes keep their
+    /// durations where possible; the shift is absorbed by the rests around them, and a note is
+    /// only shortened if the next one now starts before it ends. The sequence's total length is
+    /// unchanged, and nothing moves before the start of the sequence or so late that a note would
+    /// be left without a tick to sound in.
+    pub fn push_pull(mut self, beat_offsets: Vec<i32>, bar_ticks: u32) -> Self {
+        if beat_offsets.is_empty() {
+            return self;
+        }
+        let beat_ticks = (bar_ticks / beat_offsets.len() as u32).max(1);
+        let total = self.total_duration() as i64;
+
+        let mut events: Vec<(i64, Chord)> = Vec::new();
+        let mut position = 0;
+        for chord in self.notes {
+            let onset = position;
+            position += chord.total_duration();
+            if chord.notes.iter().all(|m| m.is_rest()) {
+                continue;
+            }
+            let mut shifted = onset as i64;
+            if onset % beat_ticks == 0 {
+                let beat = ((onset % bar_ticks.max(1)) / beat_ticks) as usize;
+                shifted += *beat_offsets.get(beat).unwrap_or(&0) as i64;
+            }
+            events.push((shifted, chord));
+        }
+        // every note keeps a tick of its own, before the next one and before the end
+        let count = events.len() as i64;
+        for i in 0..events.len() {
+            let earliest = if i == 0 { 0 } else { events[i - 1].0 + 1 };
+            let latest = total - (count - i as i64);
+            events[i].0 = events[i].0.clamp(earliest, latest.max(earliest));
+        }
+
+        let mut notes: Vec<Chord> = Vec::new();
+        if let Some((first, _)) = events.first() {
+            if *first > 0 {
+                notes.push(Chord::note(Midi::rest().set_duration(*first as u32)));
+            }
+        }
+        for i in 0..events.len() {
+            let (onset, chord) = &events[i];
+            let next = events.get(i + 1).map(|(next, _)| *next).unwrap_or(total);
+            let available = (next - onset).max(0) as u32;
+            let chord = Chord::new(chord.notes.iter()
+                .map(|m| m.set_duration(m.duration.min(available)))
+                .collect());
+            let gap = available - chord.total_duration();
+            notes.push(chord);
+            if gap > 0 {
+                notes.push(Chord::note(Midi::rest().set_duration(gap)));
+            }
+        }
+        self.notes = notes;
+        self
+    }
+
+    /// Applies `f` only to the notes whose pitch class is `tone`, in any octave, e.g. to raise
+    /// every F to an F#. Other notes and rests are left untouched.
+    pub fn map_pitch_class(mut self, tone: Tone, f: impl Fn(Midi) -> Midi) -> Self {
+        for chord in self.notes.iter_mut() {
+            for note in chord.notes.iter_mut() {
+                if !note.is_rest() && note.tone == tone {
+                    *note = f(*note);
+                }
+            }
+        }
+        self
+    }
+
     pub fn transpose_up(mut self, interval: Interval) -> Self {
         self.notes = self.notes.into_iter().map(|c| c.transpose_up(&interval)).collect();
         self
@@ -131,6 +799,27 @@ impl Seq {
         self
     }
 
+    /// Raises every note by `octaves` octaves. Notes too high to go up that far go up as many
+    /// octaves as still fit in the MIDI range.
+    pub fn octave_up(self, octaves: u8) -> Self {
+        self.map_notes(|note| note.octave_up(octaves))
+    }
+
+    /// Lowers every note by `octaves` octaves. Notes too low to go down that far go down as many
+    /// octaves as still fit, staying in octave 0 or above.
+    pub fn octave_down(self, octaves: u8) -> Self {
+        self.map_notes(|note| note.octave_down(octaves))
+    }
+
+    fn map_notes(mut self, f: impl Fn(Midi) -> Midi) -> Self {
+        for chord in self.notes.iter_mut() {
+            for note in chord.notes.iter_mut() {
+                *note = f(*note);
+            }
+        }
+        self
+    }
+
     pub fn harmonize_up(mut self, scale: &Scale, degree: Degree) -> Self {
         self.notes = self.notes.into_iter()
             .map(|m| m.harmonize_up(scale, &degree))
@@ -145,6 +834,33 @@ impl Seq {
         self
     }
 
+    /// Turns each note into a chord: the note itself plus a voice `degree` steps of `scale`
+    /// above it for each of `degrees`. A note outside the scale is first moved to its nearest
+    /// scale tone, as `Scale::snap` does. Voices that would land above the MIDI range are
+    /// dropped, as are repeats of a pitch already in the chord. Rests and control events are
+    /// left as they are.
+    pub fn harmonize_chord(mut self, scale: &Scale, degrees: Vec<Degree>) -> Self {
+        for chord in self.notes.iter_mut() {
+            let mut voiced: Vec<Midi> = Vec::new();
+            for note in chord.notes.iter() {
+                if note.is_rest() || note.control.is_some() {
+                    voiced.push(*note);
+                    continue;
+                }
+                let root = scale.snap(*note);
+                let voices = std::iter::once(Some(root))
+                    .chain(degrees.iter().map(|degree| scale.harmonize_up(root, *degree)));
+                for voice in voices.flatten().filter(|voice| !voice.is_rest()) {
+                    if !voiced.iter().any(|v| v.u8_maybe() == voice.u8_maybe()) {
+                        voiced.push(voice);
+                    }
+                }
+            }
+            chord.notes = voiced;
+        }
+        self
+    }
+
     /// Splits each note into a series of metronome ticks adding to the note's duration
     pub fn split_to_ticks(mut self) -> Self {
         self.notes = self.notes.into_iter().flat_map(|c| {
@@ -164,9 +880,9 @@ impl Seq {
     ///
     /// The mask will be applied starting from the first note of the sequence and will repeat to
     /// match the total number of notes in this sequence.
-    pub fn mask(mut self, mask: &Vec<bool>) -> Self {
+    pub fn mask(mut self, mask: &[bool]) -> Self {
         self.notes = self.notes.into_iter()
-            .zip(mask.into_iter().cycle()).map(|(c, should_play)| {
+            .zip(mask.iter().cycle()).map(|(c, should_play)| {
             if *should_play {
                 c
             } else {
@@ -176,9 +892,147 @@ impl Seq {
         self
     }
 
-    pub fn split_notes(self, mask: &Vec<bool>) -> Self {
+    /// Sets the velocity of each step from `pattern`, which is cycled like a `mask` to match the
+    /// length of the sequence, for rhythmic dynamics such as a louder first beat of every four.
+    /// Velocities above 127 are clamped, rests are left alone, and an empty pattern changes
+    /// nothing.
+    pub fn accent(mut self, pattern: &[u8]) -> Self {
+        if pattern.is_empty() {
+            return self;
+        }
+        for (chord, velocity) in self.notes.iter_mut().zip(pattern.iter().cycle()) {
+            for note in chord.notes.iter_mut().filter(|n| !n.is_rest() && n.control.is_none()) {
+                *note = note.set_velocity(*velocity);
+            }
+        }
+        self
+    }
+
+    pub fn split_notes(self, mask: &[bool]) -> Self {
         self.split_to_ticks().mask(mask)
     }
+
+    /// The rhythmic pattern of this sequence as a mask: `true` for each step that sounds at least
+    /// one note, `false` for each step that only rests.
+    pub fn rest_mask(&self) -> Vec<bool> {
+        self.notes.iter().map(|c| c.notes.iter().any(|n| !n.is_rest())).collect()
+    }
+
+    /// Combines two masks step by step with a boolean operation.
+    ///
+    /// Masks of different lengths are cycled against each other, so the result repeats after the
+    /// least common multiple of their lengths. Combining with an empty mask yields an empty mask.
+    pub fn combine_masks(a: &[bool], b: &[bool], op: MaskOp) -> Vec<bool> {
+        if a.is_empty() || b.is_empty() {
+            return Vec::new();
+        }
+        let len = lcm(a.len(), b.len());
+        a.iter().cycle()
+            .zip(b.iter().cycle())
+            .take(len)
+            .map(|(x, y)| op.apply(*x, *y))
+            .collect()
+    }
+}
+
+/// Characters used to draw each tick in a preview.
+const PREVIEW_TICK_WIDTH: usize = 2;
+
+/// The ABC name of a note's pitch: flats as `_`, octave 4 in capitals and octave 5 in lower case,
+/// with `,` and `'` marking each octave below and above those.
+fn abc_pitch(note: &Midi) -> String {
+    let name = note.tone.to_string();
+    let mut pitch = if name.len() > 1 { "_".to_string() } else { String::new() };
+    let letter = &name[..1];
+    if note.oct >= 5 {
+        pitch.push_str(&letter.to_lowercase());
+        pitch.push_str(&"'".repeat(note.oct as usize - 5));
+    } else {
+        pitch.push_str(letter);
+        pitch.push_str(&",".repeat(4 - note.oct as usize));
+    }
+    pitch
+}
+
+/// Whether `next` plays the same notes as `step`, apart from their duration, so the two can be
+/// merged into one step. Both must be held for their whole step and carry no control events.
+fn is_repeat(step: &Chord, next: &Chord) -> bool {
+    let held = |chord: &Chord| chord.total_duration() > 0 && chord.notes.iter()
+        .all(|n| n.control.is_none() && n.duration == chord.total_duration());
+    let resting = |chord: &Chord| chord.notes.iter().all(|n| n.is_rest());
+    held(step) && held(next) && if resting(step) {
+        resting(next)
+    } else {
+        step.notes.iter().map(|n| n.set_duration(0)).eq(next.notes.iter().map(|n| n.set_duration(0)))
+    }
+}
+
+/// Spreads `pulses` hits over `steps` steps by repeatedly pairing off the groups of hits with the
+/// groups of rests until at most one group is left over.
+fn bjorklund(pulses: usize, steps: usize) -> Vec<bool> {
+    let pulses = pulses.min(steps);
+    let mut groups = vec![vec![true]; pulses];
+    let mut remainder = vec![vec![false]; steps - pulses];
+    while remainder.len() > 1 && !groups.is_empty() {
+        let paired = groups.len().min(remainder.len());
+        let leftover = if groups.len() > paired {
+            groups.split_off(paired)
+        } else {
+            remainder.split_off(paired)
+        };
+        for (group, rest) in groups.iter_mut().zip(remainder) {
+            group.extend(rest);
+        }
+        remainder = leftover;
+    }
+    groups.into_iter().chain(remainder).flatten().collect()
+}
+
+/// Renders one cycle of a periodic midibox as a text timeline such as `|C4--|E4--|----|G4------|`.
+///
+/// Each step is drawn as a cell two characters wide per tick, starting with the names of its
+/// notes (joined with `+` for chords) and padded with `-`. Rests are drawn as all dashes.
+/// Returns `None` for midiboxes that don't repeat.
+pub fn render_preview(midibox: &dyn Midibox) -> Option<String> {
+    let steps = midibox.cycle()?;
+    let mut preview = String::from("|");
+    for notes in steps {
+        let ticks = notes.iter().map(|n| n.duration).max().unwrap_or(0) as usize;
+        let names: Vec<String> = notes.iter()
+            .filter(|n| !n.is_rest())
+            .map(|n| n.to_string())
+            .collect();
+        let label = names.join("+");
+        let width = (ticks * PREVIEW_TICK_WIDTH).max(label.len());
+        preview.push_str(&format!("{:-<width$}|", label, width = width));
+    }
+    Some(preview)
+}
+
+/// A boolean operation used to layer two rhythmic masks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaskOp {
+    And,
+    Or,
+    Xor,
+}
+
+impl MaskOp {
+    pub fn apply(&self, a: bool, b: bool) -> bool {
+        match self {
+            MaskOp::And => a && b,
+            MaskOp::Or => a || b,
+            MaskOp::Xor => a ^ b,
+        }
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    a / gcd(a, b) * b
 }
 
 impl Add<Seq> for Seq {
@@ -205,12 +1059,686 @@ impl Add<Interval> for Seq {
     }
 }
 
+/// The playback state of a `Seq`: its steps and the index of the next step to emit.
 pub struct IterSeq {
-    iter: Box<dyn Iterator<Item=Vec<Midi>>>
+    steps: Vec<Vec<Midi>>,
+    position: usize,
 }
 
 impl Midibox for IterSeq {
     fn next(&mut self) -> Option<Vec<Midi>> {
-        self.iter.next()
+        let step = self.steps.get(self.position)?.clone();
+        self.position = (self.position + 1) % self.steps.len();
+        Some(step)
+    }
+
+    fn cycle(&self) -> Option<Vec<Vec<Midi>>> {
+        self.peek(self.steps.len())
+    }
+
+    fn peek(&self, count: usize) -> Option<Vec<Vec<Midi>>> {
+        Some(
+            self.steps.iter()
+                .cycle()
+                .skip(self.position)
+                .take(if self.steps.is_empty() { 0 } else { count })
+                .cloned()
+                .collect()
+        )
+    }
+}
+
+/// The playback state of `Seq::once`: the steps to play, the index of the next one, and the
+/// length of the rests that follow them.
+pub struct OneShot {
+    steps: Vec<Vec<Midi>>,
+    position: usize,
+    rest_ticks: u32,
+}
+
+impl OneShot {
+    fn rest(&self) -> Vec<Midi> {
+        vec![Midi::rest().set_duration(self.rest_ticks)]
+    }
+}
+
+impl Midibox for OneShot {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        let step = self.steps.get(self.position).cloned().unwrap_or_else(|| self.rest());
+        self.position = (self.position + 1).min(self.steps.len());
+        Some(step)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.steps.len()
+    }
+
+    /// Only repeats once it has played through, after which its cycle is a single rest.
+    fn cycle(&self) -> Option<Vec<Vec<Midi>>> {
+        (self.position >= self.steps.len()).then(|| vec![self.rest()])
+    }
+
+    fn peek(&self, count: usize) -> Option<Vec<Vec<Midi>>> {
+        Some(
+            self.steps.iter()
+                .skip(self.position)
+                .cloned()
+                .chain(std::iter::repeat_with(|| self.rest()))
+                .take(count)
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::collect_emissions;
+    use crate::meter::{Bpm, NoteValue};
+    use crate::midi::Midi;
+    use crate::scale::{Degree, Interval, Scale};
+    use crate::sequences::{MaskOp, Seq};
+    use crate::smf::ParseError;
+    use crate::spec::SpecError;
+    use crate::tone::Tone;
+
+    #[test]
+    fn transformations_apply_across_chords() {
+        let pad = Seq::chords(vec![
+            Chord::new(vec![Tone::C.oct(4), Tone::E.oct(4), Tone::G.oct(4)]),
+            Chord::new(vec![Tone::D.oct(4), Tone::F.oct(4)]),
+        ]);
+        let pitches = |seq: Seq| -> Vec<Vec<Option<u8>>> {
+            seq.midibox().cycle().unwrap().iter()
+                .map(|notes| notes.iter().map(|n| n.u8_maybe()).collect())
+                .collect()
+        };
+        assert_eq!(pitches(pad.clone().transpose_up(Interval::Maj2)), vec![
+            vec![Some(62), Some(66), Some(69)],
+            vec![Some(64), Some(67)],
+        ]);
+        assert_eq!(pitches(pad.clone().mask(&[false, true])), vec![
+            vec![None, None, None],
+            vec![Some(62), Some(65)],
+        ]);
+        let durations: Vec<Vec<u32>> = pad.duration(3).midibox().cycle().unwrap().iter()
+            .map(|notes| notes.iter().map(|n| n.duration).collect())
+            .collect();
+        assert_eq!(durations, vec![vec![3, 3, 3], vec![3, 3]]);
+    }
+
+    #[test]
+    fn retrograde_turns_ties_round() {
+        let c = Tone::C.oct(4);
+        let seq = Seq::new(vec![
+            c.set_tie(true) * 1, c * 2, Tone::E.oct(4) * 1, Tone::G.oct(4).set_tie(true) * 1,
+        ]);
+        assert_eq!(seq.retrograde().midibox().cycle(), Some(vec![
+            // the G was tied into nothing, so it loses its tie
+            vec![Tone::G.oct(4) * 1],
+            vec![Tone::E.oct(4) * 1],
+            vec![c.set_tie(true) * 2],
+            vec![c * 1],
+        ]));
+    }
+
+    #[test]
+    fn invert_mirrors_pitches_around_the_axis() {
+        let seq = Seq::new(vec![Tone::E.oct(4) * 2, Midi::rest(), Tone::G.oct(4), Tone::C.oct(9)]);
+        let pitches: Vec<Option<u8>> = seq.invert(Tone::C.oct(4)).midibox().cycle().unwrap().iter()
+            .map(|notes| notes[0].u8_maybe())
+            .collect();
+        // C9 would mirror to MIDI note 0, below C0, so comes back up an octave
+        assert_eq!(pitches, vec![Some(56), None, Some(53), Some(12)]);
+    }
+
+    #[test]
+    fn harmonize_chord_stacks_diatonic_voices() {
+        let seq = Seq::new(vec![Tone::C.oct(4) * 2, Midi::rest(), Tone::Db.oct(4), Tone::B.oct(4)]);
+        let degrees = vec![Degree::Unison, Degree::Third, Degree::Fifth];
+        let chords: Vec<Vec<Option<u8>>> = seq.harmonize_chord(&Scale::major(Tone::C), degrees)
+            .midibox().cycle().unwrap().iter()
+            .map(|notes| notes.iter().map(|n| n.u8_maybe()).collect())
+            .collect();
+        // Db snaps down to C before harmonizing, and the unison adds nothing new
+        assert_eq!(chords, vec![
+            vec![Some(60), Some(64), Some(67)],
+            vec![None],
+            vec![Some(60), Some(64), Some(67)],
+            vec![Some(71), Some(74), Some(77)],
+        ]);
+    }
+
+    #[test]
+    fn accent_cycles_velocities_over_the_steps() {
+        let seq = Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4), Midi::rest(), Tone::E.oct(4), Tone::F.oct(4)]);
+        let velocities: Vec<u8> = seq.clone().accent(&[200, 60]).midibox().cycle().unwrap().iter()
+            .map(|notes| notes[0].velocity)
+            .collect();
+        // the rest keeps its velocity, and 200 is clamped
+        assert_eq!(velocities, vec![127, 60, 100, 60, 127]);
+        assert_eq!(seq.clone().accent(&[]).midibox().cycle(), seq.midibox().cycle());
+    }
+
+    #[test]
+    fn empty_sequences_compose_without_panicking() {
+        let empty = Seq::empty().fast_forward(3);
+        assert_eq!(empty.midibox().next(), None);
+        assert_eq!(empty.total_duration(), 0);
+        assert_eq!(empty.preview(), "|");
+        assert!(empty.clone().repeat(4).rotate(1).reverse().is_empty());
+
+        let seq = Seq::new(vec![Tone::C.oct(4) * 2, Tone::D.oct(4) * 1]);
+        let cycle = seq.midibox().cycle();
+        assert_eq!((empty.clone() + seq.clone()).midibox().cycle(), cycle);
+        assert_eq!(seq.clone().extend(&empty).midibox().cycle(), cycle);
+        assert_eq!((empty + seq).fast_forward(1).midibox().next(), Some(vec![Tone::D.oct(4) * 1]));
+    }
+
+    #[test]
+    fn gate_shortens_notes_but_keeps_the_step_timing() {
+        let seq = Seq::new(vec![
+            Tone::C.oct(4) * 4,
+            Midi::rest() * 2,
+            Tone::D.oct(4) * 1,
+            Tone::E.oct(4).set_tie(true) * 4,
+        ]);
+        let gated = seq.clone().gate(0.5);
+        assert_eq!(gated.total_duration(), seq.total_duration());
+        assert_eq!(gated.midibox().cycle(), Some(vec![
+            vec![Tone::C.oct(4) * 2],
+            vec![Midi::rest() * 2],
+            vec![Midi::rest() * 2],
+            // a note can't be shorter than a tick
+            vec![Tone::D.oct(4) * 1],
+            vec![Tone::E.oct(4).set_tie(true) * 4],
+        ]));
+        assert_eq!(seq.clone().gate(7.0).midibox().cycle(), seq.midibox().cycle());
+        assert_eq!(Seq::new(vec![Tone::C.oct(4) * 4]).gate(-1.0).midibox().cycle(), Some(vec![
+            vec![Tone::C.oct(4) * 1],
+            vec![Midi::rest() * 3],
+        ]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn metronome_accents_the_downbeat() {
+        let accent = Tone::A.oct(5).set_velocity(120) * 1;
+        let beat = Tone::A.oct(5).set_velocity(70) * 1;
+        let metronome = Seq::metronome(3, accent, beat * 8);
+        assert_eq!(metronome.total_duration(), 12);
+        assert_eq!(metronome.midibox().cycle(), Some(vec![
+            vec![accent],
+            vec![Midi::rest() * 3],
+            // too long for a beat, so cut down to one
+            vec![beat * 4],
+            vec![beat * 4],
+        ]));
+    }
+
+    #[test]
+    fn once_plays_through_and_then_rests() {
+        let seq = Seq::new(vec![Tone::C.oct(4) * 2, Tone::D.oct(4) * 1, Tone::E.oct(4) * 1]);
+        let mut once = seq.clone().fast_forward(1).once();
+        assert_eq!(once.cycle(), None);
+        assert_eq!(once.peek(3), Some(vec![
+            vec![Tone::D.oct(4) * 1],
+            vec![Tone::E.oct(4) * 1],
+            vec![Midi::rest() * 4],
+        ]));
+        assert_eq!(collect_emissions(once.as_mut(), 4), vec![
+            vec![Tone::D.oct(4) * 1],
+            vec![Tone::E.oct(4) * 1],
+            vec![Midi::rest() * 4],
+            vec![Midi::rest() * 4],
+        ]);
+        assert_eq!(once.cycle(), Some(vec![vec![Midi::rest() * 4]]));
+        assert_eq!(Seq::empty().once().next(), Some(vec![Midi::rest() * 1]));
+    }
+
+    #[test]
+    fn tie_repeats_merges_repeated_steps() {
+        let c = Tone::C.oct(4);
+        let joined = Seq::new(vec![Tone::D.oct(4) * 1, c * 2])
+            .extend(&Seq::new(vec![c * 1, Midi::rest() * 1]))
+            .extend(&Seq::new(vec![Midi::rest() * 2, c.set_velocity(60) * 1, c.set_velocity(60) * 1]));
+        assert_eq!(joined.tie_repeats().midibox().cycle(), Some(vec![
+            vec![Tone::D.oct(4) * 1],
+            vec![c * 3],
+            vec![Midi::rest() * 3],
+            vec![c.set_velocity(60) * 2],
+        ]));
+
+        // chords merge when all their notes repeat, but not when only some do
+        let chords = Seq::chords(vec![
+            Chord::new(vec![c * 1, Tone::E.oct(4) * 1]),
+            Chord::new(vec![c * 1, Tone::E.oct(4) * 1]),
+            Chord::new(vec![c * 1, Tone::G.oct(4) * 1]),
+        ]);
+        assert_eq!(chords.tie_repeats().midibox().cycle(), Some(vec![
+            vec![c * 2, Tone::E.oct(4) * 2],
+            vec![c * 1, Tone::G.oct(4) * 1],
+        ]));
+    }
+
+    #[test]
+    fn combine_masks() {
+        let a = [true, false, true, false];
+        let b = [true, true, false, false];
+        assert_eq!(Seq::combine_masks(&a, &b, MaskOp::And), vec![true, false, false, false]);
+        assert_eq!(Seq::combine_masks(&a, &b, MaskOp::Or), vec![true, true, true, false]);
+        assert_eq!(Seq::combine_masks(&a, &b, MaskOp::Xor), vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn combine_masks_of_different_lengths() {
+        // three against two repeats after six steps
+        let three = [true, false, false];
+        let two = [true, false];
+        assert_eq!(
+            Seq::combine_masks(&three, &two, MaskOp::Or),
+            vec![true, false, true, true, true, false]
+        );
+        assert_eq!(
+            Seq::combine_masks(&three, &two, MaskOp::And),
+            vec![true, false, false, false, false, false]
+        );
+        assert_eq!(
+            Seq::combine_masks(&three, &two, MaskOp::Xor),
+            vec![false, false, true, true, true, false]
+        );
+        assert!(Seq::combine_masks(&three, &[], MaskOp::Or).is_empty());
+    }
+
+    #[test]
+    fn rest_mask() {
+        let seq = Seq::new(vec![Tone::C.oct(4), Tone::Rest.oct(4), Tone::E.oct(4)]);
+        assert_eq!(seq.rest_mask(), vec![true, false, true]);
+    }
+
+    #[test]
+    fn from_grid() {
+        let mut midibox = Seq::from_grid(
+            vec![Tone::C.oct(4), Tone::G.oct(4)],
+            vec![
+                vec![Some(100), None, Some(90), None],
+                vec![Some(60), Some(70), None],
+            ],
+            2
+        ).midibox();
+
+        assert_eq!(midibox.next(), Some(vec![
+            Tone::C.oct(4).set_velocity(100) * 2,
+            Tone::G.oct(4).set_velocity(60) * 2,
+        ]));
+        assert_eq!(midibox.next(), Some(vec![Tone::G.oct(4).set_velocity(70) * 2]));
+        assert_eq!(midibox.next(), Some(vec![Tone::C.oct(4).set_velocity(90) * 2]));
+        assert_eq!(midibox.next(), Some(vec![Midi::rest() * 2]));
+        // and loops back around
+        assert_eq!(midibox.next().map(|notes| notes.len()), Some(2));
+    }
+
+    fn euclidean_pattern(seq: &Seq) -> String {
+        seq.notes.iter().map(|chord| if chord.notes[0].is_rest() { '.' } else { 'x' }).collect()
+    }
+
+    #[test]
+    fn euclidean() {
+        let note = Tone::C.oct(2) * 2;
+        assert_eq!(euclidean_pattern(&Seq::euclidean(3, 8, note)), "x..x..x.");
+        assert_eq!(euclidean_pattern(&Seq::euclidean(5, 8, note)), "x.xx.xx.");
+        assert_eq!(euclidean_pattern(&Seq::euclidean(4, 12, note)), "x..x..x..x..");
+        assert_eq!(euclidean_pattern(&Seq::euclidean(0, 3, note)), "...");
+        assert_eq!(euclidean_pattern(&Seq::euclidean(5, 3, note)), "xxx");
+        assert!(Seq::euclidean(3, 0, note).is_empty());
+        assert_eq!(euclidean_pattern(&Seq::euclidean_rotated(3, 8, 2, note)), ".x..x.x.");
+        assert_eq!(Seq::euclidean(3, 8, note).total_duration(), 16);
+    }
+
+    #[test]
+    fn rotate() {
+        let seq = Seq::new(vec![Tone::A.oct(4), Tone::B.oct(4), Tone::C.oct(4)]);
+        let tones = |seq: Seq| seq.notes.iter().map(|c| c.notes[0].tone).collect::<Vec<Tone>>();
+        assert_eq!(tones(seq.clone().rotate(1)), vec![Tone::B, Tone::C, Tone::A]);
+        assert_eq!(tones(seq.clone().rotate(-1)), vec![Tone::C, Tone::A, Tone::B]);
+        assert_eq!(tones(seq.clone().rotate(7)), vec![Tone::B, Tone::C, Tone::A]);
+        assert_eq!(tones(seq.clone().rotate(-6)), vec![Tone::A, Tone::B, Tone::C]);
+        assert!(Seq::empty().rotate(3).is_empty());
+    }
+
+    #[test]
+    fn ping_pong() {
+        let seq = Seq::new(vec![Tone::A.oct(4), Tone::B.oct(4), Tone::C.oct(4), Tone::D.oct(4)]);
+        let tones = |seq: Seq| seq.notes.iter().map(|c| c.notes[0].tone).collect::<Vec<Tone>>();
+        assert_eq!(tones(seq.clone().ping_pong(true)), vec![
+            Tone::A, Tone::B, Tone::C, Tone::D, Tone::D, Tone::C, Tone::B, Tone::A,
+        ]);
+        assert_eq!(tones(seq.ping_pong(false)), vec![
+            Tone::A, Tone::B, Tone::C, Tone::D, Tone::C, Tone::B,
+        ]);
+        assert_eq!(tones(Seq::new(vec![Tone::A.oct(4), Tone::B.oct(4)]).ping_pong(false)), vec![Tone::A, Tone::B]);
+        assert_eq!(tones(Seq::new(vec![Tone::A.oct(4)]).ping_pong(false)), vec![Tone::A]);
+    }
+
+    #[test]
+    fn from_chords() {
+        let scale = Scale::major(Tone::C);
+        let mut midibox = Seq::from_chords(vec![
+            scale.triad(Tone::C.oct(4) * 2, Degree::Fifth),
+            scale.triad(Tone::Rest * 2, Degree::Unison),
+        ]).midibox();
+        assert_eq!(midibox.next(), Some(vec![Tone::G.oct(4) * 2, Tone::B.oct(4) * 2, Tone::D.oct(5) * 2]));
+        assert_eq!(midibox.next(), Some(vec![Midi::rest()]));
+    }
+
+    #[test]
+    fn octave_shifts_stay_in_range() {
+        let seq = Seq::new(vec![Tone::C.oct(4), Tone::Rest * 1, Tone::F.oct(9), Tone::A.oct(0)]);
+        let pitches = |seq: Seq| seq.notes.iter().map(|c| c.notes[0].u8_maybe()).collect::<Vec<_>>();
+        // F9 (125) can't go up at all, and A0 (21) can't go down
+        assert_eq!(pitches(seq.clone().octave_up(1)), vec![Some(72), None, Some(125), Some(33)]);
+        assert_eq!(pitches(seq.clone().octave_up(20)), vec![Some(120), None, Some(125), Some(117)]);
+        assert_eq!(pitches(seq.clone().octave_down(2)), vec![Some(36), None, Some(101), Some(21)]);
+        assert_eq!(pitches(seq.clone().octave_down(20)), vec![Some(12), None, Some(17), Some(21)]);
+        assert_eq!(pitches(seq.octave_down(0)), vec![Some(60), None, Some(125), Some(21)]);
+    }
+
+    #[test]
+    fn preview() {
+        let seq = Seq::new(vec![
+            Tone::C.oct(4) * 2,
+            Tone::E.oct(4) * 2,
+            Tone::Rest * 2,
+            Tone::G.oct(4) * 4,
+        ]);
+        assert_eq!(seq.preview(), "|C4--|E4--|----|G4------|");
+        assert_eq!(
+            Seq::chords(vec![Chord::new(vec![Tone::C.oct(4), Tone::E.oct(4)])]).preview(),
+            "|C4+E4|"
+        );
+        assert_eq!(Seq::empty().preview(), "|");
+    }
+
+    #[test]
+    fn split_bars() {
+        let bars = Seq::new(vec![
+            Tone::C.oct(4) * 3,
+            Tone::E.oct(4) * 3,
+            Tone::Rest * 5,
+            Tone::G.oct(4) * 1,
+        ]).split_bars(4);
+
+        assert_eq!(bars.len(), 3);
+        assert!(bars.iter().all(|bar| bar.total_duration() == 4));
+        assert_eq!(bars[0].preview(), "|C4----|E4|");
+        assert_eq!(bars[1].preview(), "|E4--|----|");
+        assert_eq!(bars[2].preview(), "|------|G4|");
+
+        // the straddling note is tied into its continuation
+        let mut first = bars[0].midibox();
+        first.next();
+        assert_eq!(first.next(), Some(vec![Tone::E.oct(4).set_tie(true)]));
+        assert_eq!(bars[1].midibox().next(), Some(vec![Tone::E.oct(4) * 2]));
+    }
+
+    #[test]
+    fn peek() {
+        let mut midibox = Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4), Tone::E.oct(4)]).midibox();
+        midibox.next();
+        assert_eq!(midibox.peek(4), Some(vec![
+            vec![Tone::D.oct(4)],
+            vec![Tone::E.oct(4)],
+            vec![Tone::C.oct(4)],
+            vec![Tone::D.oct(4)],
+        ]));
+        // peeking doesn't move the head
+        assert_eq!(midibox.next(), Some(vec![Tone::D.oct(4)]));
+        assert_eq!(Seq::empty().midibox().peek(4), Some(vec![]));
+    }
+
+    #[test]
+    fn shuffle_within() {
+        let seq = Seq::new((0..16).map(|i| Midi::from(60 + i)).collect());
+        let shuffled = seq.clone().shuffle_within(4, 7);
+
+        let original = seq.midibox().peek(16).unwrap();
+        let steps = shuffled.midibox().peek(16).unwrap();
+        assert_ne!(steps, original);
+        for window in 0..4 {
+            let mut expected: Vec<Option<u8>> = original[window * 4..(window + 1) * 4].iter()
+                .map(|notes| notes[0].u8_maybe())
+                .collect();
+            let mut actual: Vec<Option<u8>> = steps[window * 4..(window + 1) * 4].iter()
+                .map(|notes| notes[0].u8_maybe())
+                .collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected);
+        }
+        assert_eq!(seq.shuffle_within(4, 7).midibox().peek(16).unwrap(), steps);
+    }
+
+    #[test]
+    fn push_pull() {
+        let seq = Seq::new(vec![
+            Tone::C.oct(4) * 2, Tone::Rest * 2,
+            Tone::D.oct(4) * 2, Tone::Rest * 2,
+            Tone::E.oct(4) * 2, Tone::Rest * 2,
+            Tone::F.oct(4) * 2, Tone::Rest * 2,
+        ]).push_pull(vec![0, 1, 0, -1], 16);
+
+        assert_eq!(seq.total_duration(), 16);
+        let mut onsets: Vec<(u32, Tone)> = Vec::new();
+        let mut position = 0;
+        for notes in seq.midibox().cycle().unwrap() {
+            if !notes[0].is_rest() {
+                assert_eq!(notes[0].duration, 2);
+                onsets.push((position, notes[0].tone));
+            }
+            position += notes[0].duration;
+        }
+        assert_eq!(onsets, vec![(0, Tone::C), (5, Tone::D), (8, Tone::E), (11, Tone::F)]);
+
+        // laid back onto the end of the sequence, the D keeps its last tick
+        let late = Seq::new(vec![Tone::C.oct(4) * 2, Tone::D.oct(4) * 2]).push_pull(vec![0, 2], 4);
+        assert_eq!(late.total_duration(), 4);
+        assert_eq!(late.midibox().cycle().unwrap(), vec![
+            vec![Tone::C.oct(4) * 2],
+            vec![Tone::Rest * 1],
+            vec![Tone::D.oct(4) * 1],
+        ]);
+    }
+
+    fn track_chunk(events: &[u8]) -> Vec<u8> {
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((events.len() as u32).to_be_bytes());
+        chunk.extend(events);
+        chunk
+    }
+
+    #[test]
+    fn from_smf_multi() {
+        let mut file = b"MThd".to_vec();
+        file.extend([0, 0, 0, 6, 0, 1, 0, 3, 0, 96]);
+        // A tempo track with no notes
+        file.extend(track_chunk(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x00, 0xFF, 0x2F, 0x00]));
+        file.extend(track_chunk(&[
+            0x00, 0xFF, 0x03, 0x04, b'B', b'a', b's', b's',
+            0x00, 0x91, 36, 100,
+            0x60, 0x81, 36, 0,
+            // Running status, with a zero-velocity note-on as the note-off
+            0x60, 0x91, 40, 80,
+            0x30, 40, 0,
+            0x30, 0xFF, 0x2F, 0x00,
+        ]));
+        file.extend(track_chunk(&[
+            0x00, 0xFF, 0x03, 0x04, b'K', b'e', b'y', b's',
+            0x00, 0x90, 60, 64,
+            0x00, 0x90, 64, 64,
+            0x81, 0x40, 0x80, 60, 0,
+            0x00, 0x80, 64, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ]));
+
+        let tracks = Seq::from_smf_multi(&file).unwrap();
+        let names: Vec<&str> = tracks.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Bass", "Keys"]);
+
+        let bass = tracks[0].1.midibox().cycle().unwrap();
+        assert_eq!(bass, vec![
+            vec![Tone::C.oct(2).set_channel(1) * 4],
+            vec![Tone::Rest * 4],
+            vec![Tone::E.oct(2).set_velocity(80).set_channel(1) * 2],
+            vec![Tone::Rest * 2],
+        ]);
+        let keys = tracks[1].1.midibox().cycle().unwrap();
+        assert_eq!(keys, vec![vec![
+            Tone::C.oct(4).set_velocity(64).set_channel(0) * 8,
+            Tone::E.oct(4).set_velocity(64).set_channel(0) * 8,
+        ]]);
+
+        assert!(Seq::from_smf_multi(b"MThd").is_err());
+    }
+
+    #[test]
+    fn from_json_and_ron() {
+        let expected = vec![
+            vec![Tone::C.oct(4).set_velocity(90) * 2],
+            vec![Midi::rest()],
+            vec![Tone::E.oct(4), Tone::Bb.oct(3)],
+        ];
+        let json = r#"{ "steps": [
+            { "pitch": "C4", "duration": 2, "velocity": 90 },
+            { "pitch": "rest" },
+            [{ "pitch": "E4" }, { "pitch": "Bb3" }]
+        ] }"#;
+        assert_eq!(Seq::from_json(json).unwrap().midibox().cycle(), Some(expected.clone()));
+        let ron = r#"(steps: [
+            (pitch: "C4", duration: 2, velocity: 90),
+            (pitch: "Rest"),
+            [(pitch: "E4"), (pitch: "Bb3")],
+        ])"#;
+        assert_eq!(Seq::from_ron(ron).unwrap().midibox().cycle(), Some(expected));
+
+        let error = Seq::from_json("{ \"steps\": [\n  { \"pitch\": \"H4\" }\n] }").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("Unknown note: H4"));
+        assert!(matches!(Seq::from_ron("(steps: [(pitch: \"C4\",)"), Err(SpecError { line: 1, .. })));
+    }
+
+    #[test]
+    fn to_json_and_ron_round_trip() {
+        let seq = Seq::chords(vec![
+            Chord::note(Tone::Eb.oct(2).set_velocity(30) * 3),
+            Chord::note(Midi::rest() * 2),
+            Chord::new(vec![Tone::G.oct(4), Tone::C.oct(5) * 2]),
+        ]);
+        let loaded = Seq::from_json(&seq.to_json()).unwrap();
+        assert_eq!(loaded.midibox().cycle(), seq.midibox().cycle());
+        assert_eq!(Seq::from_json(&loaded.to_json()).unwrap().to_spec(), loaded.to_spec());
+        let loaded = Seq::from_ron(&seq.to_ron()).unwrap();
+        assert_eq!(loaded.midibox().cycle(), seq.midibox().cycle());
+        assert!(seq.to_json().contains("\"Eb2\""));
+    }
+
+    #[test]
+    fn durations_from_note_values() {
+        let seq = Seq::new(vec![Tone::C.oct(4); 6]).durations_from(vec![
+            NoteValue::QUARTER.dotted(), NoteValue::EIGHTH,
+            NoteValue::EIGHTH.triplet(), NoteValue::EIGHTH.triplet(), NoteValue::EIGHTH.triplet(),
+        ]);
+        let durations: Vec<u32> = seq.midibox().cycle().unwrap().iter().map(|c| c[0].duration).collect();
+        assert_eq!(durations, vec![6, 2, 1, 2, 1, 6]);
+        assert_eq!(Tone::C.oct(4).eighth().duration, 2);
+        assert_eq!(Tone::C.oct(4).set_note_value(NoteValue::HALF.dotted()).duration, 12);
+    }
+
+    #[test]
+    fn map_pitch_class() {
+        let lydian = Seq::new(Scale::major(Tone::C).midi(4))
+            .map_pitch_class(Tone::F, |m| m + Interval::Min2);
+        let tones: Vec<Tone> = lydian.midibox().cycle().unwrap().iter().map(|c| c[0].tone).collect();
+        assert_eq!(tones, vec![Tone::C, Tone::D, Tone::E, Tone::Gb, Tone::G, Tone::A, Tone::B]);
+
+        let softened = Seq::new(vec![Tone::F.oct(2), Tone::F.oct(5), Tone::Rest * 1])
+            .map_pitch_class(Tone::F, |m| m.set_velocity(10));
+        let velocities: Vec<u8> = softened.midibox().cycle().unwrap().iter().map(|c| c[0].velocity).collect();
+        assert_eq!(velocities, vec![10, 10, 100]);
+    }
+
+    #[test]
+    fn fade_join() {
+        let verse = Seq::new(vec![Tone::C.oct(4) * 4, Tone::D.oct(4), Tone::E.oct(4), Tone::F.oct(4), Tone::G.oct(4)]);
+        let chorus = Seq::new(vec![Tone::A.oct(4), Tone::B.oct(4), Tone::C.oct(5), Tone::D.oct(5), Tone::E.oct(5) * 4]);
+        let joined = verse.fade_join(&chorus.velocity(80), 4);
+
+        let velocities: Vec<u8> = joined.midibox().cycle().unwrap().iter().map(|c| c[0].velocity).collect();
+        assert_eq!(velocities, vec![100, 100, 75, 50, 25, 20, 40, 60, 80, 80]);
+        assert_eq!(joined.total_duration(), 16);
+    }
+
+    #[test]
+    fn quantize_dynamics() {
+        let recorded = Seq::new([1, 20, 30, 50, 64, 70, 90, 102, 127].iter()
+            .map(|v| Tone::C.oct(4).set_velocity(*v))
+            .collect());
+        let velocities: Vec<u8> = recorded.quantize_dynamics(5).midibox().cycle().unwrap().iter()
+            .map(|c| c[0].velocity)
+            .collect();
+        assert_eq!(velocities, vec![13, 13, 38, 38, 64, 64, 90, 90, 115]);
+    }
+
+    #[test]
+    fn to_abc() {
+        let scale = Seq::new(Scale::major(Tone::C).midi(4))
+            .extend(&Seq::new(vec![Tone::C.oct(5) * 4, Tone::Rest * 2]))
+            .extend(&Seq::chords(vec![Chord::new(vec![Tone::Bb.oct(3) * 2, Tone::D.oct(6) * 2])]));
+        assert_eq!(
+            scale.to_abc(&Bpm::new(480)),
+            "X:1\nM:none\nL:1/16\nQ:1/4=120\nK:C\nC D E F G A B c4 z2 [_B,d']2\n"
+        );
+    }
+
+    #[test]
+    fn invert_dynamics() {
+        let straight = Seq::new((0..8)
+            .map(|i| Tone::C.oct(4).set_velocity(if i % 2 == 0 { 100 } else { 60 }) * 2)
+            .collect());
+        let skank: Vec<u8> = straight.invert_dynamics(16).midibox().cycle().unwrap().iter()
+            .map(|c| c[0].velocity)
+            .collect();
+        assert_eq!(skank, vec![60, 100, 60, 100, 60, 100, 60, 100]);
+    }
+
+    #[test]
+    fn from_smf() {
+        let mut file = b"MThd".to_vec();
+        file.extend([0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        file.extend(track_chunk(&[
+            0x00, 0x90, 60, 100,
+            // 95 ticks is just short of a beat, and is rounded up to it
+            0x5F, 0x80, 60, 0,
+            0x19, 0x90, 62, 90,
+            0x30, 0x80, 62, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ]));
+        let (seq, quantized) = Seq::from_smf(&file, 0).unwrap();
+        assert_eq!(quantized, 1);
+        assert_eq!(seq.midibox().cycle().unwrap(), vec![
+            vec![Tone::C.oct(4).set_channel(0) * 4],
+            vec![Tone::Rest * 1],
+            vec![Tone::D.oct(4).set_velocity(90).set_channel(0) * 2],
+        ]);
+        assert_eq!(Seq::from_smf(&file, 1).unwrap_err(), ParseError::MissingTrack(1));
+
+        let mut chord = b"MThd".to_vec();
+        chord.extend([0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        chord.extend(track_chunk(&[
+            0x00, 0x90, 60, 100,
+            0x18, 0x90, 64, 100,
+            0x60, 0x80, 60, 0,
+            0x00, 0x80, 64, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ]));
+        assert_eq!(Seq::from_smf(&chord, 0).unwrap_err(), ParseError::Polyphonic { track: 0, at: 24 });
+    }
+}
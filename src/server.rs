@@ -1,8 +1,6 @@
 use std::collections::HashMap;
-use std::hash::Hash;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use crossbeam::atomic::AtomicCell;
 use env_logger::init;
 use log::info;
 use tonic::{transport::Server, Request, Response, Status};
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::info;
+use midir::{MidiOutput, MidiOutputConnection};
+
+/// A destination for the raw MIDI messages produced by the player.
+pub trait MidiSink {
+    fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError>;
+}
+
+/// Why a message couldn't be delivered during playback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaybackError {
+    /// The router has no port for this channel.
+    NoRoute { channel_id: usize },
+    /// The port the message was routed to has no open connection.
+    ConnectionMissing { port_id: usize },
+    /// The connection to the port refused the message, e.g. because the device was unplugged.
+    SendFailed { port_id: usize, reason: String },
+}
+
+impl PlaybackError {
+    /// The port that failed, for errors that are down to a port rather than the configuration.
+    pub fn port_id(&self) -> Option<usize> {
+        match self {
+            PlaybackError::NoRoute { .. } => None,
+            PlaybackError::ConnectionMissing { port_id } => Some(*port_id),
+            PlaybackError::SendFailed { port_id, .. } => Some(*port_id),
+        }
+    }
+}
+
+impl Display for PlaybackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaybackError::NoRoute { channel_id } => {
+                write!(f, "no port configured for channel {}", channel_id)
+            }
+            PlaybackError::ConnectionMissing { port_id } => {
+                write!(f, "could not find connection for port {}", port_id)
+            }
+            PlaybackError::SendFailed { port_id, reason } => {
+                write!(f, "failed to send to port {}: {}", port_id, reason)
+            }
+        }
+    }
+}
+
+impl Error for PlaybackError {}
+
+/// Playback was asked to send to output ports that aren't there, e.g. because a device is
+/// unplugged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingPorts {
+    /// The indices of the required ports that don't exist, lowest first.
+    pub missing: Vec<usize>,
+    /// How many output ports there are.
+    pub available: usize,
+}
+
+impl MissingPorts {
+    /// Fails with the ports out of `required` that aren't among the `available` ones, if any.
+    pub fn check(required: &HashSet<usize>, available: usize) -> Result<(), MissingPorts> {
+        let mut missing: Vec<usize> = required.iter().copied().filter(|port| *port >= available).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        Err(MissingPorts { missing, available })
+    }
+}
+
+impl Display for MissingPorts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let missing: Vec<String> = self.missing.iter().map(|port| port.to_string()).collect();
+        write!(
+            f, "missing MIDI output port(s) {}: only {} port(s) available",
+            missing.join(", "), self.available
+        )
+    }
+}
+
+impl Error for MissingPorts {}
+
+/// Sends messages to hardware (or virtual) MIDI output ports through `midir`.
+pub struct MidirSink {
+    port_id_to_conn: HashMap<usize, MidiOutputConnection>,
+}
+
+impl MidirSink {
+    /// Opens a connection to each of the required output ports, failing with `MissingPorts`
+    /// before connecting to any if some of them don't exist.
+    pub fn connect(required_ports: &HashSet<usize>) -> Result<Self, Box<dyn Error>> {
+        let midi_out = MidiOutput::new("Midi Outputs")?;
+        let out_ports = midi_out.ports();
+
+        for (i, p) in out_ports.iter().enumerate() {
+            info!("{}: {}", i, midi_out.port_name(p).unwrap());
+        }
+        MissingPorts::check(required_ports, out_ports.len())?;
+
+        let mut port_id_to_conn: HashMap<usize, MidiOutputConnection> =
+            HashMap::with_capacity(required_ports.len());
+
+        for i in 0..out_ports.len() {
+            let port = out_ports.get(i).expect("Missing midi port");
+            let port_name = format!("midibox {}", i);
+            let output = MidiOutput::new(&port_name)?;
+
+            if required_ports.contains(&i) {
+                let conn = output.connect(port, &port_name)?;
+                port_id_to_conn.insert(i, conn);
+            }
+        }
+
+        Ok(MidirSink { port_id_to_conn })
+    }
+}
+
+impl MidiSink for MidirSink {
+    fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+        self.port_id_to_conn.get_mut(&port_id)
+            .ok_or(PlaybackError::ConnectionMissing { port_id })?
+            .send(message)
+            .map_err(|err| PlaybackError::SendFailed { port_id, reason: err.to_string() })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    pub port_id: usize,
+    pub message: Vec<u8>,
+    /// When the message was sent.
+    pub at: Instant,
+}
+
+/// Keeps every message in memory instead of sending it anywhere.
+///
+/// Clones share the same recording, so a clone can be handed to the player and the original
+/// inspected once playback has finished.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSink {
+    messages: Arc<Mutex<Vec<RecordedMessage>>>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<RecordedMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl MidiSink for RecordingSink {
+    fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+        self.messages.lock().unwrap().push(RecordedMessage {
+            port_id,
+            message: message.to_vec(),
+            at: Instant::now(),
+        });
+        Ok(())
+    }
+}
+
+/// Forwards every message to each of its sinks in turn, e.g. to play to hardware while recording.
+///
+/// A failing sink doesn't stop the message reaching the others; the first error is returned once
+/// every sink has been tried.
+pub struct TeeSink(pub Vec<Box<dyn MidiSink>>);
+
+impl MidiSink for TeeSink {
+    fn send(&mut self, port_id: usize, message: &[u8]) -> Result<(), PlaybackError> {
+        let mut result = Ok(());
+        for sink in self.0.iter_mut() {
+            let sent = sink.send(port_id, message);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::sink::{MidiSink, MissingPorts, RecordingSink, TeeSink};
+
+    #[test]
+    fn tee_sends_to_every_sink() {
+        let first = RecordingSink::new();
+        let second = RecordingSink::new();
+        let mut tee = TeeSink(vec![Box::new(first.clone()), Box::new(second.clone())]);
+        tee.send(0, &[0x90, 60, 100]).unwrap();
+        tee.send(1, &[0x80, 60, 100]).unwrap();
+
+        for sink in [first, second] {
+            let messages: Vec<(usize, Vec<u8>)> = sink.messages().into_iter()
+                .map(|m| (m.port_id, m.message))
+                .collect();
+            assert_eq!(messages, vec![(0, vec![0x90, 60, 100]), (1, vec![0x80, 60, 100])]);
+        }
+    }
+
+    #[test]
+    fn missing_ports_lists_every_absent_index() {
+        assert_eq!(MissingPorts::check(&HashSet::from([0, 1]), 2), Ok(()));
+        let missing = MissingPorts::check(&HashSet::from([7, 1, 5]), 2).unwrap_err();
+        assert_eq!(missing, MissingPorts { missing: vec![5, 7], available: 2 });
+        assert_eq!(missing.to_string(), "missing MIDI output port(s) 5, 7: only 2 port(s) available");
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// How many of the crate's ticks make up one beat (quarter note) when converting to and from
+/// Standard MIDI Files, i.e. a tick is a sixteenth note.
+pub const TICKS_PER_BEAT: u32 = 4;
+
+/// Why a Standard MIDI File couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The bytes aren't a Standard MIDI File this crate can read.
+    Malformed(String),
+    /// The file has no track with this index.
+    MissingTrack(usize),
+    /// A note starts while another is still sounding, at this time in file ticks, in a track
+    /// that was expected to be monophonic.
+    Polyphonic { track: usize, at: u64 },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Malformed(reason) => write!(f, "malformed MIDI file: {}", reason),
+            ParseError::MissingTrack(track) => write!(f, "MIDI file has no track {}", track),
+            ParseError::Polyphonic { track, at } => write!(
+                f, "track {} is polyphonic: a note starts at tick {} while another is sounding",
+                track, at
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// A note read from a Standard MIDI File, timed in the file's own ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SmfNote {
+    pub start: u64,
+    pub end: u64,
+    pub key: u8,
+    pub velocity: u8,
+    pub channel: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SmfTrack {
+    pub name: Option<String>,
+    /// Every note in the track, ordered by start time.
+    pub notes: Vec<SmfNote>,
+    /// When the end-of-track event falls, in file ticks.
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Smf {
+    /// File ticks per beat, from the header's division field.
+    pub division: u16,
+    pub tracks: Vec<SmfTrack>,
+}
+
+impl Smf {
+    /// Converts a time in file ticks to the nearest crate tick.
+    pub fn to_crate_ticks(&self, ticks: u64) -> u32 {
+        let division = self.division as u64;
+        ((ticks * TICKS_PER_BEAT as u64 + division / 2) / division) as u32
+    }
+
+    /// Whether a time in file ticks falls exactly on a crate tick.
+    pub fn is_on_crate_tick(&self, ticks: u64) -> bool {
+        (ticks * TICKS_PER_BEAT as u64).is_multiple_of(self.division as u64)
+    }
+}
+
+/// Builds a Type-1 Standard MIDI File from `tracks`, each a list of messages stamped with their
+/// time in file ticks and ordered by it. A conductor track holding the tempo comes first, and
+/// every track ends with an end-of-track event at `end`.
+pub(crate) fn write(
+    division: u16,
+    micros_per_beat: u32,
+    tracks: &[Vec<(u64, Vec<u8>)>],
+    end: u64,
+) -> Vec<u8> {
+    let mut bytes = b"MThd".to_vec();
+    bytes.extend(6_u32.to_be_bytes());
+    bytes.extend(1_u16.to_be_bytes());
+    bytes.extend((tracks.len() as u16 + 1).to_be_bytes());
+    bytes.extend(division.to_be_bytes());
+
+    let tempo = micros_per_beat.min(0xFF_FFFF).to_be_bytes();
+    let conductor = vec![(0, vec![0xFF, 0x51, 0x03, tempo[1], tempo[2], tempo[3]])];
+    for track in std::iter::once(&conductor).chain(tracks) {
+        let mut data = Vec::new();
+        let mut time = 0;
+        for (at, message) in track {
+            write_vlq(&mut data, at.saturating_sub(time));
+            data.extend(message);
+            time = time.max(*at);
+        }
+        write_vlq(&mut data, end.saturating_sub(time));
+        data.extend([0xFF, 0x2F, 0x00]);
+        bytes.extend(b"MTrk");
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(data);
+    }
+    bytes
+}
+
+fn write_vlq(bytes: &mut Vec<u8>, value: u64) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+/// Parses the header and every track of a Standard MIDI File, pairing each note-on with the
+/// note-off that ends it. Only the note events and track names are kept.
+pub(crate) fn parse(bytes: &[u8]) -> Result<Smf, ParseError> {
+    parse_file(bytes).map_err(ParseError::Malformed)
+}
+
+fn parse_file(bytes: &[u8]) -> Result<Smf, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let (id, header) = reader.chunk()?;
+    if id != b"MThd" || header.len() < 6 {
+        return Err("Missing MThd header".to_string());
+    }
+    let track_count = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 {
+        return Err("SMPTE time division is not supported".to_string());
+    }
+    if division == 0 {
+        return Err("Time division must not be zero".to_string());
+    }
+
+    let mut tracks = Vec::with_capacity(track_count as usize);
+    while tracks.len() < track_count as usize {
+        let (id, data) = reader.chunk()?;
+        // Unknown chunk types are allowed by the format and must be skipped.
+        if id == b"MTrk" {
+            tracks.push(parse_track(data)?);
+        }
+    }
+    Ok(Smf { division, tracks })
+}
+
+fn parse_track(data: &[u8]) -> Result<SmfTrack, String> {
+    let mut reader = Reader { bytes: data, position: 0 };
+    let mut name = None;
+    let mut notes = Vec::new();
+    let mut sounding: HashMap<(u8, u8), Vec<(u64, u8)>> = HashMap::new();
+    let mut time = 0;
+    let mut running_status = None;
+
+    while reader.position < data.len() {
+        time += reader.vlq()?;
+        let mut status = reader.byte()?;
+        if status < 0x80 {
+            // Running status: this byte is the first data byte of a repeated message.
+            reader.position -= 1;
+            status = running_status.ok_or("Data byte without a running status")?;
+        }
+        match status {
+            0xFF => {
+                let kind = reader.byte()?;
+                let length = reader.vlq()? as usize;
+                let payload = reader.take(length)?;
+                match kind {
+                    0x03 => name = Some(String::from_utf8_lossy(payload).into_owned()),
+                    0x2F => break,
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.vlq()? as usize;
+                reader.take(length)?;
+            }
+            _ => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                match status & 0xF0 {
+                    0x80 | 0x90 => {
+                        let key = reader.byte()?;
+                        let velocity = reader.byte()?;
+                        if status & 0xF0 == 0x90 && velocity > 0 {
+                            sounding.entry((channel, key)).or_default().push((time, velocity));
+                        } else if let Some(starts) = sounding.get_mut(&(channel, key)) {
+                            if !starts.is_empty() {
+                                let (start, velocity) = starts.remove(0);
+                                notes.push(SmfNote { start, end: time, key, velocity, channel });
+                            }
+                        }
+                    }
+                    0xC0 | 0xD0 => {
+                        reader.byte()?;
+                    }
+                    _ => {
+                        reader.take(2)?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Notes never switched off last until the end of the track.
+    for ((channel, key), starts) in sounding {
+        for (start, velocity) in starts {
+            notes.push(SmfNote { start, end: time, key, velocity, channel });
+        }
+    }
+    notes.sort_by_key(|n| (n.start, n.key));
+    Ok(SmfTrack { name, notes, end: time })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position + length;
+        let taken = self.bytes.get(self.position..end).ok_or("Unexpected end of file")?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a variable-length quantity: 7 bits per byte, high bit set on all but the last.
+    fn vlq(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Variable-length quantity is too long".to_string())
+    }
+
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8]), String> {
+        let id = self.take(4)?;
+        let length = self.take(4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]);
+        Ok((id, self.take(length as usize)?))
+    }
+}
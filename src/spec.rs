@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use crate::midi::Midi;
+
+/// A sequence written out as data, e.g. in a JSON or RON file, to be read with `Seq::from_json`
+/// or `Seq::from_ron` and written with `Seq::to_json` or `Seq::to_ron`.
+///
+/// In JSON:
+///
+/// ```json
+/// { "steps": [
+///     { "pitch": "C4", "duration": 2, "velocity": 90 },
+///     { "pitch": "rest" },
+///     [{ "pitch": "E4" }, { "pitch": "G4" }]
+/// ] }
+/// ```
+///
+/// and in RON:
+///
+/// ```ron
+/// (steps: [
+///     (pitch: "C4", duration: 2, velocity: 90),
+///     (pitch: "rest"),
+///     [(pitch: "E4"), (pitch: "G4")],
+/// ])
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeqSpec {
+    pub steps: Vec<StepSpec>,
+}
+
+/// One step of a `SeqSpec`: a single note, or a list of notes that start together.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepSpec {
+    Note(NoteSpec),
+    Chord(Vec<NoteSpec>),
+}
+
+// Told apart by hand rather than with `#[serde(untagged)]`, which would replace the note's own
+// error (e.g. an unknown pitch) with one that doesn't say what was wrong.
+impl<'de> Deserialize<'de> for StepSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(StepVisitor)
+    }
+}
+
+impl Serialize for StepSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            StepSpec::Note(note) => note.serialize(serializer),
+            StepSpec::Chord(notes) => notes.serialize(serializer),
+        }
+    }
+}
+
+struct StepVisitor;
+
+impl<'de> Visitor<'de> for StepVisitor {
+    type Value = StepSpec;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a note or a list of notes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<StepSpec, A::Error> {
+        NoteSpec::deserialize(MapAccessDeserializer::new(map)).map(StepSpec::Note)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<StepSpec, A::Error> {
+        Vec::deserialize(SeqAccessDeserializer::new(seq)).map(StepSpec::Chord)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoteSpec {
+    /// The note by name, e.g. `C4` or `Db2`, or `rest`. See `Midi::from_str`.
+    #[serde(deserialize_with = "deserialize_pitch", serialize_with = "serialize_pitch")]
+    pub pitch: Midi,
+    /// In ticks. Defaults to a single tick.
+    #[serde(default = "default_duration")]
+    pub duration: u32,
+    /// Defaults to 100, as for `Midi::from_tone`.
+    #[serde(default = "default_velocity")]
+    pub velocity: u8,
+}
+
+impl NoteSpec {
+    /// The spec of `midi`, without its channel, tie or release velocity. Control events and notes
+    /// outside `PITCH_RANGE` are written as rests.
+    pub(crate) fn from_midi(midi: &Midi) -> Self {
+        NoteSpec {
+            pitch: Midi::from_option(midi.u8_maybe()),
+            duration: midi.duration,
+            velocity: midi.velocity,
+        }
+    }
+
+    pub(crate) fn midi(&self) -> Midi {
+        self.pitch.set_duration(self.duration).set_velocity(self.velocity)
+    }
+}
+
+fn default_duration() -> u32 {
+    Midi::rest().duration
+}
+
+fn default_velocity() -> u8 {
+    Midi::rest().velocity
+}
+
+fn deserialize_pitch<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Midi, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+}
+
+fn serialize_pitch<S: Serializer>(pitch: &Midi, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pitch)
+}
+
+/// Why a sequence spec couldn't be read, and where in the text, counting lines and columns
+/// from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for SpecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for SpecError {}
+
+impl From<serde_json::Error> for SpecError {
+    fn from(error: serde_json::Error) -> Self {
+        // serde_json ends its messages with the position, which is kept separately here
+        let message = error.to_string();
+        let message = message.split(" at line ").next().unwrap_or_default();
+        SpecError { line: error.line(), column: error.column(), message: message.to_string() }
+    }
+}
+
+impl From<ron::error::SpannedError> for SpecError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        SpecError {
+            line: error.position.line,
+            column: error.position.col,
+            message: error.code.to_string(),
+        }
+    }
+}
+
+pub(crate) fn parse_json(text: &str) -> Result<SeqSpec, SpecError> {
+    Ok(serde_json::from_str(text)?)
+}
+
+pub(crate) fn parse_ron(text: &str) -> Result<SeqSpec, SpecError> {
+    Ok(ron::from_str(text)?)
+}
+
+pub(crate) fn write_json(spec: &SeqSpec) -> String {
+    serde_json::to_string_pretty(spec).expect("sequence specs always serialize")
+}
+
+pub(crate) fn write_ron(spec: &SeqSpec) -> String {
+    ron::ser::to_string_pretty(spec, ron::ser::PrettyConfig::default())
+        .expect("sequence specs always serialize")
+}
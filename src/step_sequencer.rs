@@ -0,0 +1,149 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// An edit to a `StepSequencer`'s pattern, sent from another thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepCommand {
+    /// Turns the step on at the sequencer note's velocity, or off if it was on.
+    ToggleStep(usize),
+    /// Turns the step on at the given velocity.
+    SetVelocity(usize, u8),
+    /// Turns every step off.
+    Clear,
+}
+
+/// A looping row of steps that each either play a note or rest, editable while playing.
+///
+/// Edits are sent over a channel (see `controller`) so that an input thread, e.g. one listening
+/// to a hardware controller, never touches the pattern directly. They are applied between steps,
+/// before the next step is emitted.
+pub struct StepSequencer {
+    note: Midi,
+    /// The velocity of each step, or `None` for a step that rests.
+    steps: Vec<Option<u8>>,
+    step_ticks: u32,
+    position: usize,
+    sender: Sender<StepCommand>,
+    receiver: Receiver<StepCommand>,
+}
+
+impl StepSequencer {
+    pub fn new(note: Midi, steps: Vec<Option<u8>>, step_ticks: u32) -> Self {
+        let (sender, receiver) = unbounded();
+        StepSequencer {
+            note,
+            steps,
+            step_ticks,
+            position: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    /// A sequencer with `len` steps that are all off.
+    pub fn empty(note: Midi, len: usize, step_ticks: u32) -> Self {
+        Self::new(note, vec![None; len], step_ticks)
+    }
+
+    /// A handle for sending edits to this sequencer from another thread.
+    pub fn controller(&self) -> Sender<StepCommand> {
+        self.sender.clone()
+    }
+
+    pub fn steps(&self) -> &[Option<u8>] {
+        &self.steps
+    }
+
+    /// Applies a command right away. Indices past the end of the pattern are ignored.
+    pub fn apply(&mut self, command: StepCommand) {
+        match command {
+            StepCommand::ToggleStep(i) => {
+                let velocity = self.note.velocity;
+                if let Some(step) = self.steps.get_mut(i) {
+                    *step = match step {
+                        Some(_) => None,
+                        None => Some(velocity),
+                    };
+                }
+            }
+            StepCommand::SetVelocity(i, velocity) => {
+                if let Some(step) = self.steps.get_mut(i) {
+                    *step = Some(velocity);
+                }
+            }
+            StepCommand::Clear => {
+                self.steps.iter_mut().for_each(|step| *step = None);
+            }
+        }
+    }
+
+    fn render_step(&self, step: Option<u8>) -> Vec<Midi> {
+        let note = match step {
+            Some(velocity) => self.note.set_velocity(velocity),
+            None => Midi::rest(),
+        };
+        vec![note.set_duration(self.step_ticks)]
+    }
+}
+
+impl Midibox for StepSequencer {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        while let Ok(command) = self.receiver.try_recv() {
+            self.apply(command);
+        }
+        let step = *self.steps.get(self.position)?;
+        self.position = (self.position + 1) % self.steps.len();
+        Some(self.render_step(step))
+    }
+
+    fn cycle(&self) -> Option<Vec<Vec<Midi>>> {
+        self.peek(self.steps.len())
+    }
+
+    /// Looks ahead at the pattern as it stands; edits that haven't been applied yet aren't
+    /// reflected.
+    fn peek(&self, count: usize) -> Option<Vec<Vec<Midi>>> {
+        Some(
+            self.steps.iter()
+                .cycle()
+                .skip(self.position)
+                .take(if self.steps.is_empty() { 0 } else { count })
+                .map(|step| self.render_step(*step))
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::Midibox;
+    use crate::midi::Midi;
+    use crate::step_sequencer::{StepCommand, StepSequencer};
+    use crate::tone::Tone;
+
+    #[test]
+    fn commands_apply_on_the_next_poll() {
+        let mut sequencer = StepSequencer::empty(Tone::C.oct(2), 2, 1);
+        let controller = sequencer.controller();
+        assert_eq!(sequencer.next(), Some(vec![Midi::rest()]));
+
+        thread::spawn(move || {
+            controller.send(StepCommand::ToggleStep(1)).unwrap();
+            controller.send(StepCommand::SetVelocity(0, 40)).unwrap();
+        }).join().unwrap();
+        assert_eq!(sequencer.steps(), &[None, None]);
+
+        assert_eq!(sequencer.next(), Some(vec![Tone::C.oct(2)]));
+        assert_eq!(sequencer.steps(), &[Some(40), Some(100)]);
+        assert_eq!(sequencer.next(), Some(vec![Tone::C.oct(2).set_velocity(40)]));
+
+        sequencer.controller().send(StepCommand::ToggleStep(1)).unwrap();
+        assert_eq!(sequencer.next(), Some(vec![Midi::rest()]));
+
+        sequencer.controller().send(StepCommand::Clear).unwrap();
+        assert_eq!(sequencer.next(), Some(vec![Midi::rest()]));
+        assert_eq!(sequencer.steps(), &[None, None]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// The order a `Strum` starts the notes of a chord in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrumDirection {
+    /// The lowest note first, like a downstroke on a guitar.
+    Up,
+    /// The highest note first.
+    Down,
+}
+
+/// Staggers the notes of each chord the wrapped midibox emits, starting each one `delay_ticks`
+/// after the one before so the chord is strummed rather than struck.
+///
+/// Every note still ends when it would have, and the chord's step keeps its length, so the
+/// rhythm is unchanged. To let the notes overlap, the delay is shortened where needed so that
+/// the last note starts before the shortest one ends. The chord is emitted as a series of
+/// shorter steps, each adding a note and tying over the ones already sounding (see
+/// `Midi::tie`), so the player keeps them sustained. Rests, control events and single notes
+/// are passed through.
+pub struct Strum {
+    delay_ticks: u32,
+    direction: StrumDirection,
+    /// The remaining steps of the chord being strummed.
+    pending: VecDeque<Vec<Midi>>,
+    midibox: Box<dyn Midibox>,
+}
+
+impl Strum {
+    pub fn wrap(midibox: Box<dyn Midibox>, delay_ticks: u32, direction: StrumDirection) -> Box<dyn Midibox> {
+        Box::new(Strum {
+            delay_ticks,
+            direction,
+            pending: VecDeque::new(),
+            midibox,
+        })
+    }
+
+    /// Splits `chord` into one step per note onset, or `None` if it can't be strummed.
+    fn strum(&self, chord: &[Midi]) -> Option<VecDeque<Vec<Midi>>> {
+        let step_ticks = chord.iter().map(|n| n.duration).max().unwrap_or(0);
+        let mut notes: Vec<Midi> = chord.iter()
+            .filter(|note| !note.is_rest() && note.duration > 0)
+            .copied()
+            .collect();
+        if notes.len() < 2 {
+            return None;
+        }
+        let shortest = notes.iter().map(|n| n.duration).min().unwrap_or(0);
+        let delay = self.delay_ticks.min((shortest - 1) / (notes.len() as u32 - 1));
+        if delay == 0 {
+            return None;
+        }
+        notes.sort_by_key(|note| note.u8_maybe());
+        if self.direction == StrumDirection::Down {
+            notes.reverse();
+        }
+
+        let mut steps = VecDeque::new();
+        for started in 1..=notes.len() {
+            let at = (started as u32 - 1) * delay;
+            let length = if started == notes.len() { step_ticks - at } else { delay };
+            let mut step: Vec<Midi> = notes[..started].iter()
+                .filter(|note| note.duration > at)
+                .map(|note| {
+                    let remaining = note.duration - at;
+                    let tie = if remaining > length { true } else { note.tie };
+                    note.set_duration(remaining.min(length)).set_tie(tie)
+                })
+                .collect();
+            if step.iter().all(|note| note.duration < length) {
+                step.push(Midi::rest().set_duration(length));
+            }
+            steps.push_back(step);
+        }
+        // control events go out with the first note
+        steps[0].splice(0..0, chord.iter().filter(|note| note.control.is_some()).copied());
+        Some(steps)
+    }
+}
+
+impl Midibox for Strum {
+    fn next(&mut self) -> Option<Vec<Midi>> {
+        if let Some(step) = self.pending.pop_front() {
+            return Some(step);
+        }
+        let chord = self.midibox.next()?;
+        match self.strum(&chord) {
+            None => Some(chord),
+            Some(mut steps) => {
+                let first = steps.pop_front();
+                self.pending = steps;
+                first
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.midibox.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::collect_emissions;
+    use crate::meter::Bpm;
+    use crate::player::{Player, Trace, TraceEvent};
+    use crate::sequences::Seq;
+    use crate::strum::{Strum, StrumDirection};
+    use crate::tone::Tone;
+
+    fn chord() -> Seq {
+        Seq::chords(vec![
+            Chord::new(vec![Tone::E.oct(4) * 4, Tone::C.oct(4) * 4, Tone::G.oct(4) * 4]),
+            Chord::note(Tone::A.oct(4) * 2),
+        ])
+    }
+
+    #[test]
+    fn strums_chords_into_tied_steps() {
+        let c = Tone::C.oct(4);
+        let e = Tone::E.oct(4);
+        let g = Tone::G.oct(4);
+        assert_eq!(collect_emissions(Strum::wrap(chord().midibox(), 1, StrumDirection::Up).as_mut(), 4), vec![
+            vec![(c * 1).set_tie(true)],
+            vec![(c * 1).set_tie(true), (e * 1).set_tie(true)],
+            vec![c * 2, e * 2, g * 2],
+            vec![Tone::A.oct(4) * 2],
+        ]);
+        // the delay is shortened so the last note starts before the chord ends
+        assert_eq!(collect_emissions(Strum::wrap(chord().midibox(), 3, StrumDirection::Down).as_mut(), 3), vec![
+            vec![(g * 1).set_tie(true)],
+            vec![(g * 1).set_tie(true), (e * 1).set_tie(true)],
+            vec![g * 2, e * 2, c * 2],
+        ]);
+    }
+
+    #[test]
+    fn strummed_notes_sound_together() {
+        let trace = Trace::new();
+        let mut player = Player::new().with_trace(trace.clone());
+        let mut channels = vec![Strum::wrap(chord().midibox(), 1, StrumDirection::Up)];
+        while player.time() < 4 {
+            player.poll_channels(&mut channels);
+            player.release_held_ties();
+            player.do_tick(&Bpm::new(600_000));
+            player.clear_elapsed_notes();
+        }
+        let mut notes: Vec<(bool, u64, Option<u8>)> = trace.events().into_iter().filter_map(|event| match event {
+            TraceEvent::NoteOn { tick, note, .. } => Some((true, tick, note.u8_maybe())),
+            TraceEvent::NoteOff { tick, note, .. } => Some((false, tick, note.u8_maybe())),
+            _ => None,
+        }).collect();
+        // notes released on the same tick come in no particular order
+        notes[3..].sort();
+        assert_eq!(notes, vec![
+            (true, 0, Some(60)),
+            (true, 1, Some(64)),
+            (true, 2, Some(67)),
+            (false, 4, Some(60)),
+            (false, 4, Some(64)),
+            (false, 4, Some(67)),
+        ]);
+    }
+}
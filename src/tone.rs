@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::ops::Mul;
 use crate::chord::{Chord, ToChord};
 use crate::midi::{Midi, ToMidi};
@@ -39,23 +40,25 @@ impl Tone {
         }
     }
 
+    /// The MIDI pitch of this tone in octave `oct`, or `None` for a rest or a pitch that doesn't
+    /// fit in a byte.
     pub fn u8(&self, oct: u8) -> Option<u8> {
-        let base = (oct + 1) * 12;
-        match self {
-            Tone::C => { Some(base) }
-            Tone::Db => { Some(base + 1) }
-            Tone::D => { Some(base + 2) }
-            Tone::Eb => { Some(base + 3) }
-            Tone::E => { Some(base + 4) }
-            Tone::F => { Some(base + 5) }
-            Tone::Gb => { Some(base + 6) }
-            Tone::G => { Some(base + 7) }
-            Tone::Ab => { Some(base + 8) }
-            Tone::A => { Some(base + 9) }
-            Tone::Bb => { Some(base + 10) }
-            Tone::B => { Some(base + 11) }
-            Tone::Rest => { None }
-        }
+        let offset = match self {
+            Tone::C => 0,
+            Tone::Db => 1,
+            Tone::D => 2,
+            Tone::Eb => 3,
+            Tone::E => 4,
+            Tone::F => 5,
+            Tone::Gb => 6,
+            Tone::G => 7,
+            Tone::Ab => 8,
+            Tone::A => 9,
+            Tone::Bb => 10,
+            Tone::B => 11,
+            Tone::Rest => return None,
+        };
+        oct.checked_add(1)?.checked_mul(12)?.checked_add(offset)
     }
 
     pub fn get(&self) -> Midi {
@@ -67,6 +70,27 @@ impl Tone {
     }
 }
 
+impl Display for Tone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Tone::Rest => "Rest",
+            Tone::C => "C",
+            Tone::Db => "Db",
+            Tone::D => "D",
+            Tone::Eb => "Eb",
+            Tone::E => "E",
+            Tone::F => "F",
+            Tone::Gb => "Gb",
+            Tone::G => "G",
+            Tone::Ab => "Ab",
+            Tone::A => "A",
+            Tone::Bb => "Bb",
+            Tone::B => "B",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl ToChord for Tone {
     fn chord(&self) -> Chord {
         Chord::note(self.midi())
@@ -0,0 +1,115 @@
+use std::fmt::{Display, Formatter};
+use crate::Midibox;
+use crate::midi::Midi;
+
+/// Notes lasting longer than this many ticks are reported as suspicious.
+pub const LONG_NOTE_TICKS: u32 = 1024;
+
+/// A potential problem found in a channel before playback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// The note's pitch falls outside the MIDI range 0-127.
+    OutOfRange { channel_id: usize, step: usize, note: Midi },
+    /// The note has a duration of zero and will never sound.
+    ZeroDuration { channel_id: usize, step: usize, note: Midi },
+    /// The note lasts longer than `LONG_NOTE_TICKS`.
+    LongDuration { channel_id: usize, step: usize, note: Midi },
+    /// The channel doesn't repeat, so it couldn't be checked.
+    NotPeriodic { channel_id: usize },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::OutOfRange { channel_id, step, note } => write!(
+                f, "channel {} step {}: {:?} in octave {} is outside the MIDI range 0-127",
+                channel_id, step, note.tone, note.oct
+            ),
+            Warning::ZeroDuration { channel_id, step, note } => write!(
+                f, "channel {} step {}: {:?} has a duration of 0 ticks and will never sound",
+                channel_id, step, note.tone
+            ),
+            Warning::LongDuration { channel_id, step, note } => write!(
+                f, "channel {} step {}: {:?} lasts {} ticks",
+                channel_id, step, note.tone, note.duration
+            ),
+            Warning::NotPeriodic { channel_id } => write!(
+                f, "channel {} does not repeat and was not checked", channel_id
+            ),
+        }
+    }
+}
+
+/// Checks one cycle of every periodic channel for notes that would misbehave during playback.
+///
+/// Channels that don't report a cycle (see `Midibox::cycle`) are skipped with a
+/// `Warning::NotPeriodic`.
+pub fn validate_channels(channels: &[Box<dyn Midibox>]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (channel_id, channel) in channels.iter().enumerate() {
+        match channel.cycle() {
+            None => warnings.push(Warning::NotPeriodic { channel_id }),
+            Some(steps) => {
+                for (step, notes) in steps.into_iter().enumerate() {
+                    for note in notes {
+                        warnings.extend(validate_note(channel_id, step, note));
+                    }
+                }
+            }
+        }
+    }
+    warnings
+}
+
+fn validate_note(channel_id: usize, step: usize, note: Midi) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if pitch(&note).is_some_and(|p| p > 127) {
+        warnings.push(Warning::OutOfRange { channel_id, step, note });
+    }
+    if note.duration == 0 && note.control.is_none() {
+        warnings.push(Warning::ZeroDuration { channel_id, step, note });
+    } else if note.duration > LONG_NOTE_TICKS {
+        warnings.push(Warning::LongDuration { channel_id, step, note });
+    }
+    warnings
+}
+
+/// The note number without the `u8` arithmetic in `Tone::u8`, which overflows for high octaves.
+fn pitch(note: &Midi) -> Option<u32> {
+    note.tone.u8(0).map(|v| (v as u32 - 12) + (note.oct as u32 + 1) * 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Midibox;
+    use crate::rand::RandomVelocity;
+    use crate::sequences::Seq;
+    use crate::tone::Tone;
+    use crate::validate::{validate_channels, Warning};
+
+    #[test]
+    fn reports_problem_notes() {
+        let channels: Vec<Box<dyn Midibox>> = vec![
+            Seq::new(vec![Tone::C.oct(4), Tone::A.oct(10), Tone::E.oct(4).set_duration(0)]).midibox(),
+            Seq::new(vec![Tone::C.oct(4) * 2000]).midibox(),
+            RandomVelocity::wrap(Seq::new(vec![Tone::C.oct(4)]).midibox()),
+            Seq::new(vec![Tone::G.oct(9), Tone::Rest * 4]).midibox(),
+        ];
+
+        assert_eq!(validate_channels(&channels), vec![
+            Warning::OutOfRange { channel_id: 0, step: 1, note: Tone::A.oct(10) },
+            Warning::ZeroDuration { channel_id: 0, step: 2, note: Tone::E.oct(4).set_duration(0) },
+            Warning::LongDuration { channel_id: 1, step: 0, note: Tone::C.oct(4) * 2000 },
+            Warning::NotPeriodic { channel_id: 2 },
+        ]);
+    }
+
+    #[test]
+    fn does_not_advance_channels() {
+        let mut channels: Vec<Box<dyn Midibox>> = vec![
+            Seq::new(vec![Tone::C.oct(4), Tone::D.oct(4)]).midibox(),
+        ];
+        validate_channels(&channels);
+        assert_eq!(channels[0].next(), Some(vec![Tone::C.oct(4)]));
+    }
+}